@@ -0,0 +1,54 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use std::hint::black_box;
+
+use rust_decode::{disassemble_next_instruction, InstructionBuffer};
+
+/// Mix of register, memory, immediate, group and jump encodings
+const PROGRAM: &[u8] = &[
+    0x89, 0xd9, // mov cx, bx
+    0x8b, 0x56, 0x00, // mov dx, [bp]
+    0x89, 0x4e, 0x02, // mov [bp+2], cx
+    0xc6, 0x46, 0x03, 0xff, // mov byte [bp+3], 255
+    0xb9, 0x00, 0x01, // mov cx, 256
+    0xa1, 0xfb, 0x09, // mov ax, [2555]
+    0x03, 0x18, // add bx, [bx+si]
+    0x83, 0xc5, 0x04, // add bp, 4
+    0x81, 0xee, 0xe8, 0x03, // sub si, 1000
+    0x83, 0xf9, 0x40, // cmp cx, 64
+    0x3c, 0x14, // cmp al, 20
+    0xf7, 0x5e, 0x04, // neg word [bp+4]
+    0xfe, 0xc0, // inc al
+    0xd1, 0xe0, // shl ax, 1
+    0x50, // push ax
+    0x26, 0x8b, 0x07, // mov ax, es:[bx]
+    0xf3, 0xa5, // rep movsw
+    0x75, 0xe0, // jnz $-30
+    0xe2, 0xfe, // loop $
+];
+
+fn decode(c: &mut Criterion) {
+    let program = PROGRAM.repeat(1024);
+
+    let mut buffer = InstructionBuffer::from_bytes(&program);
+    let mut count = 0;
+    while !buffer.is_at_the_end() {
+        disassemble_next_instruction(&mut buffer).unwrap();
+        count += 1;
+    }
+
+    let mut group = c.benchmark_group("decode");
+    group.throughput(Throughput::Elements(count));
+    group.bench_function("instructions", |b| {
+        b.iter(|| {
+            let mut buffer = InstructionBuffer::from_bytes(black_box(&program));
+
+            while !buffer.is_at_the_end() {
+                black_box(disassemble_next_instruction(&mut buffer).unwrap());
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, decode);
+criterion_main!(benches);
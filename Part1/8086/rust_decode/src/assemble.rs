@@ -0,0 +1,619 @@
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+
+use strum::IntoEnumIterator;
+
+use crate::assembled_instruction::{definitions_of, Operation, DISP_LO, MOD};
+use crate::cpu::cpu::{Access, EffectiveAddress, Reg, RegPart};
+use crate::encode::{encode, register_code, Argument, EncodingError, Statement};
+use crate::instruction::operand::Size;
+use crate::instruction::prefix::{Prefix, Prefixes, Repeat};
+
+/// Labels may move when jumps between them change their length
+const MAX_PASSES: usize = 32;
+
+#[derive(Debug)]
+pub enum AssemblyErrorKind {
+    SyntaxError(String),
+    UnknownMnemonicError(String),
+    UndefinedLabelError(String),
+    DuplicateLabelError(String),
+    UnstableLabelsError,
+    EncodingError(EncodingError),
+}
+
+impl fmt::Display for AssemblyErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::SyntaxError(text) => write!(f, "cannot parse '{}'", text),
+            Self::UnknownMnemonicError(mnemonic) => write!(f, "unknown mnemonic '{}'", mnemonic),
+            Self::UndefinedLabelError(label) => write!(f, "undefined label '{}'", label),
+            Self::DuplicateLabelError(label) => write!(f, "label '{}' defined twice", label),
+            Self::UnstableLabelsError => {
+                write!(f, "labels did not settle in {} passes", MAX_PASSES)
+            }
+            Self::EncodingError(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<EncodingError> for AssemblyErrorKind {
+    fn from(e: EncodingError) -> Self {
+        Self::EncodingError(e)
+    }
+}
+
+/// Error with the line of the source it was found at, lines are numbered from one
+#[derive(Debug)]
+pub struct AssemblyError {
+    pub line: usize,
+    pub kind: AssemblyErrorKind,
+}
+
+impl fmt::Display for AssemblyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.kind)
+    }
+}
+
+impl error::Error for AssemblyError {}
+
+type ParseResult<T> = Result<T, AssemblyErrorKind>;
+
+/// Operand as written, labels and `$` are resolved once the position is known
+#[derive(Debug, Clone)]
+enum Operand {
+    Argument(Argument),
+    Number(i64),
+    Label(String),
+    /// Offset from the start of the instruction written with `$`
+    Relative(i64),
+}
+
+#[derive(Debug)]
+struct Parsed {
+    prefixes: Prefixes,
+    operation: Operation,
+    operands: Vec<Operand>,
+    size: Option<Size>,
+}
+
+#[derive(Debug)]
+enum Line {
+    Empty,
+    Data(Vec<u8>),
+    Instruction(Parsed),
+}
+
+const ALIASES: [(&str, Operation); 19] = [
+    ("jz", Operation::JE),
+    ("jne", Operation::JNZ),
+    ("jc", Operation::JB),
+    ("jnae", Operation::JB),
+    ("jae", Operation::JNB),
+    ("jnc", Operation::JNB),
+    ("jge", Operation::JNL),
+    ("jnle", Operation::JG),
+    ("jng", Operation::JLE),
+    ("jnge", Operation::JL),
+    ("jna", Operation::JBE),
+    ("jnbe", Operation::JA),
+    ("jpe", Operation::JP),
+    ("jpo", Operation::JNP),
+    ("loope", Operation::LOOPZ),
+    ("loopne", Operation::LOOPNZ),
+    ("sal", Operation::SHL),
+    ("xlat", Operation::XLAT),
+    ("retn", Operation::RET),
+];
+
+/// Operation of the mnemonic, string instructions carry their size in the suffix
+fn operation(mnemonic: &str) -> Option<(Operation, Option<Size>)> {
+    if let Some((_, operation)) = ALIASES.iter().find(|(alias, _)| *alias == mnemonic) {
+        return Some((*operation, None));
+    }
+
+    if let Some(operation) = Operation::iter()
+        .find(|operation| !operation.is_string() && operation.to_string() == mnemonic)
+    {
+        return Some((operation, None));
+    }
+
+    let (base, size) = match mnemonic.split_at(mnemonic.len().checked_sub(1)?) {
+        (base, "b") => (base, Size::BYTE),
+        (base, "w") => (base, Size::WORD),
+        _ => return None,
+    };
+
+    Operation::iter()
+        .find(|operation| operation.is_string() && operation.to_string() == base)
+        .map(|operation| (operation, Some(size)))
+}
+
+/// Jumps and calls whose operand is a displacement from the next instruction
+fn is_relative(operation: Operation) -> bool {
+    definitions_of(operation)
+        .any(|definition| !definition.includes_bits(MOD) && definition.includes_bits(DISP_LO))
+}
+
+pub(crate) fn register(name: &str) -> Option<(Reg, RegPart)> {
+    Some(match name {
+        "ax" => (Reg::A, RegPart::Whole),
+        "cx" => (Reg::C, RegPart::Whole),
+        "dx" => (Reg::D, RegPart::Whole),
+        "bx" => (Reg::B, RegPart::Whole),
+        "sp" => (Reg::Sp, RegPart::Whole),
+        "bp" => (Reg::Bp, RegPart::Whole),
+        "si" => (Reg::Si, RegPart::Whole),
+        "di" => (Reg::Di, RegPart::Whole),
+        "al" => (Reg::A, RegPart::Low),
+        "cl" => (Reg::C, RegPart::Low),
+        "dl" => (Reg::D, RegPart::Low),
+        "bl" => (Reg::B, RegPart::Low),
+        "ah" => (Reg::A, RegPart::High),
+        "ch" => (Reg::C, RegPart::High),
+        "dh" => (Reg::D, RegPart::High),
+        "bh" => (Reg::B, RegPart::High),
+        _ => return segment(name).map(|reg| (reg, RegPart::Whole)),
+    })
+}
+
+fn segment(name: &str) -> Option<Reg> {
+    match name {
+        "es" => Some(Reg::Es),
+        "cs" => Some(Reg::Cs),
+        "ss" => Some(Reg::Ss),
+        "ds" => Some(Reg::Ds),
+        _ => None,
+    }
+}
+
+fn syntax_error(text: &str) -> AssemblyErrorKind {
+    AssemblyErrorKind::SyntaxError(text.to_string())
+}
+
+/// Decimal, `0x` hexadecimal, `h` suffixed hexadecimal, `0b` binary, character or their product
+pub(crate) fn number(text: &str) -> Option<i64> {
+    let text = text.trim();
+
+    if let Some((a, b)) = text.split_once('*') {
+        return Some(number(a)? * number(b)?);
+    }
+
+    if let Some(rest) = text.strip_prefix('-') {
+        return number(rest).map(|value| -value);
+    }
+
+    let bytes = text.as_bytes();
+    if bytes.len() == 3 && matches!(bytes[0], b'\'' | b'"') && bytes[2] == bytes[0] {
+        return Some(bytes[1] as i64);
+    }
+
+    if !text.starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+
+    let lower = text.to_ascii_lowercase();
+
+    if let Some(hex) = lower.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = lower.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()
+    } else if let Some(hex) = lower.strip_suffix('h') {
+        i64::from_str_radix(hex, 16).ok()
+    } else {
+        lower.parse().ok()
+    }
+}
+
+fn is_identifier(text: &str) -> bool {
+    text.starts_with(|c: char| c.is_ascii_alphabetic() || matches!(c, '_' | '.' | '?'))
+        && text
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '?' | '$' | '@'))
+}
+
+/// Sum of base, index and displacement written inside the brackets
+fn memory(text: &str, segment_override: Option<Reg>) -> ParseResult<Argument> {
+    let (segment_override, expression) = match text.split_once(':') {
+        Some((name, rest)) => (Some(segment(name.trim()).ok_or(syntax_error(text))?), rest),
+        None => (segment_override, text),
+    };
+
+    let mut registers = vec![];
+    let mut displacement: i64 = 0;
+    let mut term = String::new();
+    let mut sign = 1;
+
+    for c in expression.chars().chain(['+']) {
+        if c != '+' && c != '-' {
+            term.push(c);
+            continue;
+        }
+
+        let name = term.trim();
+
+        if let Some((reg @ (Reg::B | Reg::Bp | Reg::Si | Reg::Di), RegPart::Whole)) = register(name)
+        {
+            if sign < 0 {
+                return Err(syntax_error(text));
+            }
+
+            registers.push(reg);
+        } else if !name.is_empty() {
+            displacement += sign * number(name).ok_or(syntax_error(text))?;
+        }
+
+        sign = if c == '-' { -1 } else { 1 };
+        term.clear();
+    }
+
+    if !(-0x8000..=0xFFFF).contains(&displacement) {
+        return Err(syntax_error(text));
+    }
+
+    let d = displacement as i16;
+
+    let access = match registers.as_slice() {
+        [] => Access::Direct(displacement as u16),
+        [Reg::B, Reg::Si] | [Reg::Si, Reg::B] => Access::Address(EffectiveAddress::BxSi(d)),
+        [Reg::B, Reg::Di] | [Reg::Di, Reg::B] => Access::Address(EffectiveAddress::BxDi(d)),
+        [Reg::Bp, Reg::Si] | [Reg::Si, Reg::Bp] => Access::Address(EffectiveAddress::BpSi(d)),
+        [Reg::Bp, Reg::Di] | [Reg::Di, Reg::Bp] => Access::Address(EffectiveAddress::BpDi(d)),
+        [Reg::Si] => Access::Address(EffectiveAddress::Si(d)),
+        [Reg::Di] => Access::Address(EffectiveAddress::Di(d)),
+        [Reg::Bp] => Access::Address(EffectiveAddress::Bp(d)),
+        [Reg::B] => Access::Address(EffectiveAddress::Bx(d)),
+        _ => return Err(syntax_error(text)),
+    };
+
+    Ok(Argument::Memory(access, segment_override))
+}
+
+/// Size and distance specifiers precede the operand they apply to
+fn operand(text: &str, size: &mut Option<Size>, far: &mut bool) -> ParseResult<Operand> {
+    let text = text.trim();
+    let lower = text.to_ascii_lowercase();
+    let mut rest = lower.as_str();
+
+    loop {
+        let (word, after) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+
+        match word {
+            "byte" => *size = Some(Size::BYTE),
+            "word" => *size = Some(Size::WORD),
+            "far" => *far = true,
+            // Distance of the jump is the size of its displacement
+            "short" => *size = Some(Size::BYTE),
+            "near" => *size = Some(Size::WORD),
+            _ => break,
+        }
+
+        rest = after.trim_start();
+    }
+
+    // Labels keep their case
+    let original = &text[text.len() - rest.len()..];
+
+    if let Some(open) = rest.find('[') {
+        let inner = rest[open + 1..]
+            .strip_suffix(']')
+            .ok_or(syntax_error(text))?;
+
+        let prefix = rest[..open].trim();
+        let segment_override = match prefix.strip_suffix(':') {
+            Some(name) => Some(segment(name.trim()).ok_or(syntax_error(text))?),
+            None if prefix.is_empty() => None,
+            None => return Err(syntax_error(text)),
+        };
+
+        return memory(inner, segment_override).map(Operand::Argument);
+    }
+
+    if let Some((reg, part)) = register(rest) {
+        return Ok(Operand::Argument(Argument::Register(reg, part)));
+    }
+
+    if let Some(relative) = rest.strip_prefix('$') {
+        let relative = relative.replace(' ', "");
+
+        return match relative.as_str() {
+            "" => Ok(Operand::Relative(0)),
+            _ => number(relative.strip_prefix('+').unwrap_or(&relative))
+                .map(Operand::Relative)
+                .ok_or(syntax_error(text)),
+        };
+    }
+
+    if let Some((segment, offset)) = rest.split_once(':') {
+        return match (number(segment), number(offset)) {
+            (Some(segment), Some(offset)) => Ok(Operand::Argument(Argument::FarPointer(
+                segment as u16,
+                offset as u16,
+            ))),
+            _ => Err(syntax_error(text)),
+        };
+    }
+
+    if let Some(value) = number(rest) {
+        return Ok(Operand::Number(value));
+    }
+
+    if is_identifier(original) {
+        return Ok(Operand::Label(original.to_string()));
+    }
+
+    Err(syntax_error(text))
+}
+
+fn data(text: &str, size: Size) -> ParseResult<Vec<u8>> {
+    let mut bytes = vec![];
+
+    for item in text.split(',') {
+        let item = item.trim();
+
+        // Strings are written byte after byte
+        if item.len() > 3 && (item.starts_with('\'') || item.starts_with('"')) {
+            bytes.extend_from_slice(&item.as_bytes()[1..item.len() - 1]);
+            continue;
+        }
+
+        let value = number(item).ok_or(syntax_error(item))?;
+
+        match size {
+            Size::BYTE => bytes.push(value as u8),
+            Size::WORD => bytes.extend_from_slice(&(value as u16).to_le_bytes()),
+        }
+    }
+
+    Ok(bytes)
+}
+
+fn instruction(text: &str) -> ParseResult<Line> {
+    let mut prefixes = Prefixes::default();
+    let mut segment_prefix = None;
+    let mut rest = text;
+
+    let (mnemonic, operands) = loop {
+        let (word, after) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+        let word = word.to_ascii_lowercase();
+        rest = after.trim_start();
+
+        match word.as_str() {
+            "lock" => prefixes.add(Prefix::Lock),
+            "rep" | "repe" | "repz" => prefixes.add(Prefix::Repeat(Repeat::Rep)),
+            "repne" | "repnz" => prefixes.add(Prefix::Repeat(Repeat::Repne)),
+            _ => match segment(&word) {
+                Some(reg) if !rest.is_empty() => {
+                    segment_prefix = Some(reg);
+                    prefixes.add(Prefix::Segment(
+                        register_code(reg, RegPart::Whole).ok_or(syntax_error(text))?,
+                    ));
+                }
+                _ => break (word, rest),
+            },
+        }
+    };
+
+    match mnemonic.as_str() {
+        "bits" if operands.trim() == "16" => return Ok(Line::Empty),
+        "db" => return data(operands, Size::BYTE).map(Line::Data),
+        "dw" => return data(operands, Size::WORD).map(Line::Data),
+        _ => (),
+    }
+
+    let (mut operation, mut size) =
+        operation(&mnemonic).ok_or(AssemblyErrorKind::UnknownMnemonicError(mnemonic.clone()))?;
+
+    let mut far = false;
+    let mut parsed = vec![];
+
+    if !operands.trim().is_empty() {
+        for text in operands.split(',') {
+            parsed.push(operand(text, &mut size, &mut far)?);
+        }
+    }
+
+    let far_pointer = parsed
+        .iter()
+        .any(|operand| matches!(operand, Operand::Argument(Argument::FarPointer(..))));
+
+    if far || far_pointer {
+        operation = match operation {
+            Operation::CALL => Operation::CALLF,
+            Operation::JMP => Operation::JMPF,
+            _ => return Err(syntax_error(text)),
+        };
+    }
+
+    // AAM and AAD written without operand use base 10
+    if parsed.is_empty() && matches!(operation, Operation::AAM | Operation::AAD) {
+        parsed.push(Operand::Number(10));
+    }
+
+    // Segment prefix written before the instruction applies to its memory operand, it keeps
+    // its place among the prefixes
+    if let Some(reg) = segment_prefix {
+        let memory = parsed.iter_mut().find_map(|operand| match operand {
+            Operand::Argument(Argument::Memory(_, segment @ None)) => Some(segment),
+            _ => None,
+        });
+
+        if let Some(segment) = memory {
+            *segment = Some(reg);
+            prefixes.segment = None;
+        }
+    }
+
+    Ok(Line::Instruction(Parsed {
+        prefixes,
+        operation,
+        operands: parsed,
+        size,
+    }))
+}
+
+/// Label defined on the line and the rest of the line after it
+fn line(text: &str) -> ParseResult<(Option<String>, Line)> {
+    let text = text.split(';').next().unwrap_or("").trim();
+
+    let (label, rest) = match text.split_once(':') {
+        Some((label, rest)) if is_identifier(label.trim()) && segment(label.trim()).is_none() => {
+            (Some(label.trim().to_string()), rest.trim())
+        }
+        _ => (None, text),
+    };
+
+    if rest.is_empty() {
+        return Ok((label, Line::Empty));
+    }
+
+    Ok((label, instruction(rest)?))
+}
+
+impl Parsed {
+    /// Labels not placed yet point at the instruction itself
+    fn statement(&self, offset: usize, labels: &HashMap<String, usize>) -> ParseResult<Statement> {
+        let relative = is_relative(self.operation);
+        let mut arguments = vec![];
+
+        for operand in &self.operands {
+            let position = match operand {
+                Operand::Argument(argument) => {
+                    arguments.push(*argument);
+                    continue;
+                }
+                Operand::Number(value) if !relative => {
+                    arguments.push(Argument::Immediate(*value as i32));
+                    continue;
+                }
+                Operand::Number(value) => *value,
+                Operand::Relative(value) => offset as i64 + value,
+                Operand::Label(label) => labels.get(label).copied().unwrap_or(offset) as i64,
+            };
+
+            // Only the displacement of the jump has to fit, the target may be before the program
+            arguments.push(match relative {
+                true => Argument::Target(position),
+                false => Argument::Immediate(position as i32),
+            });
+        }
+
+        Ok(Statement {
+            prefixes: self.prefixes,
+            operation: self.operation,
+            arguments,
+            size: self.size,
+        })
+    }
+
+    fn labels(&self) -> impl Iterator<Item = &String> {
+        self.operands.iter().filter_map(|operand| match operand {
+            Operand::Label(label) => Some(label),
+            _ => None,
+        })
+    }
+}
+
+/// Assembles the subset of NASM the disassembler prints, jumps take their shortest form
+pub fn assemble(source: &str) -> Result<Vec<u8>, AssemblyError> {
+    let mut lines = vec![];
+    let mut defined: HashMap<String, usize> = HashMap::new();
+
+    for (n, text) in source.lines().enumerate() {
+        let (label, parsed) = line(text).map_err(|kind| AssemblyError { line: n + 1, kind })?;
+
+        if let Some(label) = &label {
+            if defined.insert(label.clone(), n + 1).is_some() {
+                return Err(AssemblyError {
+                    line: n + 1,
+                    kind: AssemblyErrorKind::DuplicateLabelError(label.clone()),
+                });
+            }
+        }
+
+        lines.push((n + 1, label, parsed));
+    }
+
+    for (n, _, parsed) in &lines {
+        if let Line::Instruction(parsed) = parsed {
+            if let Some(label) = parsed.labels().find(|label| !defined.contains_key(*label)) {
+                return Err(AssemblyError {
+                    line: *n,
+                    kind: AssemblyErrorKind::UndefinedLabelError(label.clone()),
+                });
+            }
+        }
+    }
+
+    let mut labels: HashMap<String, usize> = HashMap::new();
+
+    for _ in 0..MAX_PASSES {
+        let mut output: Vec<u8> = vec![];
+        let mut found = HashMap::new();
+
+        for (n, label, parsed) in &lines {
+            if let Some(label) = label {
+                found.insert(label.clone(), output.len());
+            }
+
+            let bytes = match parsed {
+                Line::Empty => continue,
+                Line::Data(bytes) => bytes.clone(),
+                Line::Instruction(parsed) => parsed
+                    .statement(output.len(), &labels)
+                    .and_then(|statement| Ok(encode(&statement, output.len())?))
+                    .map_err(|kind| AssemblyError { line: *n, kind })?,
+            };
+
+            output.extend(bytes);
+        }
+
+        if found == labels {
+            return Ok(output);
+        }
+
+        labels = found;
+    }
+
+    Err(AssemblyError {
+        line: lines.len(),
+        kind: AssemblyErrorKind::UnstableLabelsError,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_number() {
+        assert_eq!(number("37"), Some(37));
+        assert_eq!(number("-37"), Some(-37));
+        assert_eq!(number("0x1F"), Some(31));
+        assert_eq!(number("0FFh"), Some(255));
+        assert_eq!(number("0b101"), Some(5));
+        assert_eq!(number("'A'"), Some(65));
+        assert_eq!(number("64*4"), Some(256));
+        assert_eq!(number("label"), None);
+    }
+
+    #[test]
+    fn test_memory() {
+        assert_eq!(
+            memory("bx + si+4999", None).unwrap(),
+            Argument::Memory(Access::Address(EffectiveAddress::BxSi(4999)), None)
+        );
+        assert_eq!(
+            memory("es:bp-37", None).unwrap(),
+            Argument::Memory(Access::Address(EffectiveAddress::Bp(-37)), Some(Reg::Es))
+        );
+        assert_eq!(
+            memory("2555", Some(Reg::Ds)).unwrap(),
+            Argument::Memory(Access::Direct(2555), Some(Reg::Ds))
+        );
+        assert!(memory("bx + bp", None).is_err());
+        assert!(memory("si - bx", None).is_err());
+    }
+}
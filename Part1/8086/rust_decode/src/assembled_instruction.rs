@@ -1,14 +1,19 @@
 use bitflags::bitflags;
+use strum_macros::EnumIter;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum BitUsage {
     LITERAL,
+    /// Literal in REG field of the second byte selecting operation of the opcode group
+    Extension,
     MOD,
     REG,
+    SR,
     RM,
     Flag(BitFlag),
     Data(BitOrder),
     Disp(BitOrder),
+    Operand(ImpliedOperand),
 
     PLACEHOLDER,
 }
@@ -19,6 +24,14 @@ pub enum BitOrder {
     HIGH,
 }
 
+/// Operands which are not encoded by any field but are given by the opcode itself
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImpliedOperand {
+    Dx,
+    Cl,
+    One,
+}
+
 bitflags! {
     #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
     pub struct BitFlag: u8 {
@@ -55,25 +68,61 @@ impl Bits {
         }
     }
 
+    /// Instructions sharing the first byte differ in the value of their extension
+    pub const fn extension(value: u8) -> Self {
+        Bits {
+            usage: BitUsage::Extension,
+            value: Some(value),
+            shift: None,
+            size: 3,
+        }
+    }
+
+    /// Field which takes no bits in the instruction, its value is fixed by the opcode
+    pub const fn implied(usage: BitUsage, value: u8) -> Self {
+        Bits {
+            usage,
+            value: Some(value),
+            shift: None,
+            size: 0,
+        }
+    }
+
     pub fn is_bit_usage(&self, bit_usage: &BitUsage) -> bool {
         self.usage == *bit_usage
     }
 
+    pub const fn is_implied(&self) -> bool {
+        self.size == 0
+    }
+
     const fn mask(&self) -> u8 {
         let mut i = 0;
         let mut mask: u8 = 1;
 
         while i < self.size {
-            mask = mask | 1 << i;
+            mask |= 1 << i;
             i += 1;
         }
 
         mask
     }
 
-    pub fn decode_value(&self, byte: u8) -> u8 {
+    pub const fn decode_value(&self, byte: u8) -> u8 {
+        if self.is_implied() {
+            return self.value.expect("Implied bits have to have a value");
+        }
+
         byte >> self.shift.expect("Every bits need shift specified") & self.mask()
     }
+
+    /// Inverse of `decode_value`, implied bits take no space in the byte
+    pub fn encode_value(&self, value: u8) -> u8 {
+        match self.shift {
+            Some(shift) if !self.is_implied() => (value & self.mask()) << shift,
+            _ => 0,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -104,21 +153,31 @@ macro_rules! bits {
     };
 }
 
-const MOD: Bits = bits!(BitUsage::MOD, 2);
+pub const MOD: Bits = bits!(BitUsage::MOD, 2);
 const REG: Bits = bits!(BitUsage::REG, 3);
+const SR: Bits = bits!(BitUsage::SR, 2);
 const RM: Bits = bits!(BitUsage::RM, 3);
 const D: Bits = bits!(-f BitFlag::D);
 pub const S: Bits = bits!(-f BitFlag::S);
-const W: Bits = bits!(-f BitFlag::W);
+pub const W: Bits = bits!(-f BitFlag::W);
+pub const V: Bits = bits!(-f BitFlag::V);
 const DATA_LO: Bits = bits!(-data BitOrder::LOW);
-const DATA_HI: Bits = bits!(-data BitOrder::HIGH);
-const DISP_LO: Bits = bits!(-disp BitOrder::LOW);
-const DISP_HI: Bits = bits!(-disp BitOrder::HIGH);
+pub const DATA_HI: Bits = bits!(-data BitOrder::HIGH);
+pub const DISP_LO: Bits = bits!(-disp BitOrder::LOW);
+pub const DISP_HI: Bits = bits!(-disp BitOrder::HIGH);
+
+const D_SET: Bits = Bits::implied(BitUsage::Flag(BitFlag::D), 1);
+const W_SET: Bits = Bits::implied(BitUsage::Flag(BitFlag::W), 1);
+const ACC: Bits = Bits::implied(BitUsage::REG, 0b000);
+const MOD_REGISTER: Bits = Bits::implied(BitUsage::MOD, 0b11);
+const MOD_MEMORY: Bits = Bits::implied(BitUsage::MOD, 0b00);
+const RM_ACC: Bits = Bits::implied(BitUsage::RM, 0b000);
+const RM_DIRECT: Bits = Bits::implied(BitUsage::RM, 0b110);
+const DX: Bits = Bits::implied(BitUsage::Operand(ImpliedOperand::Dx), 0);
 
 #[derive(Debug)]
 pub enum AssembledInstructionLookupError {
     IncompleteDefinitionError,
-    LiteralMissingError,
     InstructionUndefinedError,
 }
 
@@ -131,18 +190,82 @@ pub struct AssembledInstruction {
 }
 
 impl AssembledInstruction {
-    pub fn literal_in(&self, byte: u8) -> InstuctionLookupResult<bool> {
-        let literal = self.bytes[0]
-            .ok_or(AssembledInstructionLookupError::IncompleteDefinitionError)?
-            .bits[0]
-            .ok_or(AssembledInstructionLookupError::IncompleteDefinitionError)?;
-
-        let _ = matches!(literal.usage, BitUsage::LITERAL)
-            .then(|| ())
-            .ok_or(AssembledInstructionLookupError::LiteralMissingError)?;
-
-        Ok(literal.value.expect("Literal has to have a value")
-            == byte >> literal.shift.expect("Should not Fail"))
+    /// Malformed definition fails the build when the dispatch table is evaluated
+    const fn literal_in(&self, byte: u8) -> bool {
+        let first_byte = match self.bytes[0] {
+            Some(first_byte) => first_byte,
+            None => panic!("Instruction has to have at least one byte"),
+        };
+
+        if !matches!(
+            first_byte.bits[0],
+            Some(Bits {
+                usage: BitUsage::LITERAL,
+                ..
+            })
+        ) {
+            panic!("Instruction has to start with a literal");
+        }
+
+        // Some instructions have literal also after a field, e.g. PUSH segment register
+        let mut i = 0;
+
+        while i < first_byte.bits.len() {
+            if let Some(
+                bits @ Bits {
+                    usage: BitUsage::LITERAL,
+                    ..
+                },
+            ) = first_byte.bits[i]
+            {
+                match bits.value {
+                    Some(value) if value == bits.decode_value(byte) => (),
+                    _ => return false,
+                }
+            }
+
+            i += 1;
+        }
+
+        true
+    }
+
+    /// Extension of the instruction in the opcode group, none when it is not in any group
+    pub const fn extension(&self) -> Option<u8> {
+        match self.extension_bits() {
+            Some(bits) => bits.value,
+            None => None,
+        }
+    }
+
+    /// Extension given by the second byte of the instruction in the same group as this one
+    pub fn extension_in(&self, byte: u8) -> Option<u8> {
+        self.extension_bits().map(|bits| bits.decode_value(byte))
+    }
+
+    const fn extension_bits(&self) -> Option<Bits> {
+        let second_byte = match self.bytes[1] {
+            Some(second_byte) => second_byte,
+            None => return None,
+        };
+
+        let mut i = 0;
+
+        while i < second_byte.bits.len() {
+            if let Some(
+                bits @ Bits {
+                    usage: BitUsage::Extension,
+                    ..
+                },
+            ) = second_byte.bits[i]
+            {
+                return Some(bits);
+            }
+
+            i += 1;
+        }
+
+        None
     }
 
     pub fn includes_bits(&self, bits_checked_againts: Bits) -> bool {
@@ -172,7 +295,7 @@ macro_rules! INSTR {
 
             AssembledInstruction {
                 operation: $operation,
-                bytes: bytes
+                bytes
             }
         }
     };
@@ -183,9 +306,9 @@ macro_rules! INSTR {
             let mut shift = 8;
 
             while i < $byte.len() {
-                let mut bits_cp: Bits = $byte[i].clone();
+                let mut bits_cp: Bits = $byte[i];
                 shift -= bits_cp.size;
-                if let None = bits_cp.shift {
+                if bits_cp.shift.is_none() {
                     bits_cp.shift = Some(shift);
                 }
 
@@ -198,12 +321,65 @@ macro_rules! INSTR {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumIter)]
 pub enum Operation {
     MOV,
+    PUSH,
+    POP,
+    XCHG,
+    IN,
+    OUT,
+    XLAT,
+    LEA,
+    LDS,
+    LES,
+    LAHF,
+    SAHF,
+    PUSHF,
+    POPF,
     ADD,
+    ADC,
+    INC,
+    AAA,
+    DAA,
     SUB,
+    SBB,
+    DEC,
+    NEG,
     CMP,
+    AAS,
+    DAS,
+    MUL,
+    IMUL,
+    AAM,
+    DIV,
+    IDIV,
+    AAD,
+    CBW,
+    CWD,
+    NOT,
+    SHL,
+    SHR,
+    SAR,
+    ROL,
+    ROR,
+    RCL,
+    RCR,
+    AND,
+    TEST,
+    OR,
+    XOR,
+    MOVS,
+    CMPS,
+    SCAS,
+    LODS,
+    STOS,
+    CALL,
+    CALLF,
+    JMP,
+    JMPF,
+    RET,
+    RETF,
     JNZ,
     JE,
     JL,
@@ -224,6 +400,43 @@ pub enum Operation {
     LOOPZ,
     LOOPNZ,
     JCXZ,
+    INT,
+    INT3,
+    INTO,
+    IRET,
+    CLC,
+    CMC,
+    STC,
+    CLD,
+    STD,
+    CLI,
+    STI,
+    HLT,
+    WAIT,
+    NOP,
+}
+
+impl Operation {
+    /// String instructions are printed with b/w suffix instead of operands
+    pub fn is_string(&self) -> bool {
+        matches!(self, MOVS | CMPS | SCAS | LODS | STOS)
+    }
+
+    pub fn is_shift(&self) -> bool {
+        matches!(self, SHL | SHR | SAR | ROL | ROR | RCL | RCR)
+    }
+
+    pub fn is_far(&self) -> bool {
+        matches!(self, CALLF | JMPF)
+    }
+
+    /// Instructions pushing to or popping from the stack
+    pub fn uses_stack(&self) -> bool {
+        matches!(
+            self,
+            PUSH | POP | PUSHF | POPF | CALL | CALLF | RET | RETF | INT | INT3 | INTO | IRET
+        )
+    }
 }
 
 use std::fmt;
@@ -232,9 +445,62 @@ impl fmt::Display for Operation {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let str_repr = match self {
             MOV => "mov",
+            PUSH => "push",
+            POP => "pop",
+            XCHG => "xchg",
+            IN => "in",
+            OUT => "out",
+            XLAT => "xlatb",
+            LEA => "lea",
+            LDS => "lds",
+            LES => "les",
+            LAHF => "lahf",
+            SAHF => "sahf",
+            PUSHF => "pushf",
+            POPF => "popf",
             ADD => "add",
+            ADC => "adc",
+            INC => "inc",
+            AAA => "aaa",
+            DAA => "daa",
             SUB => "sub",
+            SBB => "sbb",
+            DEC => "dec",
+            NEG => "neg",
             CMP => "cmp",
+            AAS => "aas",
+            DAS => "das",
+            MUL => "mul",
+            IMUL => "imul",
+            AAM => "aam",
+            DIV => "div",
+            IDIV => "idiv",
+            AAD => "aad",
+            CBW => "cbw",
+            CWD => "cwd",
+            NOT => "not",
+            SHL => "shl",
+            SHR => "shr",
+            SAR => "sar",
+            ROL => "rol",
+            ROR => "ror",
+            RCL => "rcl",
+            RCR => "rcr",
+            AND => "and",
+            TEST => "test",
+            OR => "or",
+            XOR => "xor",
+            MOVS => "movs",
+            CMPS => "cmps",
+            SCAS => "scas",
+            LODS => "lods",
+            STOS => "stos",
+            CALL => "call",
+            CALLF => "call",
+            JMP => "jmp",
+            JMPF => "jmp",
+            RET => "ret",
+            RETF => "retf",
             JNZ => "jnz",
             JE => "je",
             JL => "jl",
@@ -255,6 +521,20 @@ impl fmt::Display for Operation {
             LOOPZ => "loopz",
             LOOPNZ => "loopnz",
             JCXZ => "jcxz",
+            INT => "int",
+            INT3 => "int3",
+            INTO => "into",
+            IRET => "iret",
+            CLC => "clc",
+            CMC => "cmc",
+            STC => "stc",
+            CLD => "cld",
+            STD => "std",
+            CLI => "cli",
+            STI => "sti",
+            HLT => "hlt",
+            WAIT => "wait",
+            NOP => "nop",
         };
 
         write!(f, "{}", str_repr)
@@ -263,118 +543,630 @@ impl fmt::Display for Operation {
 
 use Operation::*;
 
-lazy_static! {
-    pub static ref ADD_INSTR: AssembledInstruction = INSTR!(
+static INSTRUCTION_TABLE: [AssembledInstruction; 131] = [
+    // Data transfer
+    INSTR!(
+        MOV,
+        [Bits::literal(0b100010, 6), D, W],
+        [MOD, REG, RM],
+        [DISP_LO],
+        [DISP_HI]
+    ),
+    INSTR!(
+        MOV,
+        [Bits::literal(0b1011, 4), W, REG],
+        [DATA_LO],
+        [DATA_HI]
+    ),
+    INSTR!(
+        MOV,
+        [Bits::literal(0b1100011, 7), W],
+        [MOD, Bits::extension(0b000), RM],
+        [DISP_LO],
+        [DISP_HI],
+        [DATA_LO],
+        [DATA_HI]
+    ),
+    INSTR!(
+        MOV,
+        [
+            Bits::literal(0b1010000, 7),
+            W,
+            D_SET,
+            ACC,
+            MOD_MEMORY,
+            RM_DIRECT
+        ],
+        [DISP_LO],
+        [DISP_HI]
+    ),
+    INSTR!(
+        MOV,
+        [Bits::literal(0b1010001, 7), W, ACC, MOD_MEMORY, RM_DIRECT],
+        [DISP_LO],
+        [DISP_HI]
+    ),
+    INSTR!(
+        MOV,
+        [Bits::literal(0b10001110, 8), W_SET, D_SET],
+        [MOD, Bits::literal(0b0, 1), SR, RM],
+        [DISP_LO],
+        [DISP_HI]
+    ),
+    INSTR!(
+        MOV,
+        [Bits::literal(0b10001100, 8), W_SET],
+        [MOD, Bits::literal(0b0, 1), SR, RM],
+        [DISP_LO],
+        [DISP_HI]
+    ),
+    INSTR!(
+        PUSH,
+        [Bits::literal(0b11111111, 8), W_SET],
+        [MOD, Bits::extension(0b110), RM],
+        [DISP_LO],
+        [DISP_HI]
+    ),
+    INSTR!(PUSH, [Bits::literal(0b01010, 5), W_SET, REG]),
+    INSTR!(
+        PUSH,
+        [Bits::literal(0b000, 3), W_SET, SR, Bits::literal(0b110, 3)]
+    ),
+    INSTR!(
+        POP,
+        [Bits::literal(0b10001111, 8), W_SET],
+        [MOD, Bits::extension(0b000), RM],
+        [DISP_LO],
+        [DISP_HI]
+    ),
+    INSTR!(POP, [Bits::literal(0b01011, 5), W_SET, REG]),
+    INSTR!(
+        POP,
+        [Bits::literal(0b000, 3), W_SET, SR, Bits::literal(0b111, 3)]
+    ),
+    INSTR!(NOP, [Bits::literal(0b10010000, 8)]),
+    INSTR!(
+        XCHG,
+        [Bits::literal(0b1000011, 7), W],
+        [MOD, REG, RM],
+        [DISP_LO],
+        [DISP_HI]
+    ),
+    INSTR!(
+        XCHG,
+        [Bits::literal(0b10010, 5), W_SET, REG, MOD_REGISTER, RM_ACC]
+    ),
+    INSTR!(IN, [Bits::literal(0b1110010, 7), W, ACC], [DATA_LO]),
+    INSTR!(IN, [Bits::literal(0b1110110, 7), W, D_SET, ACC, DX]),
+    INSTR!(
+        OUT,
+        [Bits::literal(0b1110011, 7), W, D_SET, MOD_REGISTER, RM_ACC],
+        [DATA_LO]
+    ),
+    INSTR!(OUT, [Bits::literal(0b1110111, 7), W, ACC, DX]),
+    INSTR!(XLAT, [Bits::literal(0b11010111, 8)]),
+    INSTR!(
+        LEA,
+        [Bits::literal(0b10001101, 8), W_SET, D_SET],
+        [MOD, REG, RM],
+        [DISP_LO],
+        [DISP_HI]
+    ),
+    INSTR!(
+        LDS,
+        [Bits::literal(0b11000101, 8), W_SET, D_SET],
+        [MOD, REG, RM],
+        [DISP_LO],
+        [DISP_HI]
+    ),
+    INSTR!(
+        LES,
+        [Bits::literal(0b11000100, 8), W_SET, D_SET],
+        [MOD, REG, RM],
+        [DISP_LO],
+        [DISP_HI]
+    ),
+    INSTR!(LAHF, [Bits::literal(0b10011111, 8)]),
+    INSTR!(SAHF, [Bits::literal(0b10011110, 8)]),
+    INSTR!(PUSHF, [Bits::literal(0b10011100, 8)]),
+    INSTR!(POPF, [Bits::literal(0b10011101, 8)]),
+    // Arithmetic
+    INSTR!(
+        ADD,
+        [Bits::literal(0b000000, 6), D, W],
+        [MOD, REG, RM],
+        [DISP_LO],
+        [DISP_HI]
+    ),
+    INSTR!(
         ADD,
         [Bits::literal(0b100000, 6), S, W],
-        [MOD, Bits::literal(0b000, 3), RM],
+        [MOD, Bits::extension(0b000), RM],
         [DISP_LO],
         [DISP_HI],
         [DATA_LO],
         [DATA_HI]
-    );
-    pub static ref SUB_INSTR: AssembledInstruction = INSTR!(
+    ),
+    INSTR!(
+        ADD,
+        [Bits::literal(0b0000010, 7), W, ACC],
+        [DATA_LO],
+        [DATA_HI]
+    ),
+    INSTR!(
+        ADC,
+        [Bits::literal(0b000100, 6), D, W],
+        [MOD, REG, RM],
+        [DISP_LO],
+        [DISP_HI]
+    ),
+    INSTR!(
+        ADC,
+        [Bits::literal(0b100000, 6), S, W],
+        [MOD, Bits::extension(0b010), RM],
+        [DISP_LO],
+        [DISP_HI],
+        [DATA_LO],
+        [DATA_HI]
+    ),
+    INSTR!(
+        ADC,
+        [Bits::literal(0b0001010, 7), W, ACC],
+        [DATA_LO],
+        [DATA_HI]
+    ),
+    INSTR!(
+        INC,
+        [Bits::literal(0b1111111, 7), W],
+        [MOD, Bits::extension(0b000), RM],
+        [DISP_LO],
+        [DISP_HI]
+    ),
+    INSTR!(INC, [Bits::literal(0b01000, 5), W_SET, REG]),
+    INSTR!(AAA, [Bits::literal(0b00110111, 8)]),
+    INSTR!(DAA, [Bits::literal(0b00100111, 8)]),
+    INSTR!(
+        SUB,
+        [Bits::literal(0b001010, 6), D, W],
+        [MOD, REG, RM],
+        [DISP_LO],
+        [DISP_HI]
+    ),
+    INSTR!(
         SUB,
         [Bits::literal(0b100000, 6), S, W],
-        [MOD, Bits::literal(0b101, 3), RM],
+        [MOD, Bits::extension(0b101), RM],
+        [DISP_LO],
+        [DISP_HI],
+        [DATA_LO],
+        [DATA_HI]
+    ),
+    INSTR!(
+        SUB,
+        [Bits::literal(0b0010110, 7), W, ACC],
+        [DATA_LO],
+        [DATA_HI]
+    ),
+    INSTR!(
+        SBB,
+        [Bits::literal(0b000110, 6), D, W],
+        [MOD, REG, RM],
+        [DISP_LO],
+        [DISP_HI]
+    ),
+    INSTR!(
+        SBB,
+        [Bits::literal(0b100000, 6), S, W],
+        [MOD, Bits::extension(0b011), RM],
         [DISP_LO],
         [DISP_HI],
         [DATA_LO],
         [DATA_HI]
-    );
-    pub static ref CMP_INSTR: AssembledInstruction = INSTR!(
+    ),
+    INSTR!(
+        SBB,
+        [Bits::literal(0b0001110, 7), W, ACC],
+        [DATA_LO],
+        [DATA_HI]
+    ),
+    INSTR!(
+        DEC,
+        [Bits::literal(0b1111111, 7), W],
+        [MOD, Bits::extension(0b001), RM],
+        [DISP_LO],
+        [DISP_HI]
+    ),
+    INSTR!(DEC, [Bits::literal(0b01001, 5), W_SET, REG]),
+    INSTR!(
+        NEG,
+        [Bits::literal(0b1111011, 7), W],
+        [MOD, Bits::extension(0b011), RM],
+        [DISP_LO],
+        [DISP_HI]
+    ),
+    INSTR!(
+        CMP,
+        [Bits::literal(0b001110, 6), D, W],
+        [MOD, REG, RM],
+        [DISP_LO],
+        [DISP_HI]
+    ),
+    INSTR!(
         CMP,
         [Bits::literal(0b100000, 6), S, W],
-        [MOD, Bits::literal(0b111, 3), RM],
+        [MOD, Bits::extension(0b111), RM],
         [DISP_LO],
         [DISP_HI],
         [DATA_LO],
         [DATA_HI]
-    );
-    static ref INSTRUCTION_TABLE: [AssembledInstruction; 35] = [
-        INSTR!(
-            MOV,
-            [Bits::literal(0b100010, 6), D, W],
-            [MOD, REG, RM],
-            [DISP_LO],
-            [DISP_HI]
-        ),
-        INSTR!(
-            MOV,
-            [Bits::literal(0b1011, 4), W, REG],
-            [DATA_LO],
-            [DATA_HI]
-        ),
-        INSTR!(
-            MOV,
-            [Bits::literal(0b1100011, 7), W],
-            [MOD, Bits::literal(0b000, 3), RM],
-            [DISP_LO],
-            [DISP_HI],
-            [DATA_LO],
-            [DATA_HI]
-        ),
-        INSTR!(MOV, [Bits::literal(0b1010000, 7), W], [DISP_LO], [DISP_HI]),
-        INSTR!(MOV, [Bits::literal(0b1010001, 7), W], [DISP_LO], [DISP_HI]),
-        INSTR!(
-            ADD,
-            [Bits::literal(0b000000, 6), D, W],
-            [MOD, REG, RM],
-            [DISP_LO],
-            [DISP_HI]
-        ),
-        *ADD_INSTR,
-        INSTR!(ADD, [Bits::literal(0b0000010, 7), W], [DATA_LO], [DATA_HI]),
-        INSTR!(
-            SUB,
-            [Bits::literal(0b001010, 6), D, W],
-            [MOD, REG, RM],
-            [DISP_LO],
-            [DISP_HI]
-        ),
-        *SUB_INSTR,
-        INSTR!(SUB, [Bits::literal(0b0010110, 7), W], [DATA_LO], [DATA_HI]),
-        INSTR!(
-            CMP,
-            [Bits::literal(0b001110, 6), D, W],
-            [MOD, REG, RM],
-            [DISP_LO],
-            [DISP_HI]
-        ),
-        *CMP_INSTR,
-        INSTR!(CMP, [Bits::literal(0b0011110, 7), W], [DATA_LO], [DATA_HI]),
-        INSTR!(CMP, [Bits::literal(0b00000000, 8)], [DISP_LO]),
-        INSTR!(JNZ, [Bits::literal(0b01110101, 8)], [DISP_LO]),
-        INSTR!(JE, [Bits::literal(0b01110100, 8)], [DISP_LO]),
-        INSTR!(JL, [Bits::literal(0b01111100, 8)], [DISP_LO]),
-        INSTR!(JLE, [Bits::literal(0b01111110, 8)], [DISP_LO]),
-        INSTR!(JB, [Bits::literal(0b01110010, 8)], [DISP_LO]),
-        INSTR!(JBE, [Bits::literal(0b01110110, 8)], [DISP_LO]),
-        INSTR!(JP, [Bits::literal(0b01111010, 8)], [DISP_LO]),
-        INSTR!(JO, [Bits::literal(0b01110000, 8)], [DISP_LO]),
-        INSTR!(JS, [Bits::literal(0b01111000, 8)], [DISP_LO]),
-        INSTR!(JNL, [Bits::literal(0b01111101, 8)], [DISP_LO]),
-        INSTR!(JG, [Bits::literal(0b01111111, 8)], [DISP_LO]),
-        INSTR!(JNB, [Bits::literal(0b01110011, 8)], [DISP_LO]),
-        INSTR!(JA, [Bits::literal(0b01110111, 8)], [DISP_LO]),
-        INSTR!(JNP, [Bits::literal(0b01111011, 8)], [DISP_LO]),
-        INSTR!(JNO, [Bits::literal(0b01110001, 8)], [DISP_LO]),
-        INSTR!(JNS, [Bits::literal(0b01111001, 8)], [DISP_LO]),
-        INSTR!(LOOP, [Bits::literal(0b11100010, 8)], [DISP_LO]),
-        INSTR!(LOOPZ, [Bits::literal(0b11100001, 8)], [DISP_LO]),
-        INSTR!(LOOPNZ, [Bits::literal(0b11100000, 8)], [DISP_LO]),
-        INSTR!(JCXZ, [Bits::literal(0b11100011, 8)], [DISP_LO]),
-    ];
+    ),
+    INSTR!(
+        CMP,
+        [Bits::literal(0b0011110, 7), W, ACC],
+        [DATA_LO],
+        [DATA_HI]
+    ),
+    INSTR!(AAS, [Bits::literal(0b00111111, 8)]),
+    INSTR!(DAS, [Bits::literal(0b00101111, 8)]),
+    INSTR!(
+        MUL,
+        [Bits::literal(0b1111011, 7), W],
+        [MOD, Bits::extension(0b100), RM],
+        [DISP_LO],
+        [DISP_HI]
+    ),
+    INSTR!(
+        IMUL,
+        [Bits::literal(0b1111011, 7), W],
+        [MOD, Bits::extension(0b101), RM],
+        [DISP_LO],
+        [DISP_HI]
+    ),
+    INSTR!(AAM, [Bits::literal(0b11010100, 8)], [DATA_LO]),
+    INSTR!(
+        DIV,
+        [Bits::literal(0b1111011, 7), W],
+        [MOD, Bits::extension(0b110), RM],
+        [DISP_LO],
+        [DISP_HI]
+    ),
+    INSTR!(
+        IDIV,
+        [Bits::literal(0b1111011, 7), W],
+        [MOD, Bits::extension(0b111), RM],
+        [DISP_LO],
+        [DISP_HI]
+    ),
+    INSTR!(AAD, [Bits::literal(0b11010101, 8)], [DATA_LO]),
+    INSTR!(CBW, [Bits::literal(0b10011000, 8)]),
+    INSTR!(CWD, [Bits::literal(0b10011001, 8)]),
+    // Logic
+    INSTR!(
+        NOT,
+        [Bits::literal(0b1111011, 7), W],
+        [MOD, Bits::extension(0b010), RM],
+        [DISP_LO],
+        [DISP_HI]
+    ),
+    INSTR!(
+        SHL,
+        [Bits::literal(0b110100, 6), V, W],
+        [MOD, Bits::extension(0b100), RM],
+        [DISP_LO],
+        [DISP_HI]
+    ),
+    INSTR!(
+        SHR,
+        [Bits::literal(0b110100, 6), V, W],
+        [MOD, Bits::extension(0b101), RM],
+        [DISP_LO],
+        [DISP_HI]
+    ),
+    INSTR!(
+        SAR,
+        [Bits::literal(0b110100, 6), V, W],
+        [MOD, Bits::extension(0b111), RM],
+        [DISP_LO],
+        [DISP_HI]
+    ),
+    INSTR!(
+        ROL,
+        [Bits::literal(0b110100, 6), V, W],
+        [MOD, Bits::extension(0b000), RM],
+        [DISP_LO],
+        [DISP_HI]
+    ),
+    INSTR!(
+        ROR,
+        [Bits::literal(0b110100, 6), V, W],
+        [MOD, Bits::extension(0b001), RM],
+        [DISP_LO],
+        [DISP_HI]
+    ),
+    INSTR!(
+        RCL,
+        [Bits::literal(0b110100, 6), V, W],
+        [MOD, Bits::extension(0b010), RM],
+        [DISP_LO],
+        [DISP_HI]
+    ),
+    INSTR!(
+        RCR,
+        [Bits::literal(0b110100, 6), V, W],
+        [MOD, Bits::extension(0b011), RM],
+        [DISP_LO],
+        [DISP_HI]
+    ),
+    INSTR!(
+        AND,
+        [Bits::literal(0b001000, 6), D, W],
+        [MOD, REG, RM],
+        [DISP_LO],
+        [DISP_HI]
+    ),
+    INSTR!(
+        AND,
+        [Bits::literal(0b100000, 6), S, W],
+        [MOD, Bits::extension(0b100), RM],
+        [DISP_LO],
+        [DISP_HI],
+        [DATA_LO],
+        [DATA_HI]
+    ),
+    INSTR!(
+        AND,
+        [Bits::literal(0b0010010, 7), W, ACC],
+        [DATA_LO],
+        [DATA_HI]
+    ),
+    INSTR!(
+        TEST,
+        [Bits::literal(0b1000010, 7), W],
+        [MOD, REG, RM],
+        [DISP_LO],
+        [DISP_HI]
+    ),
+    INSTR!(
+        TEST,
+        [Bits::literal(0b1111011, 7), W],
+        [MOD, Bits::extension(0b000), RM],
+        [DISP_LO],
+        [DISP_HI],
+        [DATA_LO],
+        [DATA_HI]
+    ),
+    INSTR!(
+        TEST,
+        [Bits::literal(0b1010100, 7), W, ACC],
+        [DATA_LO],
+        [DATA_HI]
+    ),
+    INSTR!(
+        OR,
+        [Bits::literal(0b000010, 6), D, W],
+        [MOD, REG, RM],
+        [DISP_LO],
+        [DISP_HI]
+    ),
+    INSTR!(
+        OR,
+        [Bits::literal(0b100000, 6), S, W],
+        [MOD, Bits::extension(0b001), RM],
+        [DISP_LO],
+        [DISP_HI],
+        [DATA_LO],
+        [DATA_HI]
+    ),
+    INSTR!(
+        OR,
+        [Bits::literal(0b0000110, 7), W, ACC],
+        [DATA_LO],
+        [DATA_HI]
+    ),
+    INSTR!(
+        XOR,
+        [Bits::literal(0b001100, 6), D, W],
+        [MOD, REG, RM],
+        [DISP_LO],
+        [DISP_HI]
+    ),
+    INSTR!(
+        XOR,
+        [Bits::literal(0b100000, 6), S, W],
+        [MOD, Bits::extension(0b110), RM],
+        [DISP_LO],
+        [DISP_HI],
+        [DATA_LO],
+        [DATA_HI]
+    ),
+    INSTR!(
+        XOR,
+        [Bits::literal(0b0011010, 7), W, ACC],
+        [DATA_LO],
+        [DATA_HI]
+    ),
+    // String manipulation
+    INSTR!(MOVS, [Bits::literal(0b1010010, 7), W]),
+    INSTR!(CMPS, [Bits::literal(0b1010011, 7), W]),
+    INSTR!(SCAS, [Bits::literal(0b1010111, 7), W]),
+    INSTR!(LODS, [Bits::literal(0b1010110, 7), W]),
+    INSTR!(STOS, [Bits::literal(0b1010101, 7), W]),
+    // Control transfer
+    INSTR!(CALL, [Bits::literal(0b11101000, 8)], [DISP_LO], [DISP_HI]),
+    INSTR!(
+        CALL,
+        [Bits::literal(0b11111111, 8), W_SET],
+        [MOD, Bits::extension(0b010), RM],
+        [DISP_LO],
+        [DISP_HI]
+    ),
+    INSTR!(
+        CALLF,
+        [Bits::literal(0b10011010, 8), W_SET],
+        [DISP_LO],
+        [DISP_HI],
+        [DATA_LO],
+        [DATA_HI]
+    ),
+    INSTR!(
+        CALLF,
+        [Bits::literal(0b11111111, 8), W_SET],
+        [MOD, Bits::extension(0b011), RM],
+        [DISP_LO],
+        [DISP_HI]
+    ),
+    INSTR!(JMP, [Bits::literal(0b11101001, 8)], [DISP_LO], [DISP_HI]),
+    INSTR!(JMP, [Bits::literal(0b11101011, 8)], [DISP_LO]),
+    INSTR!(
+        JMP,
+        [Bits::literal(0b11111111, 8), W_SET],
+        [MOD, Bits::extension(0b100), RM],
+        [DISP_LO],
+        [DISP_HI]
+    ),
+    INSTR!(
+        JMPF,
+        [Bits::literal(0b11101010, 8), W_SET],
+        [DISP_LO],
+        [DISP_HI],
+        [DATA_LO],
+        [DATA_HI]
+    ),
+    INSTR!(
+        JMPF,
+        [Bits::literal(0b11111111, 8), W_SET],
+        [MOD, Bits::extension(0b101), RM],
+        [DISP_LO],
+        [DISP_HI]
+    ),
+    INSTR!(RET, [Bits::literal(0b11000011, 8)]),
+    INSTR!(
+        RET,
+        [Bits::literal(0b11000010, 8), W_SET],
+        [DATA_LO],
+        [DATA_HI]
+    ),
+    INSTR!(RETF, [Bits::literal(0b11001011, 8)]),
+    INSTR!(
+        RETF,
+        [Bits::literal(0b11001010, 8), W_SET],
+        [DATA_LO],
+        [DATA_HI]
+    ),
+    INSTR!(JNZ, [Bits::literal(0b01110101, 8)], [DISP_LO]),
+    INSTR!(JE, [Bits::literal(0b01110100, 8)], [DISP_LO]),
+    INSTR!(JL, [Bits::literal(0b01111100, 8)], [DISP_LO]),
+    INSTR!(JLE, [Bits::literal(0b01111110, 8)], [DISP_LO]),
+    INSTR!(JB, [Bits::literal(0b01110010, 8)], [DISP_LO]),
+    INSTR!(JBE, [Bits::literal(0b01110110, 8)], [DISP_LO]),
+    INSTR!(JP, [Bits::literal(0b01111010, 8)], [DISP_LO]),
+    INSTR!(JO, [Bits::literal(0b01110000, 8)], [DISP_LO]),
+    INSTR!(JS, [Bits::literal(0b01111000, 8)], [DISP_LO]),
+    INSTR!(JNL, [Bits::literal(0b01111101, 8)], [DISP_LO]),
+    INSTR!(JG, [Bits::literal(0b01111111, 8)], [DISP_LO]),
+    INSTR!(JNB, [Bits::literal(0b01110011, 8)], [DISP_LO]),
+    INSTR!(JA, [Bits::literal(0b01110111, 8)], [DISP_LO]),
+    INSTR!(JNP, [Bits::literal(0b01111011, 8)], [DISP_LO]),
+    INSTR!(JNO, [Bits::literal(0b01110001, 8)], [DISP_LO]),
+    INSTR!(JNS, [Bits::literal(0b01111001, 8)], [DISP_LO]),
+    INSTR!(LOOP, [Bits::literal(0b11100010, 8)], [DISP_LO]),
+    INSTR!(LOOPZ, [Bits::literal(0b11100001, 8)], [DISP_LO]),
+    INSTR!(LOOPNZ, [Bits::literal(0b11100000, 8)], [DISP_LO]),
+    INSTR!(JCXZ, [Bits::literal(0b11100011, 8)], [DISP_LO]),
+    INSTR!(INT, [Bits::literal(0b11001101, 8)], [DATA_LO]),
+    INSTR!(INT3, [Bits::literal(0b11001100, 8)]),
+    INSTR!(INTO, [Bits::literal(0b11001110, 8)]),
+    INSTR!(IRET, [Bits::literal(0b11001111, 8)]),
+    // Processor control
+    INSTR!(CLC, [Bits::literal(0b11111000, 8)]),
+    INSTR!(CMC, [Bits::literal(0b11110101, 8)]),
+    INSTR!(STC, [Bits::literal(0b11111001, 8)]),
+    INSTR!(CLD, [Bits::literal(0b11111100, 8)]),
+    INSTR!(STD, [Bits::literal(0b11111101, 8)]),
+    INSTR!(CLI, [Bits::literal(0b11111010, 8)]),
+    INSTR!(STI, [Bits::literal(0b11111011, 8)]),
+    INSTR!(HLT, [Bits::literal(0b11110100, 8)]),
+    INSTR!(WAIT, [Bits::literal(0b10011011, 8)]),
+];
+
+/// Entry of the dispatch table pointing to the instruction table
+#[derive(Debug, Clone, Copy)]
+enum Dispatch {
+    Undefined,
+    Instruction(u8),
+    /// Instructions sharing the first byte, indexed by the literal in the second byte
+    Group(u8, [Option<u8>; 8]),
 }
 
-pub fn get_assembled_instruction(byte: u8) -> InstuctionLookupResult<AssembledInstruction> {
-    for instr in INSTRUCTION_TABLE.iter() {
-        if instr.literal_in(byte)? {
-            return Ok(instr.clone());
+/// First byte of the instruction selects its definition without scanning the table
+static DISPATCH_TABLE: [Dispatch; 256] = dispatch_table(&INSTRUCTION_TABLE);
+
+/// Earlier definition wins when more of them match the same byte
+const fn dispatch_table(instructions: &[AssembledInstruction]) -> [Dispatch; 256] {
+    let mut table = [Dispatch::Undefined; 256];
+    let mut byte = 0;
+
+    while byte < table.len() {
+        let mut i = 0;
+
+        while i < instructions.len() {
+            if instructions[i].literal_in(byte as u8) {
+                table[byte] = match (table[byte], instructions[i].extension()) {
+                    (Dispatch::Undefined, None) => Dispatch::Instruction(i as u8),
+                    (Dispatch::Undefined, Some(extension)) => {
+                        let mut group = [None; 8];
+                        group[extension as usize] = Some(i as u8);
+                        Dispatch::Group(i as u8, group)
+                    }
+                    (Dispatch::Group(first, mut group), Some(extension)) => {
+                        if group[extension as usize].is_none() {
+                            group[extension as usize] = Some(i as u8);
+                        }
+                        Dispatch::Group(first, group)
+                    }
+                    (dispatch, _) => dispatch,
+                };
+            }
+
+            i += 1;
         }
+
+        byte += 1;
     }
 
-    Err(AssembledInstructionLookupError::InstructionUndefinedError)
+    table
+}
+
+pub fn get_assembled_instruction(
+    byte: u8,
+) -> InstuctionLookupResult<&'static AssembledInstruction> {
+    match DISPATCH_TABLE[byte as usize] {
+        Dispatch::Instruction(i) | Dispatch::Group(i, _) => Ok(&INSTRUCTION_TABLE[i as usize]),
+        Dispatch::Undefined => Err(AssembledInstructionLookupError::InstructionUndefinedError),
+    }
+}
+
+/// All encodings of the operation in the order of their definition
+pub fn definitions_of(operation: Operation) -> impl Iterator<Item = &'static AssembledInstruction> {
+    INSTRUCTION_TABLE
+        .iter()
+        .filter(move |instr| instr.operation == operation)
+}
+
+/// Instructions sharing the first byte are told apart by their extension
+pub fn get_assembled_instruction_in_group(
+    byte: u8,
+    extension: u8,
+) -> InstuctionLookupResult<&'static AssembledInstruction> {
+    match DISPATCH_TABLE[byte as usize] {
+        Dispatch::Group(_, group) => group
+            .get(extension as usize)
+            .copied()
+            .flatten()
+            .map(|i| &INSTRUCTION_TABLE[i as usize])
+            .ok_or(AssembledInstructionLookupError::InstructionUndefinedError),
+        _ => Err(AssembledInstructionLookupError::InstructionUndefinedError),
+    }
 }
 
 #[cfg(test)]
@@ -392,6 +1184,72 @@ mod tests {
             get_assembled_instruction(0b00000000).unwrap().operation,
             ADD
         ));
+        assert!(matches!(
+            get_assembled_instruction(0b11110100).unwrap().operation,
+            HLT
+        ));
+        assert!(matches!(
+            get_assembled_instruction(0b00011110).unwrap().operation,
+            PUSH
+        ));
+        assert!(matches!(
+            get_assembled_instruction(0b00000111).unwrap().operation,
+            POP
+        ));
+        assert!(get_assembled_instruction(0b11110001).is_err());
+    }
+
+    #[test]
+    fn test_get_assembled_instruction_in_group() {
+        assert!(matches!(
+            get_assembled_instruction_in_group(0b10000011, 0b101)
+                .unwrap()
+                .operation,
+            SUB
+        ));
+        assert!(matches!(
+            get_assembled_instruction_in_group(0b11111111, 0b110)
+                .unwrap()
+                .operation,
+            PUSH
+        ));
+        assert!(matches!(
+            get_assembled_instruction_in_group(0b11111110, 0b001)
+                .unwrap()
+                .operation,
+            DEC
+        ));
+        assert!(get_assembled_instruction_in_group(0b11111110, 0b010).is_err());
+        assert!(get_assembled_instruction_in_group(0b11010000, 0b110).is_err());
+    }
+
+    #[test]
+    fn test_dispatch_table_matches_definitions() {
+        for byte in 0..=255 {
+            let first = INSTRUCTION_TABLE
+                .iter()
+                .find(|instr| instr.literal_in(byte));
+
+            assert_eq!(
+                get_assembled_instruction(byte)
+                    .ok()
+                    .map(|instr| instr as *const _),
+                first.map(|instr| instr as *const _)
+            );
+
+            for extension in 0..8 {
+                let member = INSTRUCTION_TABLE
+                    .iter()
+                    .find(|instr| instr.literal_in(byte) && instr.extension() == Some(extension));
+
+                assert_eq!(
+                    get_assembled_instruction_in_group(byte, extension)
+                        .ok()
+                        .map(|instr| instr as *const _),
+                    member.map(|instr| instr as *const _)
+                );
+            }
+        }
     }
 
     #[test]
@@ -428,6 +1286,8 @@ mod tests {
         };
 
         assert_eq!(bits.decode_value(test_byte), 1);
+        assert_eq!(ACC.decode_value(test_byte), 0);
+        assert_eq!(RM_DIRECT.decode_value(test_byte), 0b110);
     }
 
     #[test]
@@ -435,7 +1295,7 @@ mod tests {
         let instr = INSTR!(
             MOV,
             [Bits::literal(0b1100011, 7), W],
-            [MOD, Bits::literal(0b000, 3), RM],
+            [MOD, Bits::extension(0b000), RM],
             [DISP_LO],
             [DISP_HI],
             [DATA_LO],
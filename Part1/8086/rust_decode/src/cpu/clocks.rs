@@ -0,0 +1,622 @@
+use std::fmt;
+
+use clap::ValueEnum;
+
+use super::cpu::{Access, CpuOperand, EffectiveAddress, Reg};
+use crate::assembled_instruction::Operation::{self, *};
+use crate::instruction::operand::Size;
+
+/// Processors differ only in the penalty for word transfers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Processor {
+    #[value(name = "8086")]
+    I8086,
+    #[value(name = "8088")]
+    I8088,
+}
+
+/// Clocks of a single instruction split the way the reference output prints them
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Clocks {
+    pub base: u32,
+    pub ea: u32,
+    pub penalty: u32,
+}
+
+impl Clocks {
+    pub fn total(&self) -> u32 {
+        self.base + self.ea + self.penalty
+    }
+}
+
+/// What happened during execution of the instruction that changes its timing
+#[derive(Debug, Clone, Copy)]
+pub struct Execution {
+    pub operation: Operation,
+    pub destination: CpuOperand,
+    pub source: CpuOperand,
+    pub size: Size,
+    pub jump_taken: bool,
+    /// Repetitions of a string instruction or count of a shift by CL
+    pub repetitions: Option<u32>,
+    pub odd_address: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Accumulator,
+    Register,
+    Segment,
+    Memory,
+    Immediate,
+    None,
+}
+
+impl Kind {
+    fn new(operand: &CpuOperand) -> Self {
+        match operand {
+            CpuOperand::Register(Reg::A, _) => Kind::Accumulator,
+            CpuOperand::Register(Reg::Es | Reg::Cs | Reg::Ss | Reg::Ds, _) => Kind::Segment,
+            CpuOperand::Register(_, _) => Kind::Register,
+            CpuOperand::Memory(_, _, _) => Kind::Memory,
+            CpuOperand::Immediate(_) => Kind::Immediate,
+            CpuOperand::Jump(_) | CpuOperand::FarPointer(_, _) | CpuOperand::NotUsed => Kind::None,
+        }
+    }
+}
+
+impl EffectiveAddress {
+    /// Effective address calculation time from the 8086 manual
+    pub fn clocks(&self) -> u32 {
+        match *self {
+            Self::Si(0) | Self::Di(0) | Self::Bx(0) => 5,
+            Self::Si(_) | Self::Di(_) | Self::Bx(_) | Self::Bp(_) => 9,
+            Self::BpDi(0) | Self::BxSi(0) => 7,
+            Self::BpSi(0) | Self::BxDi(0) => 8,
+            Self::BpDi(_) | Self::BxSi(_) => 11,
+            Self::BpSi(_) | Self::BxDi(_) => 12,
+        }
+    }
+}
+
+/// Segment override costs two more clocks on top of the address calculation
+fn ea_clocks(operand: &CpuOperand) -> u32 {
+    match operand {
+        CpuOperand::Memory(access, segment, _) => {
+            let clocks = match access {
+                Access::Direct(_) => 6,
+                Access::Address(address) => address.clocks(),
+            };
+
+            clocks + if segment.is_some() { 2 } else { 0 }
+        }
+        _ => 0,
+    }
+}
+
+/// Base clocks and number of memory transfers of the instruction
+fn base_clocks(execution: &Execution) -> (u32, u32) {
+    let dst = Kind::new(&execution.destination);
+    let src = Kind::new(&execution.source);
+    let taken = execution.jump_taken;
+
+    match (execution.operation, dst, src) {
+        (MOV, _, _) if is_accumulator_direct(execution) => (10, 1),
+        (MOV, _, _) => match (dst, src) {
+            (Kind::Memory, Kind::Immediate) => (10, 1),
+            (Kind::Memory, _) => (9, 1),
+            (_, Kind::Memory) => (8, 1),
+            (_, Kind::Immediate) => (4, 0),
+            _ => (2, 0),
+        },
+        (ADD | ADC | SUB | SBB | AND | OR | XOR, _, _) => match (dst, src) {
+            (Kind::Memory, Kind::Immediate) => (17, 2),
+            (Kind::Memory, _) => (16, 2),
+            (_, Kind::Memory) => (9, 1),
+            (_, Kind::Immediate) => (4, 0),
+            _ => (3, 0),
+        },
+        (CMP, _, _) => match (dst, src) {
+            (Kind::Memory, Kind::Immediate) => (10, 1),
+            (Kind::Memory, _) | (_, Kind::Memory) => (9, 1),
+            (_, Kind::Immediate) => (4, 0),
+            _ => (3, 0),
+        },
+        (TEST, _, _) => match (dst, src) {
+            (Kind::Memory, Kind::Immediate) => (11, 1),
+            (Kind::Memory, _) | (_, Kind::Memory) => (9, 1),
+            (Kind::Accumulator, Kind::Immediate) => (4, 0),
+            (_, Kind::Immediate) => (5, 0),
+            _ => (3, 0),
+        },
+        (INC | DEC, Kind::Memory, _) => (15, 2),
+        (INC | DEC, _, _) if execution.size == Size::WORD => (2, 0),
+        (INC | DEC, _, _) => (3, 0),
+        (NEG | NOT, Kind::Memory, _) => (16, 2),
+        (NEG | NOT, _, _) => (3, 0),
+        (SHL | SHR | SAR | ROL | ROR | RCL | RCR, _, _) => shift_clocks(execution, dst),
+        (MUL | IMUL | DIV | IDIV, _, _) => multiply_clocks(execution, dst),
+        (AAA | AAS | DAA | DAS, _, _) => (4, 0),
+        (AAM, _, _) => (83, 0),
+        (AAD, _, _) => (60, 0),
+        (JNZ | JE | JL | JLE | JB | JBE | JP | JO | JS | JNL | JG | JNB | JA | JNP | JNO, _, _)
+        | (JNS, _, _) => branch(taken, 16, 4),
+        (LOOP, _, _) => branch(taken, 17, 5),
+        (LOOPZ | JCXZ, _, _) => branch(taken, 18, 6),
+        (LOOPNZ, _, _) => branch(taken, 19, 5),
+        (CLC | STC | CMC | CLD | STD | CLI | STI, _, _) => (2, 0),
+        (MOVS | CMPS | SCAS | LODS | STOS, _, _) => string_clocks(execution),
+        (PUSH, Kind::Memory, _) => (16, 2),
+        (PUSH, Kind::Segment, _) => (10, 1),
+        (PUSH, _, _) => (11, 1),
+        (POP, Kind::Memory, _) => (17, 2),
+        (POP, _, _) => (8, 1),
+        (PUSHF, _, _) => (10, 1),
+        (POPF, _, _) => (8, 1),
+        (CALL, Kind::Memory, _) => (21, 2),
+        (CALL, Kind::Register | Kind::Accumulator, _) => (16, 1),
+        (CALL, _, _) => (19, 1),
+        (CALLF, Kind::Memory, _) => (37, 4),
+        (CALLF, _, _) => (28, 2),
+        (JMP, Kind::Memory, _) => (18, 1),
+        (JMP, Kind::Register | Kind::Accumulator, _) => (11, 0),
+        (JMP, _, _) => (15, 0),
+        (JMPF, Kind::Memory, _) => (24, 2),
+        (JMPF, _, _) => (15, 0),
+        (RET, Kind::Immediate, _) => (12, 1),
+        (RET, _, _) => (8, 1),
+        (RETF, Kind::Immediate, _) => (17, 2),
+        (RETF, _, _) => (18, 2),
+        (INT, _, _) => (51, 5),
+        (INT3, _, _) => (52, 5),
+        (INTO, _, _) if taken => (53, 5),
+        (INTO, _, _) => (4, 0),
+        (IRET, _, _) => (24, 3),
+        (XCHG, Kind::Memory, _) | (XCHG, _, Kind::Memory) => (17, 2),
+        (XCHG, Kind::Accumulator, _) | (XCHG, _, Kind::Accumulator)
+            if execution.size == Size::WORD =>
+        {
+            (3, 0)
+        }
+        (XCHG, _, _) => (4, 0),
+        (IN, _, Kind::Immediate) | (OUT, Kind::Immediate, _) => (10, 1),
+        (IN | OUT, _, _) => (8, 1),
+        (XLAT, _, _) => (11, 1),
+        (LEA, _, _) => (2, 0),
+        (LDS | LES, _, _) => (16, 2),
+        (LAHF | SAHF, _, _) => (4, 0),
+        (CBW, _, _) => (2, 0),
+        (CWD, _, _) => (5, 0),
+        (HLT, _, _) => (2, 0),
+        (WAIT | NOP, _, _) => (3, 0),
+    }
+}
+
+/// Timing depends on the operands, the lowest clocks of the manual's range are used
+fn multiply_clocks(execution: &Execution, dst: Kind) -> (u32, u32) {
+    let (byte, word) = match execution.operation {
+        MUL => (70, 118),
+        IMUL => (80, 128),
+        DIV => (80, 144),
+        _ => (101, 165),
+    };
+
+    let base = match execution.size {
+        Size::BYTE => byte,
+        Size::WORD => word,
+    };
+
+    match dst {
+        Kind::Memory => (base + 6, 1),
+        _ => (base, 0),
+    }
+}
+
+/// Shifts by CL take four clocks per bit
+fn shift_clocks(execution: &Execution, dst: Kind) -> (u32, u32) {
+    let (base, transfers) = match (dst, execution.repetitions) {
+        (Kind::Memory, None) => (15, 2),
+        (Kind::Memory, Some(_)) => (20, 2),
+        (_, None) => (2, 0),
+        (_, Some(_)) => (8, 0),
+    };
+
+    (base + 4 * execution.repetitions.unwrap_or(0), transfers)
+}
+
+/// Repeated string instructions pay the prefix once and then per repetition
+fn string_clocks(execution: &Execution) -> (u32, u32) {
+    let (single, repeated, transfers) = match execution.operation {
+        MOVS => (18, 17, 2),
+        CMPS => (22, 22, 2),
+        SCAS => (15, 15, 1),
+        LODS => (12, 13, 1),
+        STOS => (11, 10, 1),
+        _ => panic!("Not a string instruction"),
+    };
+
+    match execution.repetitions {
+        None => (single, transfers),
+        Some(n) => (9 + repeated * n, transfers * n),
+    }
+}
+
+fn branch(taken: bool, clocks_taken: u32, clocks_not_taken: u32) -> (u32, u32) {
+    if taken {
+        (clocks_taken, 0)
+    } else {
+        (clocks_not_taken, 0)
+    }
+}
+
+/// Accumulator has its own MOV encoding with direct address and no address calculation
+fn is_accumulator_direct(execution: &Execution) -> bool {
+    let direct =
+        |operand: &CpuOperand| matches!(operand, CpuOperand::Memory(Access::Direct(_), _, _));
+
+    match (
+        Kind::new(&execution.destination),
+        Kind::new(&execution.source),
+    ) {
+        (Kind::Accumulator, _) => direct(&execution.source),
+        (_, Kind::Accumulator) => direct(&execution.destination),
+        _ => false,
+    }
+}
+
+/// 8086 pays four clocks for word transfer at odd address, 8088 for every word transfer
+pub fn estimate(processor: Processor, execution: &Execution) -> Clocks {
+    let (base, transfers) = base_clocks(execution);
+
+    let ea = if execution.operation == MOV && is_accumulator_direct(execution) {
+        0
+    } else {
+        ea_clocks(&execution.destination) + ea_clocks(&execution.source)
+    };
+
+    // Stack is always accessed by words
+    let size = match execution.operation.uses_stack() {
+        true => Size::WORD,
+        false => execution.size,
+    };
+
+    let penalized = match (size, processor) {
+        (Size::BYTE, _) => false,
+        (Size::WORD, Processor::I8086) => execution.odd_address,
+        (Size::WORD, Processor::I8088) => true,
+    };
+
+    Clocks {
+        base,
+        ea,
+        penalty: if penalized { 4 * transfers } else { 0 },
+    }
+}
+
+/// Running count of clocks printed with every executed instruction
+#[derive(Debug, Clone)]
+pub struct ClockEstimator {
+    pub processor: Processor,
+    total: u64,
+    last: Clocks,
+}
+
+impl ClockEstimator {
+    pub fn new(processor: Processor) -> Self {
+        ClockEstimator {
+            processor,
+            total: 0,
+            last: Clocks::default(),
+        }
+    }
+
+    /// Continues counting from the clocks counted before
+    pub fn resume(processor: Processor, count: ClockCount) -> Self {
+        ClockEstimator {
+            processor,
+            total: count.total,
+            last: count.last,
+        }
+    }
+
+    pub fn add(&mut self, clocks: Clocks) {
+        self.total += clocks.total() as u64;
+        self.last = clocks;
+    }
+
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    pub fn count(&self) -> ClockCount {
+        ClockCount {
+            last: self.last,
+            total: self.total,
+        }
+    }
+}
+
+impl fmt::Display for ClockEstimator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.count())
+    }
+}
+
+/// Clocks of the last executed instruction and of all instructions so far
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClockCount {
+    pub last: Clocks,
+    pub total: u64,
+}
+
+impl fmt::Display for ClockCount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Clocks { base, ea, penalty } = self.last;
+
+        write!(f, "Clocks: +{} = {}", self.last.total(), self.total)?;
+
+        if ea == 0 && penalty == 0 {
+            return Ok(());
+        }
+
+        write!(f, " ({}", base)?;
+
+        if ea != 0 {
+            write!(f, " + {}ea", ea)?;
+        }
+
+        if penalty != 0 {
+            write!(f, " + {}p", penalty)?;
+        }
+
+        write!(f, ")")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn execution(operation: Operation, destination: CpuOperand, source: CpuOperand) -> Execution {
+        Execution {
+            operation,
+            destination,
+            source,
+            size: Size::WORD,
+            jump_taken: false,
+            repetitions: None,
+            odd_address: false,
+        }
+    }
+
+    fn memory(address: EffectiveAddress, size: Size) -> CpuOperand {
+        CpuOperand::Memory(Access::Address(address), None, size)
+    }
+
+    #[test]
+    fn test_effective_address_clocks() {
+        assert_eq!(EffectiveAddress::Bx(0).clocks(), 5);
+        assert_eq!(EffectiveAddress::Bp(0).clocks(), 9);
+        assert_eq!(EffectiveAddress::Si(4).clocks(), 9);
+        assert_eq!(EffectiveAddress::BxSi(0).clocks(), 7);
+        assert_eq!(EffectiveAddress::BpSi(0).clocks(), 8);
+        assert_eq!(EffectiveAddress::BpDi(2).clocks(), 11);
+        assert_eq!(EffectiveAddress::BxDi(2).clocks(), 12);
+    }
+
+    #[test]
+    fn test_estimate() {
+        let bx = CpuOperand::register(0b011, Size::WORD).unwrap();
+        let word = memory(EffectiveAddress::Bp(0), Size::WORD);
+
+        let add = execution(ADD, bx, word);
+        assert_eq!(
+            estimate(Processor::I8086, &add),
+            Clocks {
+                base: 9,
+                ea: 9,
+                penalty: 0
+            }
+        );
+        assert_eq!(
+            estimate(Processor::I8088, &add),
+            Clocks {
+                base: 9,
+                ea: 9,
+                penalty: 4
+            }
+        );
+
+        let add = Execution {
+            odd_address: true,
+            ..execution(ADD, word, bx)
+        };
+        assert_eq!(
+            estimate(Processor::I8086, &add),
+            Clocks {
+                base: 16,
+                ea: 9,
+                penalty: 8
+            }
+        );
+
+        let direct = CpuOperand::Memory(Access::Direct(1000), Some(Reg::Es), Size::WORD);
+        let mov = execution(MOV, CpuOperand::register(0, Size::WORD).unwrap(), direct);
+        assert_eq!(estimate(Processor::I8086, &mov).total(), 10);
+    }
+
+    #[test]
+    fn test_stack_clocks() {
+        let bx = CpuOperand::register(0b011, Size::WORD).unwrap();
+        let push = execution(PUSH, bx, CpuOperand::NotUsed);
+        assert_eq!(
+            estimate(Processor::I8088, &push),
+            Clocks {
+                base: 11,
+                ea: 0,
+                penalty: 4
+            }
+        );
+
+        let word = memory(EffectiveAddress::Bx(0), Size::WORD);
+        let call = execution(CALL, word, CpuOperand::NotUsed);
+        assert_eq!(
+            estimate(Processor::I8086, &call),
+            Clocks {
+                base: 21,
+                ea: 5,
+                penalty: 0
+            }
+        );
+
+        // Interrupt type is a byte but the stack is written by words
+        let int = Execution {
+            size: Size::BYTE,
+            ..execution(INT, CpuOperand::Immediate(33), CpuOperand::NotUsed)
+        };
+        assert_eq!(estimate(Processor::I8088, &int).total(), 51 + 5 * 4);
+    }
+
+    #[test]
+    fn test_transfer_clocks() {
+        let ax = CpuOperand::register(0b000, Size::WORD).unwrap();
+        let bx = CpuOperand::register(0b011, Size::WORD).unwrap();
+        let word = memory(EffectiveAddress::Bx(0), Size::WORD);
+
+        assert_eq!(
+            estimate(Processor::I8086, &execution(XCHG, ax, bx)).total(),
+            3
+        );
+        assert_eq!(
+            estimate(Processor::I8088, &execution(XCHG, word, bx)),
+            Clocks {
+                base: 17,
+                ea: 5,
+                penalty: 8
+            }
+        );
+        assert_eq!(
+            estimate(Processor::I8086, &execution(LEA, bx, word)).total(),
+            2 + 5
+        );
+        assert_eq!(
+            estimate(Processor::I8086, &execution(LES, bx, word)).total(),
+            16 + 5
+        );
+        assert_eq!(
+            estimate(Processor::I8088, &execution(LDS, bx, word)),
+            Clocks {
+                base: 16,
+                ea: 5,
+                penalty: 8
+            }
+        );
+
+        let dx = CpuOperand::register(0b010, Size::WORD).unwrap();
+        let port = CpuOperand::Immediate(0x40);
+        assert_eq!(
+            estimate(Processor::I8086, &execution(IN, ax, port)).total(),
+            10
+        );
+        assert_eq!(
+            estimate(Processor::I8086, &execution(OUT, dx, ax)).total(),
+            8
+        );
+
+        let none = CpuOperand::NotUsed;
+        assert_eq!(
+            estimate(Processor::I8086, &execution(XLAT, none, none)).total(),
+            11
+        );
+        assert_eq!(
+            estimate(Processor::I8086, &execution(LAHF, none, none)).total(),
+            4
+        );
+        assert_eq!(
+            estimate(Processor::I8086, &execution(SAHF, none, none)).total(),
+            4
+        );
+        assert_eq!(
+            estimate(Processor::I8086, &execution(CBW, none, none)).total(),
+            2
+        );
+        assert_eq!(
+            estimate(Processor::I8086, &execution(CWD, none, none)).total(),
+            5
+        );
+        assert_eq!(
+            estimate(Processor::I8086, &execution(HLT, none, none)).total(),
+            2
+        );
+        assert_eq!(
+            estimate(Processor::I8086, &execution(WAIT, none, none)).total(),
+            3
+        );
+        assert_eq!(
+            estimate(Processor::I8086, &execution(NOP, none, none)).total(),
+            3
+        );
+    }
+
+    #[test]
+    fn test_multiply_clocks() {
+        let bl = CpuOperand::register(0b011, Size::BYTE).unwrap();
+        let mul = Execution {
+            size: Size::BYTE,
+            ..execution(MUL, bl, CpuOperand::NotUsed)
+        };
+        assert_eq!(estimate(Processor::I8086, &mul).total(), 70);
+
+        let word = memory(EffectiveAddress::Bx(0), Size::WORD);
+        let idiv = execution(IDIV, word, CpuOperand::NotUsed);
+        assert_eq!(
+            estimate(Processor::I8088, &idiv),
+            Clocks {
+                base: 171,
+                ea: 5,
+                penalty: 4
+            }
+        );
+    }
+
+    #[test]
+    fn test_shift_clocks() {
+        let cl = CpuOperand::register(0b001, Size::BYTE).unwrap();
+        let word = memory(EffectiveAddress::Bx(0), Size::WORD);
+
+        let by_one = execution(SHL, word, CpuOperand::Immediate(1));
+        assert_eq!(estimate(Processor::I8086, &by_one).total(), 15 + 5);
+
+        let by_cl = Execution {
+            repetitions: Some(3),
+            ..execution(RCR, word, cl)
+        };
+        assert_eq!(
+            estimate(Processor::I8088, &by_cl),
+            Clocks {
+                base: 32,
+                ea: 5,
+                penalty: 8
+            }
+        );
+    }
+
+    #[test]
+    fn test_estimator_display() {
+        let mut estimator = ClockEstimator::new(Processor::I8086);
+
+        estimator.add(Clocks {
+            base: 4,
+            ea: 0,
+            penalty: 0,
+        });
+        assert_eq!(estimator.to_string(), "Clocks: +4 = 4");
+
+        estimator.add(Clocks {
+            base: 16,
+            ea: 9,
+            penalty: 4,
+        });
+        assert_eq!(estimator.to_string(), "Clocks: +29 = 33 (16 + 9ea + 4p)");
+    }
+}
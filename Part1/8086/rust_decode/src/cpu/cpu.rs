@@ -1,34 +1,98 @@
 use std::collections::HashMap;
 use std::fmt::{self, Display};
-use std::fs::File;
-use std::io::Write;
-use std::u16;
+use std::io::{self, Write};
+use std::ops::Range;
 
-use bitflags::{bitflags, parser::to_writer};
 use strum::IntoEnumIterator;
 use strum_macros::EnumIter;
 
-use crate::assembled_instruction::Operation::*;
-use crate::disassemble::{disassemble_next_instruction, DisassemblyResult};
+use super::clocks::{self, ClockEstimator, Execution, Processor};
+use super::dump::{DumpError, MemoryDump};
+use super::flags::{self, CpuFlags};
+use super::services::Services;
+use super::snapshot::Snapshot;
+use super::trace::{MemoryWrite, RegisterChange, TextTracer, TraceStep, Tracer};
+use crate::assembled_instruction::Operation::{self, *};
+use crate::disassemble::{disassemble_next_instruction, DisassemblyError, DisassemblyResult};
+use crate::instruction::instruction::{DecodingError, Instruction};
+use crate::instruction::operand::Size;
+use crate::instruction::prefix::Repeat;
+use crate::loader::Executable;
 use crate::InstructionBuffer;
-use crate::MEMORY_SIZE;
+use crate::{MEMORY_MASK, MEMORY_SIZE};
 
 #[derive(Debug, Clone, Copy)]
 pub enum CpuOperand {
-    Register(Reg),
-    Memory(Access),
+    Register(Reg, RegPart),
+    Memory(Access, Option<Reg>, Size),
     Immediate(i16),
     Jump(i16),
+    /// Segment and offset of the far call or jump
+    FarPointer(u16, u16),
     NotUsed,
 }
 
-#[derive(Debug, Clone, Copy)]
+impl CpuOperand {
+    /// Byte registers 0-3 are low and 4-7 high halves of ax, cx, dx and bx
+    pub fn register(value: u8, size: Size) -> Result<Self, DecodingError> {
+        Ok(match (size, value & 0b100) {
+            (Size::WORD, _) => Self::Register(Reg::new(value)?, RegPart::Whole),
+            (Size::BYTE, 0) => Self::Register(Reg::new(value)?, RegPart::Low),
+            (Size::BYTE, _) => Self::Register(Reg::new(value & 0b11)?, RegPart::High),
+        })
+    }
+
+    /// Immediates are already sign extended to the size of the other operand
+    pub fn size(&self) -> Size {
+        match self {
+            Self::Register(_, part) => part.size(),
+            Self::Memory(_, _, size) => *size,
+            _ => Size::WORD,
+        }
+    }
+}
+
+/// Part of the 16 bit register accessed by the instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegPart {
+    Low,
+    High,
+    Whole,
+}
+
+impl RegPart {
+    pub fn size(&self) -> Size {
+        match self {
+            Self::Whole => Size::WORD,
+            _ => Size::BYTE,
+        }
+    }
+
+    /// Byte halves are sign extended so flags can be computed as for words
+    fn extract(&self, content: i16) -> i16 {
+        match self {
+            Self::Low => content as i8 as i16,
+            Self::High => (content >> 8) as i8 as i16,
+            Self::Whole => content,
+        }
+    }
+
+    pub(crate) fn merge(&self, content: i16, value: i16) -> i16 {
+        match self {
+            Self::Low => (content & !0xFF) | (value & 0xFF),
+            Self::High => (content & 0xFF) | (value << 8),
+            Self::Whole => value,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Access {
     Address(EffectiveAddress),
-    Direct(usize),
+    Direct(u16),
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum EffectiveAddress {
     BxSi(i16),
     BxDi(i16),
@@ -41,19 +105,27 @@ pub enum EffectiveAddress {
 }
 
 impl EffectiveAddress {
-    pub fn new(value: u8, displacement: Option<i16>) -> Self {
+    pub fn new(value: u8, displacement: Option<i16>) -> Result<Self, DecodingError> {
         let displacement = displacement.unwrap_or(0);
 
         match value {
-            0 => Self::BxSi(displacement),
-            1 => Self::BxDi(displacement),
-            2 => Self::BpSi(displacement),
-            3 => Self::BpDi(displacement),
-            4 => Self::Si(displacement),
-            5 => Self::Di(displacement),
-            6 => Self::Bp(displacement),
-            7 => Self::Bx(displacement),
-            _ => panic!("Unknown effective addres value"),
+            0 => Ok(Self::BxSi(displacement)),
+            1 => Ok(Self::BxDi(displacement)),
+            2 => Ok(Self::BpSi(displacement)),
+            3 => Ok(Self::BpDi(displacement)),
+            4 => Ok(Self::Si(displacement)),
+            5 => Ok(Self::Di(displacement)),
+            6 => Ok(Self::Bp(displacement)),
+            7 => Ok(Self::Bx(displacement)),
+            _ => Err(DecodingError::UnexpectedDecodedValueError(value)),
+        }
+    }
+
+    /// Addresses based on BP are in stack segment, all other in data segment
+    pub fn default_segment(&self) -> Reg {
+        match self {
+            Self::BpSi(_) | Self::BpDi(_) | Self::Bp(_) => Reg::Ss,
+            _ => Reg::Ds,
         }
     }
 }
@@ -68,78 +140,99 @@ pub enum Reg {
     Bp,
     Si,
     Di,
+    Es,
+    Cs,
+    Ss,
+    Ds,
 }
 
 impl Reg {
-    pub fn new(value: u8) -> Self {
+    pub fn new(value: u8) -> Result<Self, DecodingError> {
         match value {
-            0 => Self::A,
-            1 => Self::C,
-            2 => Self::D,
-            3 => Self::B,
-            4 => Self::Sp,
-            5 => Self::Bp,
-            6 => Self::Si,
-            7 => Self::Di,
-            _ => panic!("Unknown register value"),
+            0 => Ok(Self::A),
+            1 => Ok(Self::C),
+            2 => Ok(Self::D),
+            3 => Ok(Self::B),
+            4 => Ok(Self::Sp),
+            5 => Ok(Self::Bp),
+            6 => Ok(Self::Si),
+            7 => Ok(Self::Di),
+            _ => Err(DecodingError::UnexpectedDecodedValueError(value)),
         }
     }
-}
 
-impl fmt::Display for Reg {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let to_write: &str;
-        match self {
-            Self::A => to_write = "ax",
-            Self::B => to_write = "bx",
-            Self::C => to_write = "cx",
-            Self::D => to_write = "dx",
-            Self::Sp => to_write = "sp",
-            Self::Bp => to_write = "bp",
-            Self::Si => to_write = "si",
-            Self::Di => to_write = "di",
+    pub fn segment(value: u8) -> Result<Self, DecodingError> {
+        match value {
+            0 => Ok(Self::Es),
+            1 => Ok(Self::Cs),
+            2 => Ok(Self::Ss),
+            3 => Ok(Self::Ds),
+            _ => Err(DecodingError::UnexpectedDecodedValueError(value)),
         }
-
-        write!(f, "{}", to_write)
-    }
-}
-
-bitflags! {
-    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-    struct CpuFlags: u8 {
-        const S = 0b001;
-        const Z = 0b010;
-        const ZERO =0b000;
     }
 }
 
-impl CpuFlags {
-    pub fn is_flag_toogled(&self, flag: CpuFlags) -> bool {
-        *self & flag == flag
-    }
-}
-
-impl Display for CpuFlags {
+impl fmt::Display for Reg {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        to_writer(self, f)
+        let to_write: &str = match self {
+            Self::A => "ax",
+            Self::B => "bx",
+            Self::C => "cx",
+            Self::D => "dx",
+            Self::Sp => "sp",
+            Self::Bp => "bp",
+            Self::Si => "si",
+            Self::Di => "di",
+            Self::Es => "es",
+            Self::Cs => "cs",
+            Self::Ss => "ss",
+            Self::Ds => "ds",
+        };
+
+        write!(f, "{}", to_write)
     }
 }
 
 struct Registers {
     regs: HashMap<Reg, i16>,
+    changes: Vec<RegisterChange>,
 }
 
 impl Registers {
-    pub fn mov(&mut self, reg: Reg, new: i16) -> () {
+    pub fn mov(&mut self, reg: Reg, new: i16) {
         let old = self.regs.insert(reg, new).unwrap_or(0);
 
-        print!("{}:{:#x}->{:#x}", reg, old, new)
+        self.trace_change(reg, old)
+    }
+
+    /// Changes are collected and traced once the instruction is finished
+    pub fn trace_change(&mut self, reg: Reg, old: i16) {
+        let new = self.content_of(reg);
+
+        self.changes.push(RegisterChange {
+            reg,
+            old: old as u16,
+            new: new as u16,
+        })
+    }
+
+    /// Changes the register without tracing, used by repeated instructions
+    pub fn set(&mut self, reg: Reg, new: i16) {
+        self.regs.insert(reg, new);
     }
 
     pub fn content_of(&self, reg: Reg) -> i16 {
         *self.regs.get(&reg).unwrap()
     }
 
+    pub fn read(&self, reg: Reg, part: RegPart) -> i16 {
+        part.extract(self.content_of(reg))
+    }
+
+    pub fn write(&mut self, reg: Reg, part: RegPart, value: i16) {
+        self.mov(reg, part.merge(self.content_of(reg), value))
+    }
+
     fn reg_to_str(&self, reg: Reg) -> String {
         format!(
             "{}: {value:#x} ({value})",
@@ -156,9 +249,7 @@ impl Display for Registers {
         for reg in Reg::iter() {
             result = writeln!(f, "      {}", self.reg_to_str(reg));
 
-            if result.is_err() {
-                return result;
-            }
+            result?;
         }
 
         result
@@ -167,242 +258,1195 @@ impl Display for Registers {
 
 struct Memory {
     mem: Vec<u8>,
+    /// Old values of the bytes written by the instruction, kept after it only while recording
+    journal: Vec<(usize, u8)>,
+    recording: bool,
 }
 
 impl Memory {
     pub fn new() -> Self {
         Memory {
-            mem: vec![0 as u8; MEMORY_SIZE],
+            mem: vec![0_u8; MEMORY_SIZE],
+            journal: Vec::new(),
+            recording: false,
         }
     }
 
+    fn write_byte(&mut self, index: usize, value: u8) {
+        self.journal.push((index, self.mem[index]));
+        self.mem[index] = value;
+    }
+
     fn value_at(&self, index: usize) -> i16 {
         let low = self.mem[index];
-        let high = self.mem[index + 1];
+        let high = self.mem[(index + 1) & MEMORY_MASK];
 
         ((high as i16) << 8) | low as i16
     }
 
-    fn save_value_at(&mut self, index: usize, value: i16) -> () {
-        self.mem[index] = value as u8;
-        self.mem[index + 1] = (value >> 8) as u8;
+    fn save_value_at(&mut self, index: usize, value: i16) {
+        self.write_byte(index, value as u8);
+        self.write_byte((index + 1) & MEMORY_MASK, (value >> 8) as u8);
+    }
+
+    fn sized_value_at(&self, index: usize, size: Size) -> i16 {
+        match size {
+            Size::BYTE => self.mem[index] as i8 as i16,
+            Size::WORD => self.value_at(index),
+        }
+    }
+
+    fn save_sized_value_at(&mut self, index: usize, size: Size, value: i16) {
+        match size {
+            Size::BYTE => self.write_byte(index, value as u8),
+            Size::WORD => self.save_value_at(index, value),
+        }
+    }
+}
+
+/// Everything but the memory, the debugger keeps it to step back
+#[derive(Debug, Clone)]
+pub struct CpuState {
+    registers: HashMap<Reg, i16>,
+    flags: CpuFlags,
+    ip: u16,
+    exit_code: Option<u8>,
+    halted: bool,
+    clocks: Option<ClockEstimator>,
+}
+
+/// Reserved bits of FLAGS are pushed as ones by 8086
+const RESERVED_FLAGS: u16 = 0xF002;
+
+/// Flags in the low byte of FLAGS, LAHF and SAHF move them through AH
+const AH_FLAGS: CpuFlags = CpuFlags::ARITHMETIC.difference(CpuFlags::O);
+
+/// Longest instruction of 8086 is 6 bytes, the rest is left for prefixes
+const FETCH_SIZE: usize = 16;
+
+/// Segment is shifted by 4 bits and added to the offset, result is 20 bit address
+pub(crate) fn physical_address(segment: u16, offset: u16) -> usize {
+    (((segment as usize) << 4) + offset as usize) & MEMORY_MASK
+}
+
+/// Why the execution stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// Program terminated itself with the exit code
+    Exited(u8),
+    Halted,
+    /// CS:IP reached the end of the loaded program, raw programs end this way
+    ProgramEnd,
+    /// CS:IP points at the physical address outside of the program and its PSP
+    LeftProgram(usize),
+}
+
+impl Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Exited(code) => write!(f, "program exited with code {}", code),
+            Self::Halted => write!(f, "program halted"),
+            Self::ProgramEnd => write!(f, "program finished"),
+            Self::LeftProgram(address) => {
+                write!(f, "execution left the program at {:05x}", address)
+            }
+        }
     }
 }
 
-pub struct CPU {
+/// Code and data share the memory, execution ends once CS:IP leaves the loaded program
+pub struct CPU<'a> {
     registers: Registers,
     flags: CpuFlags,
-    buffer: InstructionBuffer,
+    ip: u16,
+    /// Physical addresses of the loaded program
+    program: Range<usize>,
+    /// Physical addresses CS:IP may execute, the PSP of DOS programs is part of them
+    code: Range<usize>,
+    /// Offset of the start of the program in the code segment it was started in
+    origin: u16,
+    /// Set once the program terminates itself
+    exit_code: Option<u8>,
+    /// Set by HLT, there are no hardware interrupts to resume the execution
+    halted: bool,
     memory: Memory,
+    clocks: Option<ClockEstimator>,
+    tracer: Box<dyn Tracer + 'a>,
+    services: Option<Box<dyn Services + 'a>>,
 }
 
-impl CPU {
-    pub fn new(buffer: InstructionBuffer) -> Self {
+impl<'a> CPU<'a> {
+    /// Program in the buffer is loaded as raw code at address 0
+    pub fn new(buffer: InstructionBuffer<'_>) -> Self {
+        Self::load(&Executable::raw(buffer.program()))
+    }
+
+    pub fn load(executable: &Executable) -> Self {
         let regs = HashMap::from([
             (Reg::A, 0),
             (Reg::B, 0),
             (Reg::C, 0),
             (Reg::D, 0),
-            (Reg::Sp, 0),
+            (Reg::Sp, executable.sp as i16),
             (Reg::Bp, 0),
             (Reg::Si, 0),
             (Reg::Di, 0),
+            (Reg::Es, executable.ds as i16),
+            (Reg::Cs, executable.cs as i16),
+            (Reg::Ss, executable.ss as i16),
+            (Reg::Ds, executable.ds as i16),
         ]);
 
+        let mut memory = Memory::new();
+        let start = executable.load_address;
+        let program = start..start + executable.image.len();
+
+        memory.mem[program.clone()].copy_from_slice(&executable.image);
+
+        // PSP is right before the image, RET of .COM programs runs its INT 20h
+        let mut code = program.clone();
+        if let Some(psp) = &executable.psp {
+            let psp_address = (executable.ds as usize) << 4;
+            memory.mem[psp_address..psp_address + psp.len()].copy_from_slice(psp);
+            code.start = psp_address;
+        }
+
         CPU {
-            registers: Registers { regs },
-            flags: CpuFlags::ZERO,
-            buffer,
-            memory: Memory {
-                mem: vec![0 as u8; MEMORY_SIZE],
+            registers: Registers {
+                regs,
+                changes: Vec::new(),
             },
+            flags: CpuFlags::empty(),
+            ip: executable.ip,
+            program,
+            code,
+            origin: executable.origin(),
+            exit_code: None,
+            halted: false,
+            memory,
+            clocks: None,
+            tracer: Box::new(TextTracer::new(io::stdout())),
+            services: None,
         }
     }
 
+    pub fn estimate_clocks(&mut self, processor: Processor) {
+        self.clocks = Some(ClockEstimator::new(processor));
+    }
+
     pub fn execute_instructions(&mut self) -> DisassemblyResult<()> {
-        while !self.buffer.is_at_the_end() {
+        while !self.is_finished() {
             self.execute_next_instruction()?;
         }
 
         Ok(())
     }
 
-    fn execute_next_instruction(&mut self) -> DisassemblyResult<()> {
-        let old_ip = self.buffer.last_read;
+    pub fn is_finished(&self) -> bool {
+        self.stop_reason().is_some()
+    }
+
+    /// Reason the execution cannot continue, None while it can
+    pub fn stop_reason(&self) -> Option<StopReason> {
+        let address = self.code_address(self.ip);
+
+        if let Some(code) = self.exit_code {
+            Some(StopReason::Exited(code))
+        } else if self.halted {
+            Some(StopReason::Halted)
+        } else if address == self.program.end {
+            Some(StopReason::ProgramEnd)
+        } else if !self.code.contains(&address) {
+            Some(StopReason::LeftProgram(address))
+        } else {
+            None
+        }
+    }
+
+    /// Stops the execution after the current instruction
+    pub fn terminate(&mut self, exit_code: u8) {
+        self.exit_code = Some(exit_code)
+    }
+
+    /// Code the program terminated itself with
+    pub fn exit_code(&self) -> Option<u8> {
+        self.exit_code
+    }
+
+    /// Whether the execution stopped at HLT
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    pub fn ip(&self) -> usize {
+        self.ip as usize
+    }
+
+    pub fn register(&self, reg: Reg) -> i16 {
+        self.registers.content_of(reg)
+    }
+
+    pub fn set_register(&mut self, reg: Reg, value: i16) {
+        self.registers.set(reg, value)
+    }
+
+    pub fn set_ip(&mut self, ip: usize) {
+        self.ip = ip as u16
+    }
+
+    pub fn flags(&self) -> CpuFlags {
+        self.flags
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.memory.mem
+    }
+
+    pub fn memory_mut(&mut self) -> &mut [u8] {
+        &mut self.memory.mem
+    }
+
+    /// Bytes are written from the physical address as by an instruction, so they are traced
+    pub fn write_memory(&mut self, address: usize, bytes: &[u8]) {
+        for (n, byte) in bytes.iter().enumerate() {
+            self.memory.write_byte((address + n) & MEMORY_MASK, *byte);
+        }
+    }
+
+    /// Total clocks when estimation is turned on
+    pub fn clocks(&self) -> Option<u64> {
+        self.clocks.as_ref().map(ClockEstimator::total)
+    }
+
+    /// Bytes of the loaded program as they are in the memory now
+    pub fn program(&self) -> &[u8] {
+        &self.memory.mem[self.program.clone()]
+    }
+
+    /// IP of the first byte of the program in the code segment the program started in
+    pub fn program_origin(&self) -> usize {
+        self.origin as usize
+    }
+
+    /// Instruction at IP decoded without executing it
+    pub fn next_instruction(&self) -> DisassemblyResult<Instruction> {
+        self.fetch()
+    }
+
+    /// Physical address of the offset in the code segment
+    fn code_address(&self, offset: u16) -> usize {
+        physical_address(self.registers.content_of(Reg::Cs) as u16, offset)
+    }
 
-        let instr = disassemble_next_instruction(&mut self.buffer)?;
+    /// Instruction at CS:IP, bytes past the end of the program are not fetched
+    fn fetch(&self) -> DisassemblyResult<Instruction> {
+        let start = self.code_address(self.ip);
+        let end = (start + FETCH_SIZE).min(self.code.end).max(start);
+        let mut buffer = InstructionBuffer::from_bytes(&self.memory.mem[start..end]);
 
-        let (dst, src) = match instr.operands_sorted() {
-            (dst, src) => (dst.parse_for_cpu(), src.parse_for_cpu()),
+        let mut instruction = disassemble_next_instruction(&mut buffer).map_err(|mut e| {
+            e.offset += self.ip as usize;
+            e
+        })?;
+
+        instruction.set_position(self.ip as usize, buffer.position());
+        Ok(instruction)
+    }
+
+    pub fn state(&self) -> CpuState {
+        CpuState {
+            registers: self.registers.regs.clone(),
+            flags: self.flags,
+            ip: self.ip,
+            exit_code: self.exit_code,
+            halted: self.halted,
+            clocks: self.clocks.clone(),
+        }
+    }
+
+    pub fn restore(&mut self, state: CpuState) {
+        self.registers.regs = state.registers;
+        self.flags = state.flags;
+        self.ip = state.ip;
+        self.exit_code = state.exit_code;
+        self.halted = state.halted;
+        self.clocks = state.clocks;
+    }
+
+    /// Copy of the whole machine that can be saved and restored later
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            registers: self.registers.regs.clone(),
+            flags: self.flags,
+            ip: self.ip,
+            program: self.program.clone(),
+            code: self.code.clone(),
+            origin: self.origin,
+            exit_code: self.exit_code,
+            halted: self.halted,
+            clocks: self
+                .clocks
+                .as_ref()
+                .map(|clocks| (clocks.processor, clocks.count())),
+            memory: self.memory.mem.clone(),
+        }
+    }
+
+    /// Execution continues from the snapshot, the tracer and the services are kept
+    pub fn restore_snapshot(&mut self, snapshot: Snapshot) {
+        self.registers.regs = snapshot.registers;
+        self.flags = snapshot.flags;
+        self.ip = snapshot.ip;
+        self.program = snapshot.program;
+        self.code = snapshot.code;
+        self.origin = snapshot.origin;
+        self.exit_code = snapshot.exit_code;
+        self.halted = snapshot.halted;
+        self.clocks = snapshot
+            .clocks
+            .map(|(processor, count)| ClockEstimator::resume(processor, count));
+        self.memory.mem = snapshot.memory;
+        self.memory.mem.resize(MEMORY_SIZE, 0);
+        self.memory.journal.clear();
+    }
+
+    /// Old values of the bytes written by the executed instructions are kept until taken
+    pub fn record_memory_writes(&mut self) {
+        self.memory.recording = true;
+    }
+
+    pub fn take_memory_writes(&mut self) -> Vec<(usize, u8)> {
+        std::mem::take(&mut self.memory.journal)
+    }
+
+    /// Text trace on the standard output is used unless replaced
+    pub fn set_tracer(&mut self, tracer: Box<dyn Tracer + 'a>) {
+        self.tracer = tracer;
+    }
+
+    pub fn finish_trace(&mut self) -> io::Result<()> {
+        self.tracer.finish()
+    }
+
+    /// Software interrupts are handled by the services before their vectors are used
+    pub fn set_services(&mut self, services: Box<dyn Services + 'a>) {
+        self.services = Some(services);
+    }
+
+    /// Recorded writes are undone from the last one
+    pub fn undo_memory_writes(&mut self, writes: &[(usize, u8)]) {
+        for &(index, old) in writes.iter().rev() {
+            self.memory.mem[index] = old;
+        }
+    }
+
+    pub fn execute_next_instruction(&mut self) -> DisassemblyResult<()> {
+        let old_ip = self.ip;
+        let old_flags = self.flags;
+
+        let instr = self.fetch()?;
+        let start = self.code_address(old_ip);
+        let bytes = self.memory.mem[start..start + instr.length()].to_vec();
+
+        let (dst, src, segment) = match Self::cpu_operands(&instr) {
+            Ok(operands) => operands,
+            Err(e) => {
+                return Err(DisassemblyError {
+                    kind: e.into(),
+                    offset: old_ip as usize,
+                    bytes,
+                    instruction: Some(Box::new(instr)),
+                })
+            }
         };
 
-        print!(
-            "\n{} ; ip:{:#x}->{:#x} ",
-            instr, old_ip, self.buffer.last_read
-        );
+        self.ip = old_ip.wrapping_add(instr.length() as u16);
+        let next_ip = self.ip;
+        let count = self.registers.content_of(Reg::C);
+        let first_write = self.memory.journal.len();
+        let odd_address = self.odd_address(instr.operation(), dst, src);
 
         match instr.operation() {
-            MOV => Ok(self.execute_mov(dst, src)),
-            ADD => Ok(self.execute_add(dst, src)),
-            SUB => Ok(self.execute_sub(dst, src)),
-            CMP => Ok(self.execute_cmp(dst, src)),
-            JNZ => Ok(self.execute_jnz(src)),
-            _ => todo!(),
+            MOV => self.execute_mov(dst, src),
+            ADD | ADC | SUB | SBB | CMP | INC | DEC | NEG | AND | OR | XOR | TEST | NOT | SHL
+            | SHR | SAR | ROL | ROR | RCL | RCR => self.execute_alu(instr.operation(), dst, src),
+            MUL | IMUL => self.execute_multiply(instr.operation(), dst),
+            DIV | IDIV => self.execute_divide(instr.operation(), dst),
+            AAA | AAS | DAA | DAS | AAM | AAD => {
+                self.execute_decimal_adjust(instr.operation(), dst)
+            }
+            JNZ | JE | JL | JLE | JB | JBE | JP | JO | JS | JNL | JG | JNB | JA | JNP | JNO
+            | JNS => self.execute_conditional_jump(instr.operation(), dst),
+            LOOP | LOOPZ | LOOPNZ | JCXZ => self.execute_loop(instr.operation(), dst),
+            CLC => self.flags.remove(CpuFlags::C),
+            STC => self.flags.insert(CpuFlags::C),
+            CMC => self.flags.toggle(CpuFlags::C),
+            CLD => self.flags.remove(CpuFlags::D),
+            STD => self.flags.insert(CpuFlags::D),
+            CLI => self.flags.remove(CpuFlags::I),
+            STI => self.flags.insert(CpuFlags::I),
+            MOVS | CMPS | SCAS | LODS | STOS => {
+                let repeat = instr.prefixes().repeat;
+                self.execute_string(instr.operation(), instr.size(), repeat, segment)
+            }
+            PUSH | POP | PUSHF | POPF => self.execute_stack(instr.operation(), dst),
+            CALL | CALLF | JMP | JMPF | RET | RETF => self.execute_transfer(instr.operation(), dst),
+            INT | INT3 | INTO | IRET => self.execute_interrupt(instr.operation(), dst),
+            XCHG => self.execute_exchange(dst, src),
+            LEA | LDS | LES => self.execute_load_address(instr.operation(), dst, src),
+            CBW | CWD => self.execute_convert(instr.operation()),
+            LAHF => {
+                let flags = (self.flags & AH_FLAGS).bits() | RESERVED_FLAGS;
+                self.registers.write(Reg::A, RegPart::High, flags as i16)
+            }
+            SAHF => {
+                let ah = self.registers.read(Reg::A, RegPart::High) as u8;
+                self.set_flags(CpuFlags::from_bits_truncate(ah as u16), AH_FLAGS)
+            }
+            XLAT => self.execute_translate(segment),
+            IN | OUT => self.execute_port(instr.operation(), dst),
+            HLT => self.halted = true,
+            // There is no coprocessor to wait for
+            NOP | WAIT => (),
         }
-    }
 
-    pub fn dump_memory(&self) -> std::io::Result<()> {
-        let mut file = File::create("memory_dump.data")?;
-        file.write_all(&self.memory.mem)?;
+        let repetitions = match instr.prefixes().repeat {
+            Some(_) if instr.operation().is_string() => {
+                Some(count.wrapping_sub(self.registers.content_of(Reg::C)) as u16 as u32)
+            }
+            _ if instr.operation().is_shift() && matches!(src, CpuOperand::Register(..)) => {
+                Some(count as u8 as u32)
+            }
+            _ => None,
+        };
+
+        if let Some(estimator) = self.clocks.as_mut() {
+            let execution = Execution {
+                operation: instr.operation(),
+                destination: dst,
+                source: src,
+                size: instr.size(),
+                jump_taken: self.ip != next_ip,
+                repetitions,
+                odd_address,
+            };
+
+            estimator.add(clocks::estimate(estimator.processor, &execution));
+        }
+
+        let memory = self.memory.journal[first_write..]
+            .iter()
+            .map(|&(address, old)| MemoryWrite {
+                address,
+                old,
+                new: self.memory.mem[address],
+            })
+            .collect();
+
+        if !self.memory.recording {
+            self.memory.journal.clear();
+        }
+
+        self.tracer.trace(&TraceStep {
+            address: old_ip as usize,
+            bytes,
+            instruction: instr.to_string(),
+            mnemonic: instr.operation().to_string(),
+            registers: std::mem::take(&mut self.registers.changes),
+            old_flags,
+            flags: self.flags,
+            memory,
+            next_ip: self.ip as usize,
+            clocks: self.clocks.as_ref().map(ClockEstimator::count),
+        });
+
         Ok(())
     }
 
+    /// Destination, source and the segment override of the implicit memory operand
+    fn cpu_operands(
+        instr: &Instruction,
+    ) -> Result<(CpuOperand, CpuOperand, Option<Reg>), DecodingError> {
+        let (dst, src) = instr.operands_sorted();
+        let segment = instr.prefixes().segment.map(Reg::segment).transpose()?;
+
+        Ok((
+            dst.parse_for_cpu(instr.size())?,
+            src.parse_for_cpu(instr.size())?,
+            segment,
+        ))
+    }
+
+    /// Parity of the transferred address decides the penalty on 8086
+    fn odd_address(&self, operation: Operation, dst: CpuOperand, src: CpuOperand) -> bool {
+        // String instructions use SI unless they only access the destination
+        if operation.is_string() {
+            let index = match operation {
+                SCAS | STOS => Reg::Di,
+                _ => Reg::Si,
+            };
+
+            return self.registers.content_of(index) & 1 != 0;
+        }
+
+        if operation.uses_stack() {
+            return self.registers.content_of(Reg::Sp) & 1 != 0;
+        }
+
+        [dst, src]
+            .into_iter()
+            .find_map(|operand| match operand {
+                CpuOperand::Memory(access, segment, _) => {
+                    Some(self.access_to_index(access, segment) & 1 != 0)
+                }
+                _ => None,
+            })
+            .unwrap_or(false)
+    }
+
+    pub fn dump_memory<W: Write>(&self, dump: &MemoryDump, writer: W) -> Result<(), DumpError> {
+        dump.write(&self.memory.mem, writer)
+    }
+
     fn value(&self, source: CpuOperand) -> i16 {
         match source {
             CpuOperand::Immediate(val) => val,
-            CpuOperand::Register(reg) => self.registers.content_of(reg),
-            CpuOperand::Memory(access) => self.memory.value_at(self.access_to_index(access)),
-            _ => todo!(),
+            CpuOperand::Register(reg, part) => self.registers.read(reg, part),
+            CpuOperand::Memory(access, segment, size) => self
+                .memory
+                .sized_value_at(self.access_to_index(access, segment), size),
+            // Single operand instructions have no source
+            CpuOperand::NotUsed => 0,
+            CpuOperand::Jump(_) | CpuOperand::FarPointer(..) => {
+                unreachable!("targets of transfers are resolved by execute_transfer")
+            }
         }
     }
 
-    fn access_to_index(&self, access: Access) -> usize {
+    fn access_to_index(&self, access: Access, segment_override: Option<Reg>) -> usize {
+        let default_segment = match access {
+            Access::Direct(_) => Reg::Ds,
+            Access::Address(addr) => addr.default_segment(),
+        };
+        let offset = self.access_to_offset(access);
+
+        let segment = self
+            .registers
+            .content_of(segment_override.unwrap_or(default_segment));
+
+        physical_address(segment as u16, offset)
+    }
+
+    /// Offset of the memory operand in its segment
+    fn access_to_offset(&self, access: Access) -> u16 {
         match access {
-            Access::Direct(index) => index,
-            Access::Address(addr) => self.address_to_index(addr),
+            Access::Direct(offset) => offset,
+            Access::Address(addr) => self.address_to_offset(addr),
         }
     }
 
-    fn address_to_index(&self, address: EffectiveAddress) -> usize {
-        let result = match address {
-            EffectiveAddress::Si(displacement) => self.registers.content_of(Reg::Si) + displacement,
-            EffectiveAddress::Di(displacement) => self.registers.content_of(Reg::Di) + displacement,
-            EffectiveAddress::Bp(displacement) => self.registers.content_of(Reg::Bp) + displacement,
-            EffectiveAddress::Bx(displacement) => self.registers.content_of(Reg::B) + displacement,
+    fn address_to_offset(&self, address: EffectiveAddress) -> u16 {
+        let reg = |reg: Reg| self.registers.content_of(reg) as u16;
+
+        let (base, displacement) = match address {
+            EffectiveAddress::Si(displacement) => (reg(Reg::Si), displacement),
+            EffectiveAddress::Di(displacement) => (reg(Reg::Di), displacement),
+            EffectiveAddress::Bp(displacement) => (reg(Reg::Bp), displacement),
+            EffectiveAddress::Bx(displacement) => (reg(Reg::B), displacement),
             EffectiveAddress::BpSi(displacement) => {
-                self.registers.content_of(Reg::Bp)
-                    + self.registers.content_of(Reg::Si)
-                    + displacement
+                (reg(Reg::Bp).wrapping_add(reg(Reg::Si)), displacement)
             }
             EffectiveAddress::BxSi(displacement) => {
-                self.registers.content_of(Reg::B)
-                    + self.registers.content_of(Reg::Si)
-                    + displacement
+                (reg(Reg::B).wrapping_add(reg(Reg::Si)), displacement)
             }
             EffectiveAddress::BxDi(displacement) => {
-                self.registers.content_of(Reg::B)
-                    + self.registers.content_of(Reg::Di)
-                    + displacement
+                (reg(Reg::B).wrapping_add(reg(Reg::Di)), displacement)
             }
             EffectiveAddress::BpDi(displacement) => {
-                self.registers.content_of(Reg::Bp)
-                    + self.registers.content_of(Reg::Di)
-                    + displacement
+                (reg(Reg::Bp).wrapping_add(reg(Reg::Di)), displacement)
             }
         };
 
-        result.try_into().unwrap()
+        // Offset wraps around within the 64k segment
+        base.wrapping_add(displacement as u16)
     }
 
-    fn put_value_in_destination(&mut self, destination: CpuOperand, value: i16) -> () {
+    fn put_value_in_destination(&mut self, destination: CpuOperand, value: i16) {
         match destination {
-            CpuOperand::Register(reg) => self.registers.mov(reg, value),
-            CpuOperand::Memory(access) => self.save_in_mem(access, value),
-            _ => todo!(),
+            CpuOperand::Register(reg, part) => self.registers.write(reg, part, value),
+            CpuOperand::Memory(access, segment, size) => {
+                self.save_in_mem(access, segment, size, value)
+            }
+            destination => {
+                unreachable!("{:?} is never decoded as a destination", destination)
+            }
         };
     }
 
-    fn save_in_mem(&mut self, access: Access, value: i16) -> () {
+    fn save_in_mem(&mut self, access: Access, segment: Option<Reg>, size: Size, value: i16) {
         self.memory
-            .save_value_at(self.access_to_index(access), value)
+            .save_sized_value_at(self.access_to_index(access, segment), size, value)
     }
 
-    fn flip_flags(&mut self, value: i16) -> () {
-        let flags_before = self.flags.clone();
+    /// Only the flags affected by the instruction are changed
+    pub fn set_flags(&mut self, flags: CpuFlags, affected: CpuFlags) {
+        self.flags = (self.flags & !affected) | (flags & affected);
+    }
 
-        if value == 0 {
-            self.flip_flag(CpuFlags::Z)
-        } else if (value as u16) & 0x8000 != 0 {
-            self.flip_flag(CpuFlags::S)
-        }
+    fn execute_mov(&mut self, destination: CpuOperand, source: CpuOperand) {
+        self.put_value_in_destination(destination, self.value(source))
+    }
+
+    /// Arithmetic, logic, shift and rotate instructions, CMP and TEST only change flags
+    fn execute_alu(&mut self, operation: Operation, destination: CpuOperand, source: CpuOperand) {
+        let (value, flags) = flags::alu(
+            operation,
+            self.value(destination),
+            self.value(source),
+            self.flags,
+            destination.size(),
+        );
 
-        if value != 0 {
-            self.unflip_flag(CpuFlags::Z)
+        if !matches!(operation, CMP | TEST) {
+            self.put_value_in_destination(destination, value);
         }
 
-        if value as u16 & 0x8000 == 0 {
-            self.unflip_flag(CpuFlags::S)
+        self.flags = flags;
+    }
+
+    /// Result twice the size of the operand is split between AH:AL or DX:AX
+    fn set_double(&mut self, size: Size, high: i16, low: i16) {
+        match size {
+            Size::BYTE => self.registers.mov(Reg::A, (high << 8) | (low & 0xFF)),
+            Size::WORD => {
+                self.registers.mov(Reg::A, low);
+                self.registers.mov(Reg::D, high);
+            }
         }
+    }
+
+    fn execute_multiply(&mut self, operation: Operation, source: CpuOperand) {
+        let size = source.size();
+        let (product, flags) = flags::multiply(
+            self.accumulator(size),
+            self.value(source),
+            operation == IMUL,
+            size,
+        );
+
+        let half = match size {
+            Size::BYTE => 8,
+            Size::WORD => 16,
+        };
+        self.set_double(size, (product >> half) as i16, product as i16);
+        self.set_flags(flags, CpuFlags::C | CpuFlags::O);
+    }
+
+    /// Division by zero or quotient too big for AL or AX raises interrupt 0
+    fn execute_divide(&mut self, operation: Operation, source: CpuOperand) {
+        let size = source.size();
+        let dividend = match size {
+            Size::BYTE => self.register(Reg::A) as u16 as u32,
+            Size::WORD => {
+                (self.register(Reg::D) as u16 as u32) << 16 | self.register(Reg::A) as u16 as u32
+            }
+        };
 
-        if flags_before != self.flags {
-            print!("   flags:{} -> {}", flags_before, self.flags)
+        match flags::divide(dividend, self.value(source), operation == IDIV, size) {
+            Some((quotient, remainder)) => self.set_double(size, remainder, quotient),
+            None => self.trace_changes(&[Reg::Sp, Reg::Cs], |cpu| cpu.interrupt(0)),
         }
     }
 
-    fn flip_flag(&mut self, flag: CpuFlags) -> () {
-        self.flags = self.flags | flag;
+    /// Corrects AL after arithmetic on packed (DAA, DAS) or unpacked (AAA, AAS, AAM, AAD)
+    /// decimal digits, AAM and AAD take the base as an immediate
+    fn execute_decimal_adjust(&mut self, operation: Operation, operand: CpuOperand) {
+        let al = self.registers.read(Reg::A, RegPart::Low) as u8;
+        let ah = self.registers.read(Reg::A, RegPart::High) as u8;
+        let auxiliary = self.flags.is_flag_toogled(CpuFlags::A) || al & 0x0F > 9;
+        let base = match operand {
+            CpuOperand::Immediate(base) => base as u8,
+            _ => 10,
+        };
+        let of_al = CpuFlags::S | CpuFlags::Z | CpuFlags::P;
+
+        let (ah, al, affected) = match operation {
+            AAA | AAS => {
+                let (ah, al) = match (operation, auxiliary) {
+                    (_, false) => (ah, al),
+                    (AAA, true) => (ah.wrapping_add(1), al.wrapping_add(6)),
+                    _ => (ah.wrapping_sub(1), al.wrapping_sub(6)),
+                };
+
+                self.flags.set(CpuFlags::A | CpuFlags::C, auxiliary);
+                (ah, al & 0x0F, CpuFlags::empty())
+            }
+            DAA | DAS => {
+                let carry = self.flags.is_flag_toogled(CpuFlags::C) || al > 0x99;
+                let adjustment = (auxiliary as u8 * 0x06) | (carry as u8 * 0x60);
+                let al = match operation {
+                    DAA => al.wrapping_add(adjustment),
+                    _ => al.wrapping_sub(adjustment),
+                };
+
+                self.flags.set(CpuFlags::A, auxiliary);
+                self.flags.set(CpuFlags::C, carry);
+                (ah, al, of_al)
+            }
+            AAM if base == 0 => {
+                return self.trace_changes(&[Reg::Sp, Reg::Cs], |cpu| cpu.interrupt(0))
+            }
+            AAM => (al / base, al % base, of_al),
+            AAD => (0, ah.wrapping_mul(base).wrapping_add(al), of_al),
+            _ => panic!("Not a decimal adjust instruction"),
+        };
+
+        self.set_double(Size::BYTE, ah as i16, al as i16);
+        self.set_flags(flags::logic(al as i16, Size::BYTE).1, affected);
     }
 
-    fn unflip_flag(&mut self, flag: CpuFlags) -> () {
-        self.flags = self.flags & !flag
+    fn execute_exchange(&mut self, destination: CpuOperand, source: CpuOperand) {
+        let (first, second) = (self.value(destination), self.value(source));
+
+        self.put_value_in_destination(destination, second);
+        self.put_value_in_destination(source, first)
     }
 
-    fn execute_mov(&mut self, destination: CpuOperand, source: CpuOperand) -> () {
-        self.execute(destination, source, |_d, s| s, true, false)
+    /// LEA loads the offset of the memory operand, LDS and LES the far pointer stored in it
+    fn execute_load_address(
+        &mut self,
+        operation: Operation,
+        destination: CpuOperand,
+        source: CpuOperand,
+    ) {
+        let CpuOperand::Memory(access, segment, _) = source else {
+            unreachable!(
+                "register operands of {} are rejected by decoding",
+                operation
+            );
+        };
+
+        let (pointer_segment, offset) = match operation {
+            LEA => (None, self.access_to_offset(access)),
+            LDS | LES => {
+                let (pointer_segment, offset) = self.far_pointer_at(access, segment);
+                (Some(pointer_segment), offset)
+            }
+            _ => panic!("Not a load address instruction"),
+        };
+
+        self.put_value_in_destination(destination, offset as i16);
+
+        if let Some(pointer_segment) = pointer_segment {
+            let reg = if operation == LDS { Reg::Ds } else { Reg::Es };
+            self.registers.mov(reg, pointer_segment as i16)
+        }
     }
 
-    fn execute_add(&mut self, destination: CpuOperand, source: CpuOperand) -> () {
-        self.execute(destination, source, |d, s| d + s, true, true)
+    /// CBW extends the sign of AL to AH, CWD the sign of AX to DX
+    fn execute_convert(&mut self, operation: Operation) {
+        match operation {
+            CBW => {
+                let al = self.registers.read(Reg::A, RegPart::Low);
+                self.registers.mov(Reg::A, al)
+            }
+            CWD => {
+                let sign = self.registers.content_of(Reg::A) >> 15;
+                self.registers.mov(Reg::D, sign)
+            }
+            _ => panic!("Not a convert instruction"),
+        }
     }
 
-    fn execute_sub(&mut self, destination: CpuOperand, source: CpuOperand) -> () {
-        self.execute(destination, source, |d, s| d - s, true, true)
+    /// AL is replaced by the byte at BX + AL of the table in DS unless overridden
+    fn execute_translate(&mut self, segment: Option<Reg>) {
+        let al = self.registers.read(Reg::A, RegPart::Low) as u8;
+        let offset = (self.registers.content_of(Reg::B) as u16).wrapping_add(al as u16);
+        let segment = self.registers.content_of(segment.unwrap_or(Reg::Ds));
+
+        let value = self
+            .memory
+            .sized_value_at(physical_address(segment as u16, offset), Size::BYTE);
+        self.registers.write(Reg::A, RegPart::Low, value)
     }
 
-    fn execute_cmp(&mut self, destination: CpuOperand, source: CpuOperand) -> () {
-        self.execute(destination, source, |d, s| d - s, false, true)
+    /// No devices are attached to the ports, reads get all ones and writes are lost
+    fn execute_port(&mut self, operation: Operation, destination: CpuOperand) {
+        if operation == IN {
+            self.put_value_in_destination(destination, -1)
+        }
     }
 
-    fn execute_jnz(&mut self, jump_operand: CpuOperand) -> () {
+    fn jump_if(&mut self, condition: bool, jump_operand: CpuOperand) {
         match jump_operand {
             CpuOperand::Jump(jmp) => {
-                if !self.flags.is_flag_toogled(CpuFlags::Z) {
-                    self.buffer.jump_by(jmp);
+                if condition {
+                    self.ip = self.ip.wrapping_add(jmp as u16);
                 }
             }
             _ => panic!("Not a jump instruction"),
         }
     }
 
-    fn execute<F>(
+    fn execute_conditional_jump(&mut self, operation: Operation, jump_operand: CpuOperand) {
+        let flag = |flag: CpuFlags| self.flags.is_flag_toogled(flag);
+        let less = flag(CpuFlags::S) != flag(CpuFlags::O);
+
+        let condition = match operation {
+            JE => flag(CpuFlags::Z),
+            JNZ => !flag(CpuFlags::Z),
+            JL => less,
+            JNL => !less,
+            JLE => less || flag(CpuFlags::Z),
+            JG => !less && !flag(CpuFlags::Z),
+            JB => flag(CpuFlags::C),
+            JNB => !flag(CpuFlags::C),
+            JBE => flag(CpuFlags::C) || flag(CpuFlags::Z),
+            JA => !flag(CpuFlags::C) && !flag(CpuFlags::Z),
+            JP => flag(CpuFlags::P),
+            JNP => !flag(CpuFlags::P),
+            JO => flag(CpuFlags::O),
+            JNO => !flag(CpuFlags::O),
+            JS => flag(CpuFlags::S),
+            JNS => !flag(CpuFlags::S),
+            _ => panic!("Not a conditional jump instruction"),
+        };
+
+        self.jump_if(condition, jump_operand)
+    }
+
+    /// LOOP family decrements CX without changing flags, JCXZ only tests it
+    fn execute_loop(&mut self, operation: Operation, jump_operand: CpuOperand) {
+        if operation == JCXZ {
+            let condition = self.registers.content_of(Reg::C) == 0;
+            return self.jump_if(condition, jump_operand);
+        }
+
+        let count = self.registers.content_of(Reg::C).wrapping_sub(1);
+        self.registers.mov(Reg::C, count);
+
+        let zero = self.flags.is_flag_toogled(CpuFlags::Z);
+        let condition = count != 0
+            && match operation {
+                LOOP => true,
+                LOOPZ => zero,
+                LOOPNZ => !zero,
+                _ => panic!("Not a loop instruction"),
+            };
+
+        self.jump_if(condition, jump_operand)
+    }
+
+    fn execute_string(
         &mut self,
-        destination: CpuOperand,
-        source: CpuOperand,
-        operation: F,
-        save_to_dest: bool,
-        check_flags: bool,
-    ) -> ()
-    where
-        F: Fn(i16, i16) -> i16,
-    {
-        let value = operation(self.value(destination), self.value(source));
-
-        if save_to_dest {
-            self.put_value_in_destination(destination, value);
+        operation: Operation,
+        size: Size,
+        repeat: Option<Repeat>,
+        segment: Option<Reg>,
+    ) {
+        let traced =
+            [Reg::A, Reg::C, Reg::Si, Reg::Di].map(|reg| (reg, self.registers.content_of(reg)));
+
+        match repeat {
+            None => self.string_step(operation, size, segment),
+            Some(repeat) => {
+                while self.registers.content_of(Reg::C) != 0 {
+                    self.string_step(operation, size, segment);
+
+                    let count = self.registers.content_of(Reg::C).wrapping_sub(1);
+                    self.registers.set(Reg::C, count);
+
+                    // Only comparisons are terminated by the zero flag
+                    let zero = self.flags.is_flag_toogled(CpuFlags::Z);
+                    if matches!(operation, CMPS | SCAS) && zero == (repeat == Repeat::Repne) {
+                        break;
+                    }
+                }
+            }
         }
 
-        if check_flags {
-            self.flip_flags(value)
+        for (reg, old) in traced {
+            if old != self.registers.content_of(reg) {
+                self.registers.trace_change(reg, old)
+            }
         }
     }
+
+    /// Source is DS:SI unless overridden, destination is always ES:DI
+    fn string_step(&mut self, operation: Operation, size: Size, segment: Option<Reg>) {
+        let source_segment = segment.unwrap_or(Reg::Ds);
+        let source = self.string_address(source_segment, Reg::Si);
+        let destination = self.string_address(Reg::Es, Reg::Di);
+
+        match operation {
+            MOVS => {
+                let value = self.memory.sized_value_at(source, size);
+                self.memory.save_sized_value_at(destination, size, value)
+            }
+            CMPS => {
+                let (_, flags) = flags::sub(
+                    self.memory.sized_value_at(source, size),
+                    self.memory.sized_value_at(destination, size),
+                    false,
+                    size,
+                );
+                self.set_flags(flags, CpuFlags::ARITHMETIC)
+            }
+            SCAS => {
+                let (_, flags) = flags::sub(
+                    self.accumulator(size),
+                    self.memory.sized_value_at(destination, size),
+                    false,
+                    size,
+                );
+                self.set_flags(flags, CpuFlags::ARITHMETIC)
+            }
+            LODS => {
+                let value = self.memory.sized_value_at(source, size);
+                self.set_accumulator(size, value)
+            }
+            STOS => {
+                let value = self.accumulator(size);
+                self.memory.save_sized_value_at(destination, size, value)
+            }
+            _ => panic!("Not a string instruction"),
+        }
+
+        let step = match (size, self.flags.is_flag_toogled(CpuFlags::D)) {
+            (Size::BYTE, false) => 1,
+            (Size::WORD, false) => 2,
+            (Size::BYTE, true) => -1,
+            (Size::WORD, true) => -2,
+        };
+
+        if matches!(operation, MOVS | CMPS | LODS) {
+            self.advance(Reg::Si, step);
+        }
+
+        if matches!(operation, MOVS | CMPS | SCAS | STOS) {
+            self.advance(Reg::Di, step);
+        }
+    }
+
+    /// Physical address of the segment register and the index register
+    fn string_address(&self, segment: Reg, index: Reg) -> usize {
+        physical_address(
+            self.registers.content_of(segment) as u16,
+            self.registers.content_of(index) as u16,
+        )
+    }
+
+    fn advance(&mut self, reg: Reg, step: i16) {
+        let value = self.registers.content_of(reg).wrapping_add(step);
+        self.registers.set(reg, value);
+    }
+
+    /// Registers changed without tracing are traced once the instruction is done
+    fn trace_changes<F: FnOnce(&mut Self)>(&mut self, regs: &[Reg], change: F) {
+        let old: Vec<(Reg, i16)> = regs
+            .iter()
+            .map(|reg| (*reg, self.registers.content_of(*reg)))
+            .collect();
+
+        change(self);
+
+        for (reg, old) in old {
+            if old != self.registers.content_of(reg) {
+                self.registers.trace_change(reg, old)
+            }
+        }
+    }
+
+    /// Stack grows down from the top of the stack segment, SP points at the last pushed word
+    fn push(&mut self, value: i16) {
+        self.advance(Reg::Sp, -2);
+
+        let top = self.string_address(Reg::Ss, Reg::Sp);
+        self.memory.save_value_at(top, value);
+    }
+
+    fn pop(&mut self) -> i16 {
+        let value = self.memory.value_at(self.string_address(Reg::Ss, Reg::Sp));
+        self.advance(Reg::Sp, 2);
+
+        value
+    }
+
+    fn execute_stack(&mut self, operation: Operation, operand: CpuOperand) {
+        self.trace_changes(&[Reg::Sp], |cpu| match operation {
+            // 8086 pushes SP already decremented
+            PUSH => match operand {
+                CpuOperand::Register(Reg::Sp, _) => cpu.push(cpu.register(Reg::Sp).wrapping_sub(2)),
+                _ => cpu.push(cpu.value(operand)),
+            },
+            POP => {
+                let value = cpu.pop();
+                cpu.put_value_in_destination(operand, value)
+            }
+            PUSHF => cpu.push((cpu.flags.bits() | RESERVED_FLAGS) as i16),
+            POPF => {
+                let value = cpu.pop();
+                cpu.flags = CpuFlags::from_bits_truncate(value as u16)
+            }
+            _ => panic!("Not a stack instruction"),
+        })
+    }
+
+    /// Segment and offset of the far pointer stored in memory, offset comes first
+    fn far_pointer_at(&self, access: Access, segment: Option<Reg>) -> (u16, u16) {
+        let index = self.access_to_index(access, segment);
+
+        (
+            self.memory.value_at((index + 2) & MEMORY_MASK) as u16,
+            self.memory.value_at(index) as u16,
+        )
+    }
+
+    /// Far transfers also load CS
+    fn transfer_to(&mut self, segment: Option<u16>, offset: u16) {
+        if let Some(segment) = segment {
+            self.registers.set(Reg::Cs, segment as i16);
+        }
+
+        self.ip = offset;
+    }
+
+    fn execute_transfer(&mut self, operation: Operation, operand: CpuOperand) {
+        self.trace_changes(&[Reg::Sp, Reg::Cs], |cpu| {
+            let next_ip = cpu.ip;
+
+            if let RET | RETF = operation {
+                let offset = cpu.pop() as u16;
+                let segment = (operation == RETF).then(|| cpu.pop() as u16);
+
+                // Return can also release the arguments of the procedure
+                if let CpuOperand::Immediate(size) = operand {
+                    cpu.advance(Reg::Sp, size);
+                }
+
+                return cpu.transfer_to(segment, offset);
+            }
+
+            let (segment, offset) = match operand {
+                CpuOperand::Jump(displacement) => (None, next_ip.wrapping_add(displacement as u16)),
+                CpuOperand::FarPointer(segment, offset) => (Some(segment), offset),
+                CpuOperand::Memory(access, segment, _) if operation.is_far() => {
+                    let (segment, offset) = cpu.far_pointer_at(access, segment);
+                    (Some(segment), offset)
+                }
+                operand => (None, cpu.value(operand) as u16),
+            };
+
+            if operation == CALLF {
+                cpu.push(cpu.register(Reg::Cs));
+            }
+
+            if let CALL | CALLF = operation {
+                cpu.push(next_ip as i16);
+            }
+
+            cpu.transfer_to(segment, offset)
+        })
+    }
+
+    /// Vector of the interrupt is the far pointer at four times its type in low memory
+    pub(crate) fn interrupt(&mut self, vector: u8) {
+        self.push((self.flags.bits() | RESERVED_FLAGS) as i16);
+        self.flags.remove(CpuFlags::I | CpuFlags::T);
+
+        self.push(self.register(Reg::Cs));
+        self.push(self.ip as i16);
+
+        let index = vector as usize * 4;
+        let offset = self.memory.value_at(index) as u16;
+        let segment = self.memory.value_at(index + 2) as u16;
+
+        self.transfer_to(Some(segment), offset)
+    }
+
+    /// Services get all registers, their changes are traced as changes made by INT
+    fn serve(&mut self, vector: u8) -> bool {
+        let Some(mut services) = self.services.take() else {
+            return false;
+        };
+
+        let mut served = false;
+        let all: Vec<Reg> = Reg::iter().collect();
+        self.trace_changes(&all, |cpu| served = services.interrupt(vector, cpu));
+
+        self.services = Some(services);
+        served
+    }
+
+    fn execute_interrupt(&mut self, operation: Operation, operand: CpuOperand) {
+        if let (INT, CpuOperand::Immediate(vector)) = (operation, operand) {
+            if self.serve(vector as u8) {
+                return;
+            }
+        }
+
+        self.trace_changes(&[Reg::Sp, Reg::Cs], |cpu| match operation {
+            INT => cpu.interrupt(cpu.value(operand) as u8),
+            INT3 => cpu.interrupt(3),
+            INTO => {
+                if cpu.flags.is_flag_toogled(CpuFlags::O) {
+                    cpu.interrupt(4)
+                }
+            }
+            IRET => {
+                let offset = cpu.pop() as u16;
+                let segment = cpu.pop() as u16;
+                cpu.flags = CpuFlags::from_bits_truncate(cpu.pop() as u16);
+
+                cpu.transfer_to(Some(segment), offset)
+            }
+            _ => panic!("Not an interrupt instruction"),
+        })
+    }
+
+    fn accumulator_part(size: Size) -> RegPart {
+        match size {
+            Size::BYTE => RegPart::Low,
+            Size::WORD => RegPart::Whole,
+        }
+    }
+
+    fn accumulator(&self, size: Size) -> i16 {
+        self.registers.read(Reg::A, Self::accumulator_part(size))
+    }
+
+    fn set_accumulator(&mut self, size: Size, value: i16) {
+        let content = self.registers.content_of(Reg::A);
+        let value = Self::accumulator_part(size).merge(content, value);
+
+        self.registers.set(Reg::A, value);
+    }
 }
 
-impl Display for CPU {
+impl Display for CPU<'_> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
             "{}      ip: {:#x} ({})\n   flags:{}",
-            self.registers, self.buffer.last_read, self.buffer.last_read, self.flags
+            self.registers, self.ip, self.ip, self.flags
         )
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_default_segment() {
+        assert_eq!(EffectiveAddress::Bp(0).default_segment(), Reg::Ss);
+        assert_eq!(EffectiveAddress::BpDi(0).default_segment(), Reg::Ss);
+        assert_eq!(EffectiveAddress::BxSi(0).default_segment(), Reg::Ds);
+    }
+
+    #[test]
+    fn test_physical_address() {
+        assert_eq!(physical_address(0x1234, 0x10), 0x12350);
+        assert_eq!(physical_address(0xFFFF, 0x20), 0x10);
+    }
+}
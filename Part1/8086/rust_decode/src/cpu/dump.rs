@@ -0,0 +1,213 @@
+use std::io::{self, Write};
+use std::ops::Range;
+use std::{error, fmt};
+
+use clap::ValueEnum;
+
+use crate::assemble::number;
+use crate::MEMORY_SIZE;
+
+const BYTES_PER_LINE: usize = 16;
+const BYTES_PER_PIXEL: usize = 4;
+
+#[derive(Debug)]
+pub enum DumpError {
+    InvalidRangeError(Range<usize>),
+    /// Length of the range is not a whole number of rows of the width
+    InvalidImageSizeError(usize, usize),
+    IoError(io::Error),
+    PngError(png::EncodingError),
+}
+
+impl fmt::Display for DumpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidRangeError(range) => {
+                write!(
+                    f,
+                    "range {:#x}..{:#x} is outside of memory",
+                    range.start, range.end
+                )
+            }
+            Self::InvalidImageSizeError(length, width) => {
+                write!(f, "{} bytes are not rows of {} RGBA pixels", length, width)
+            }
+            Self::IoError(e) => write!(f, "{}", e),
+            Self::PngError(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl error::Error for DumpError {}
+
+impl From<io::Error> for DumpError {
+    fn from(e: io::Error) -> Self {
+        Self::IoError(e)
+    }
+}
+
+impl From<png::EncodingError> for DumpError {
+    fn from(e: png::EncodingError) -> Self {
+        Self::PngError(e)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DumpFormat {
+    /// Bytes as they are in memory
+    Raw,
+    /// Hexadecimal text with the address of every line
+    Hex,
+    /// RGBA pixels written as binary PPM without alpha
+    Ppm,
+    /// RGBA pixels written as PNG
+    Png,
+}
+
+/// Part of the memory written in the format, images are `width` pixels wide
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryDump {
+    pub range: Range<usize>,
+    pub format: DumpFormat,
+    pub width: usize,
+}
+
+impl Default for MemoryDump {
+    /// Whole memory as raw bytes
+    fn default() -> Self {
+        MemoryDump {
+            range: 0..MEMORY_SIZE,
+            format: DumpFormat::Raw,
+            width: 64,
+        }
+    }
+}
+
+/// Address in any notation the assembler accepts, it cannot be negative
+pub fn parse_address(text: &str) -> Option<usize> {
+    number(text.trim())
+        .filter(|value| *value >= 0)
+        .map(|value| value as usize)
+}
+
+/// Range written as `start..end` of two addresses
+pub fn parse_range(text: &str) -> Option<Range<usize>> {
+    let (start, end) = text.split_once("..")?;
+
+    Some(parse_address(start)?..parse_address(end)?)
+}
+
+/// Lines of 16 bytes starting with their address
+pub fn hex_dump(bytes: &[u8], start: usize) -> String {
+    bytes
+        .chunks(BYTES_PER_LINE)
+        .enumerate()
+        .map(|(n, line)| {
+            let hex: Vec<String> = line.iter().map(|byte| format!("{:02x}", byte)).collect();
+            format!("{:05x}: {}", start + n * BYTES_PER_LINE, hex.join(" "))
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+impl MemoryDump {
+    pub fn write<W: Write>(&self, memory: &[u8], mut writer: W) -> Result<(), DumpError> {
+        let bytes = memory
+            .get(self.range.clone())
+            .ok_or_else(|| DumpError::InvalidRangeError(self.range.clone()))?;
+
+        match self.format {
+            DumpFormat::Raw => writer.write_all(bytes)?,
+            DumpFormat::Hex => writeln!(writer, "{}", hex_dump(bytes, self.range.start))?,
+            DumpFormat::Ppm => {
+                let height = self.height(bytes)?;
+                write!(writer, "P6\n{} {}\n255\n", self.width, height)?;
+
+                for pixel in bytes.chunks(BYTES_PER_PIXEL) {
+                    writer.write_all(&pixel[..3])?;
+                }
+            }
+            DumpFormat::Png => {
+                let height = self.height(bytes)?;
+                let mut encoder = png::Encoder::new(&mut writer, self.width as u32, height as u32);
+                encoder.set_color(png::ColorType::Rgba);
+                encoder.set_depth(png::BitDepth::Eight);
+
+                let mut png = encoder.write_header()?;
+                png.write_image_data(bytes)?;
+                png.finish()?;
+            }
+        }
+
+        Ok(writer.flush()?)
+    }
+
+    /// Rows of the image, the range has to be made of whole rows
+    fn height(&self, bytes: &[u8]) -> Result<usize, DumpError> {
+        let row = self.width * BYTES_PER_PIXEL;
+
+        match row != 0 && !bytes.is_empty() && bytes.len().is_multiple_of(row) {
+            true => Ok(bytes.len() / row),
+            false => Err(DumpError::InvalidImageSizeError(bytes.len(), self.width)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn dump(range: Range<usize>, format: DumpFormat, width: usize) -> Result<Vec<u8>, DumpError> {
+        let memory: Vec<u8> = (0..64).collect();
+        let mut output = Vec::new();
+
+        MemoryDump {
+            range,
+            format,
+            width,
+        }
+        .write(&memory, &mut output)?;
+
+        Ok(output)
+    }
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("256..0x4100"), Some(256..0x4100));
+        assert_eq!(parse_range("0FFh .. 100h"), Some(0xFF..0x100));
+        assert_eq!(parse_range("-1..5"), None);
+        assert_eq!(parse_range("256"), None);
+        assert_eq!(parse_address(" 1A2h"), Some(0x1A2));
+    }
+
+    #[test]
+    fn test_hex_dump() {
+        let text = String::from_utf8(dump(14..20, DumpFormat::Hex, 0).unwrap()).unwrap();
+
+        assert_eq!(text, "0000e: 0e 0f 10 11 12 13\n");
+    }
+
+    #[test]
+    fn test_ppm_dump() {
+        let ppm = dump(8..24, DumpFormat::Ppm, 2).unwrap();
+
+        assert_eq!(ppm[..11], *b"P6\n2 2\n255\n");
+        assert_eq!(ppm[11..], [8, 9, 10, 12, 13, 14, 16, 17, 18, 20, 21, 22]);
+    }
+
+    #[test]
+    fn test_invalid_dumps() {
+        assert!(matches!(
+            dump(8..20, DumpFormat::Png, 2),
+            Err(DumpError::InvalidImageSizeError(12, 2))
+        ));
+        assert!(matches!(
+            dump(8..24, DumpFormat::Ppm, 0),
+            Err(DumpError::InvalidImageSizeError(16, 0))
+        ));
+        assert!(matches!(
+            dump(60..70, DumpFormat::Raw, 0),
+            Err(DumpError::InvalidRangeError(_))
+        ));
+    }
+}
@@ -0,0 +1,410 @@
+use std::fmt;
+
+use bitflags::bitflags;
+
+use crate::assembled_instruction::Operation::{self, *};
+use crate::instruction::operand::Size;
+
+bitflags! {
+    /// Layout of the 8086 FLAGS register
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+    pub struct CpuFlags: u16 {
+        const C = 1 << 0;
+        const P = 1 << 2;
+        const A = 1 << 4;
+        const Z = 1 << 6;
+        const S = 1 << 7;
+        const T = 1 << 8;
+        const I = 1 << 9;
+        const D = 1 << 10;
+        const O = 1 << 11;
+
+        /// Flags written by arithmetic instructions
+        const ARITHMETIC = Self::C.bits()
+            | Self::P.bits()
+            | Self::A.bits()
+            | Self::Z.bits()
+            | Self::S.bits()
+            | Self::O.bits();
+    }
+}
+
+impl CpuFlags {
+    pub fn is_flag_toogled(&self, flag: CpuFlags) -> bool {
+        *self & flag == flag
+    }
+
+    /// Zero, sign and parity flags of the result, parity counts only the low byte
+    fn of_result(result: u32, size: Size) -> Self {
+        let mut flags = CpuFlags::empty();
+
+        flags.set(CpuFlags::Z, result & mask(size) == 0);
+        flags.set(CpuFlags::S, result & sign_bit(size) != 0);
+        flags.set(CpuFlags::P, (result as u8).count_ones().is_multiple_of(2));
+
+        flags
+    }
+}
+
+/// Flags are printed the same way as the reference simulator does
+impl fmt::Display for CpuFlags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, _) in self.iter_names() {
+            write!(f, "{}", name)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn mask(size: Size) -> u32 {
+    match size {
+        Size::BYTE => 0xFF,
+        Size::WORD => 0xFFFF,
+    }
+}
+
+fn sign_bit(size: Size) -> u32 {
+    match size {
+        Size::BYTE => 0x80,
+        Size::WORD => 0x8000,
+    }
+}
+
+/// Operand zero extended to the size of the operation
+fn unsigned(value: i16, size: Size) -> u32 {
+    value as u16 as u32 & mask(size)
+}
+
+/// Result truncated to the size of the operation and sign extended back
+fn signed(result: u32, size: Size) -> i16 {
+    match size {
+        Size::BYTE => result as u8 as i8 as i16,
+        Size::WORD => result as u16 as i16,
+    }
+}
+
+/// Addition with carry in, used by ADD, ADC and INC
+pub fn add(a: i16, b: i16, carry: bool, size: Size) -> (i16, CpuFlags) {
+    let (a, b) = (unsigned(a, size), unsigned(b, size));
+    let result = a + b + carry as u32;
+
+    let mut flags = CpuFlags::of_result(result, size);
+    flags.set(CpuFlags::C, result > mask(size));
+    flags.set(CpuFlags::A, (a ^ b ^ result) & 0x10 != 0);
+    flags.set(
+        CpuFlags::O,
+        (a ^ result) & (b ^ result) & sign_bit(size) != 0,
+    );
+
+    (signed(result, size), flags)
+}
+
+/// Subtraction with borrow in, used by SUB, SBB, CMP, DEC and NEG
+pub fn sub(a: i16, b: i16, borrow: bool, size: Size) -> (i16, CpuFlags) {
+    let (a, b) = (unsigned(a, size), unsigned(b, size));
+    let result = a.wrapping_sub(b).wrapping_sub(borrow as u32);
+
+    let mut flags = CpuFlags::of_result(result, size);
+    flags.set(CpuFlags::C, a < b + borrow as u32);
+    flags.set(CpuFlags::A, (a ^ b ^ result) & 0x10 != 0);
+    flags.set(CpuFlags::O, (a ^ b) & (a ^ result) & sign_bit(size) != 0);
+
+    (signed(result, size), flags)
+}
+
+/// Logic instructions clear carry, overflow and auxiliary carry
+pub fn logic(result: i16, size: Size) -> (i16, CpuFlags) {
+    let result = unsigned(result, size);
+
+    (signed(result, size), CpuFlags::of_result(result, size))
+}
+
+/// Shifts and rotates move one bit at a time, the carry holds the last bit moved out.
+/// Count of zero changes no flags and overflow is only defined when the count is one
+pub fn shift(
+    operation: Operation,
+    value: i16,
+    count: u8,
+    flags: CpuFlags,
+    size: Size,
+) -> (i16, CpuFlags) {
+    let top = sign_bit(size);
+    let mut result = unsigned(value, size);
+    let mut carry = flags.is_flag_toogled(CpuFlags::C);
+
+    for _ in 0..count {
+        let (out, shifted) = match operation {
+            SHL => (result & top != 0, result << 1),
+            SHR => (result & 1 != 0, result >> 1),
+            SAR => (result & 1 != 0, (result >> 1) | (result & top)),
+            ROL => (
+                result & top != 0,
+                (result << 1) | (result & top != 0) as u32,
+            ),
+            ROR => (result & 1 != 0, (result >> 1) | ((result & 1) * top)),
+            RCL => (result & top != 0, (result << 1) | carry as u32),
+            RCR => (result & 1 != 0, (result >> 1) | (carry as u32 * top)),
+            _ => panic!("Not a shift or rotate instruction"),
+        };
+
+        result = shifted & mask(size);
+        carry = out;
+    }
+
+    let highest = result & top != 0;
+    let overflow = match operation {
+        SHL | ROL | RCL => highest != carry,
+        SHR => unsigned(value, size) & top != 0,
+        SAR => false,
+        _ => highest != (result & top >> 1 != 0),
+    };
+
+    // Rotates leave the flags of the result alone
+    let (mut computed, mut affected) = match operation {
+        ROL | ROR | RCL | RCR => (CpuFlags::empty(), CpuFlags::C | CpuFlags::O),
+        _ => (
+            CpuFlags::of_result(result, size),
+            CpuFlags::ARITHMETIC - CpuFlags::A,
+        ),
+    };
+    computed.set(CpuFlags::C, carry);
+    computed.set(CpuFlags::O, overflow);
+
+    match count {
+        0 => affected = CpuFlags::empty(),
+        1 => (),
+        _ => affected.remove(CpuFlags::O),
+    }
+
+    (
+        signed(result, size),
+        (flags & !affected) | (computed & affected),
+    )
+}
+
+/// Width aware ALU of the two operand and single operand instructions. Flags the
+/// operation does not define keep their value
+pub fn alu(operation: Operation, a: i16, b: i16, flags: CpuFlags, size: Size) -> (i16, CpuFlags) {
+    let carry = flags.is_flag_toogled(CpuFlags::C);
+
+    let (result, computed) = match operation {
+        ADD => add(a, b, false, size),
+        ADC => add(a, b, carry, size),
+        INC => add(a, 1, false, size),
+        SUB | CMP => sub(a, b, false, size),
+        SBB => sub(a, b, carry, size),
+        DEC => sub(a, 1, false, size),
+        NEG => sub(0, a, false, size),
+        AND | TEST => logic(a & b, size),
+        OR => logic(a | b, size),
+        XOR => logic(a ^ b, size),
+        NOT => return (signed(!a as u32, size), flags),
+        SHL | SHR | SAR | ROL | ROR | RCL | RCR => {
+            return shift(operation, a, b as u8, flags, size)
+        }
+        _ => panic!("{} is not executed by the ALU", operation),
+    };
+
+    // INC and DEC leave the carry flag untouched
+    let affected = match operation {
+        INC | DEC => CpuFlags::ARITHMETIC - CpuFlags::C,
+        _ => CpuFlags::ARITHMETIC,
+    };
+
+    (result, (flags & !affected) | (computed & affected))
+}
+
+/// Product twice the size of the operands, carry and overflow tell if its upper half is used
+pub fn multiply(a: i16, b: i16, signed_operands: bool, size: Size) -> (i32, CpuFlags) {
+    let (product, fits) = match signed_operands {
+        true => {
+            let product = signed(a as u32, size) as i32 * signed(b as u32, size) as i32;
+            (product, product == signed(product as u32, size) as i32)
+        }
+        false => {
+            let product = unsigned(a, size) * unsigned(b, size);
+            (product as i32, product <= mask(size))
+        }
+    };
+
+    let mut flags = CpuFlags::empty();
+    flags.set(CpuFlags::C | CpuFlags::O, !fits);
+
+    (product, flags)
+}
+
+/// Quotient and remainder, none on division by zero or when the quotient does not fit.
+/// Like the 8086 the most negative quotient is not produced by signed division
+pub fn divide(
+    dividend: u32,
+    divisor: i16,
+    signed_operands: bool,
+    size: Size,
+) -> Option<(i16, i16)> {
+    let (dividend, divisor, range) = match signed_operands {
+        true => {
+            let dividend = match size {
+                Size::BYTE => dividend as u16 as i16 as i64,
+                Size::WORD => dividend as i32 as i64,
+            };
+            let max = (sign_bit(size) - 1) as i64;
+
+            (dividend, signed(divisor as u32, size) as i64, -max..=max)
+        }
+        false => (
+            dividend as i64,
+            unsigned(divisor, size) as i64,
+            0..=mask(size) as i64,
+        ),
+    };
+
+    let quotient = dividend.checked_div(divisor)?;
+    let remainder = dividend % divisor;
+
+    range.contains(&quotient).then(|| {
+        (
+            signed(quotient as u32, size),
+            signed(remainder as u32, size),
+        )
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_add_flags() {
+        assert_eq!(
+            add(0x7FFF, 1, false, Size::WORD),
+            (
+                -0x8000,
+                CpuFlags::S | CpuFlags::O | CpuFlags::A | CpuFlags::P
+            )
+        );
+        assert_eq!(
+            add(-1, 1, false, Size::BYTE),
+            (0, CpuFlags::C | CpuFlags::Z | CpuFlags::A | CpuFlags::P)
+        );
+        assert_eq!(add(0x10, 0x20, true, Size::BYTE), (0x31, CpuFlags::empty()));
+    }
+
+    #[test]
+    fn test_sub_flags() {
+        assert_eq!(
+            sub(0, 1, false, Size::WORD),
+            (-1, CpuFlags::C | CpuFlags::S | CpuFlags::A | CpuFlags::P)
+        );
+        assert_eq!(
+            sub(-0x80, 1, false, Size::BYTE),
+            (0x7F, CpuFlags::O | CpuFlags::A)
+        );
+        assert_eq!(sub(5, 4, true, Size::WORD), (0, CpuFlags::Z | CpuFlags::P));
+    }
+
+    #[test]
+    fn test_logic_flags() {
+        assert_eq!(logic(0x0103, Size::BYTE), (3, CpuFlags::P));
+        assert_eq!(
+            logic(-0x8000, Size::WORD),
+            (-0x8000, CpuFlags::S | CpuFlags::P)
+        );
+    }
+
+    #[test]
+    fn test_shift_flags() {
+        let carry = CpuFlags::C;
+
+        assert_eq!(
+            shift(SHL, -0x80, 1, CpuFlags::empty(), Size::BYTE),
+            (0, CpuFlags::C | CpuFlags::O | CpuFlags::Z | CpuFlags::P)
+        );
+        assert_eq!(shift(SHR, 5, 2, CpuFlags::O, Size::WORD), (1, CpuFlags::O));
+        assert_eq!(
+            shift(SAR, -4, 1, CpuFlags::O, Size::WORD),
+            (-2, CpuFlags::S)
+        );
+        assert_eq!(
+            shift(SHL, 1, 20, carry, Size::WORD),
+            (0, CpuFlags::Z | CpuFlags::P)
+        );
+        assert_eq!(shift(SHR, 7, 0, carry, Size::BYTE), (7, carry));
+    }
+
+    #[test]
+    fn test_rotate_flags() {
+        let carry = CpuFlags::C;
+
+        assert_eq!(
+            shift(ROL, -0x80, 1, CpuFlags::Z, Size::BYTE),
+            (1, carry | CpuFlags::O | CpuFlags::Z)
+        );
+        assert_eq!(
+            shift(ROR, 1, 1, CpuFlags::empty(), Size::WORD),
+            (-0x8000, carry | CpuFlags::O)
+        );
+        assert_eq!(shift(RCL, 0x40, 1, carry, Size::BYTE), (-0x7F, CpuFlags::O));
+        assert_eq!(
+            shift(RCR, 0, 2, carry, Size::BYTE),
+            (0x40, CpuFlags::empty())
+        );
+        assert_eq!(
+            shift(ROL, 0x1234, 16, CpuFlags::empty(), Size::WORD),
+            (0x1234, CpuFlags::empty())
+        );
+    }
+
+    #[test]
+    fn test_alu() {
+        assert_eq!(
+            alu(INC, -1, 0, CpuFlags::C, Size::WORD),
+            (0, CpuFlags::C | CpuFlags::Z | CpuFlags::P | CpuFlags::A)
+        );
+        assert_eq!(
+            alu(NOT, 0x0F, 0, CpuFlags::D, Size::BYTE),
+            (-0x10, CpuFlags::D)
+        );
+        assert_eq!(
+            alu(XOR, 3, 3, CpuFlags::C | CpuFlags::D, Size::WORD),
+            (0, CpuFlags::Z | CpuFlags::P | CpuFlags::D)
+        );
+        assert_eq!(
+            alu(SHL, 3, 1, CpuFlags::empty(), Size::BYTE),
+            (6, CpuFlags::P)
+        );
+    }
+
+    #[test]
+    fn test_multiply() {
+        assert_eq!(
+            multiply(-1, -1, false, Size::BYTE),
+            (0xFE01, CpuFlags::C | CpuFlags::O)
+        );
+        assert_eq!(multiply(-1, -1, true, Size::BYTE), (1, CpuFlags::empty()));
+        assert_eq!(
+            multiply(-0x80, 1, true, Size::WORD),
+            (-0x80, CpuFlags::empty())
+        );
+        assert_eq!(
+            multiply(0x4000, 4, true, Size::WORD),
+            (0x10000, CpuFlags::C | CpuFlags::O)
+        );
+    }
+
+    #[test]
+    fn test_divide() {
+        assert_eq!(divide(100, 7, false, Size::BYTE), Some((14, 2)));
+        assert_eq!(divide(0x10000, 2, false, Size::WORD), Some((-0x8000, 0)));
+        assert_eq!(divide(-7i16 as u32, 2, true, Size::BYTE), Some((-3, -1)));
+        assert_eq!(divide(0x0100, 1, false, Size::BYTE), None);
+        assert_eq!(divide(-0x80i16 as u32, 1, true, Size::BYTE), None);
+        assert_eq!(divide(5, 0, true, Size::WORD), None);
+    }
+
+    #[test]
+    fn test_flags_display() {
+        assert_eq!((CpuFlags::S | CpuFlags::C | CpuFlags::P).to_string(), "CPS");
+        assert_eq!(CpuFlags::ARITHMETIC.to_string(), "CPAZSO");
+    }
+}
@@ -1 +1,7 @@
+pub mod clocks;
 pub mod cpu;
+pub mod dump;
+pub mod flags;
+pub mod services;
+pub mod snapshot;
+pub mod trace;
@@ -0,0 +1,356 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::cpu::{physical_address, Reg, RegPart, CPU};
+use super::flags::CpuFlags;
+
+/// Software interrupts served by the simulator instead of the program
+pub trait Services {
+    /// Interrupt that is not served goes through its vector in memory
+    fn interrupt(&mut self, vector: u8, cpu: &mut CPU<'_>) -> bool;
+}
+
+/// Handles 0 to 4 are the standard devices, opened files follow them
+const FIRST_FILE_HANDLE: u16 = 5;
+const MAX_OPEN_FILES: usize = 15;
+
+/// DOS paths are at most 128 bytes long including the terminating zero
+const MAX_PATH: u16 = 128;
+
+/// Returned by the console input at the end of the scripted input
+const END_OF_FILE: u8 = 0x1A;
+
+/// Error codes returned in AX with the carry flag set
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DosError {
+    InvalidFunction = 1,
+    FileNotFound = 2,
+    TooManyOpenFiles = 4,
+    AccessDenied = 5,
+    InvalidHandle = 6,
+}
+
+impl From<io::Error> for DosError {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::NotFound => DosError::FileNotFound,
+            _ => DosError::AccessDenied,
+        }
+    }
+}
+
+fn high(cpu: &CPU<'_>, reg: Reg) -> u8 {
+    (cpu.register(reg) >> 8) as u8
+}
+
+fn low(cpu: &CPU<'_>, reg: Reg) -> u8 {
+    cpu.register(reg) as u8
+}
+
+fn set_low(cpu: &mut CPU<'_>, reg: Reg, value: u8) {
+    cpu.set_register(reg, RegPart::Low.merge(cpu.register(reg), value as i16))
+}
+
+fn set_halves(cpu: &mut CPU<'_>, reg: Reg, high: u8, low: u8) {
+    cpu.set_register(reg, i16::from_le_bytes([low, high]))
+}
+
+/// Physical address of the byte at DS:DX and the following ones, the offset wraps around
+fn data_address(cpu: &CPU<'_>, n: u16) -> usize {
+    let offset = (cpu.register(Reg::D) as u16).wrapping_add(n);
+
+    physical_address(cpu.register(Reg::Ds) as u16, offset)
+}
+
+/// Bytes at DS:DX up to the terminator, at most `limit` of them
+fn string_at(cpu: &CPU<'_>, terminator: u8, limit: u16) -> Vec<u8> {
+    (0..limit)
+        .map(|n| cpu.memory()[data_address(cpu, n)])
+        .take_while(|byte| *byte != terminator)
+        .collect()
+}
+
+/// Carry flag tells whether the function failed, AX holds its result or the error code
+fn set_result(cpu: &mut CPU<'_>, result: Result<u16, DosError>) {
+    let (ax, carry) = match result {
+        Ok(value) => (value, CpuFlags::empty()),
+        Err(e) => (e as u16, CpuFlags::C),
+    };
+
+    cpu.set_register(Reg::A, ax as i16);
+    cpu.set_flags(carry, CpuFlags::C);
+}
+
+/// Console and file functions of INT 21h, teletype output of INT 10h and termination by
+/// INT 20h. Console output of both goes to the writer, input is read from the reader
+pub struct DosServices<R: Read, W: Write> {
+    input: R,
+    output: W,
+    /// Files are opened only inside of this directory, without it they cannot be opened
+    sandbox: Option<PathBuf>,
+    files: Vec<Option<File>>,
+    /// Hours, minutes, seconds and hundredths returned instead of the time of the host
+    time: Option<[u8; 4]>,
+}
+
+impl<R: Read, W: Write> DosServices<R, W> {
+    pub fn new(input: R, output: W) -> Self {
+        DosServices {
+            input,
+            output,
+            sandbox: None,
+            files: Vec::new(),
+            time: None,
+        }
+    }
+
+    pub fn with_sandbox<P: AsRef<Path>>(mut self, directory: P) -> Self {
+        self.sandbox = Some(directory.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn with_time(mut self, time: [u8; 4]) -> Self {
+        self.time = Some(time);
+        self
+    }
+
+    /// Console output has no way to report failures to the program
+    fn print(&mut self, bytes: &[u8]) {
+        let _ = self
+            .output
+            .write_all(bytes)
+            .and_then(|_| self.output.flush());
+    }
+
+    fn read_char(&mut self) -> u8 {
+        let mut byte = [0];
+
+        match self.input.read_exact(&mut byte) {
+            Ok(()) => byte[0],
+            Err(_) => END_OF_FILE,
+        }
+    }
+
+    /// Time of the day in UTC unless fixed
+    fn time(&self) -> [u8; 4] {
+        self.time.unwrap_or_else(|| {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            let millis = now.as_millis() % 86_400_000;
+
+            [
+                (millis / 3_600_000) as u8,
+                (millis / 60_000 % 60) as u8,
+                (millis / 1000 % 60) as u8,
+                (millis % 1000 / 10) as u8,
+            ]
+        })
+    }
+
+    /// Names are relative to the sandbox, drives and parent directories are refused
+    fn path(&self, name: &[u8]) -> Result<PathBuf, DosError> {
+        let sandbox = self.sandbox.as_ref().ok_or(DosError::AccessDenied)?;
+        let name = String::from_utf8_lossy(name).replace('\\', "/");
+        let relative = Path::new(&name);
+
+        let escapes = relative
+            .components()
+            .any(|component| !matches!(component, Component::Normal(_) | Component::CurDir));
+
+        if escapes || name.contains(':') {
+            return Err(DosError::AccessDenied);
+        }
+
+        Ok(sandbox.join(relative))
+    }
+
+    /// Name of the file is the zero terminated string at DS:DX
+    fn open(&mut self, cpu: &CPU<'_>, options: &OpenOptions) -> Result<u16, DosError> {
+        let path = self.path(&string_at(cpu, 0, MAX_PATH))?;
+        let file = options.open(path)?;
+
+        let index = match self.files.iter().position(Option::is_none) {
+            Some(index) => index,
+            None if self.files.len() < MAX_OPEN_FILES => {
+                self.files.push(None);
+                self.files.len() - 1
+            }
+            None => return Err(DosError::TooManyOpenFiles),
+        };
+
+        self.files[index] = Some(file);
+        Ok(FIRST_FILE_HANDLE + index as u16)
+    }
+
+    fn file(&mut self, handle: u16) -> Result<&mut File, DosError> {
+        let index = handle.checked_sub(FIRST_FILE_HANDLE);
+
+        index
+            .and_then(|index| self.files.get_mut(index as usize))
+            .and_then(Option::as_mut)
+            .ok_or(DosError::InvalidHandle)
+    }
+
+    fn close(&mut self, handle: u16) -> Result<u16, DosError> {
+        if handle < FIRST_FILE_HANDLE {
+            return Ok(0);
+        }
+
+        self.file(handle)?;
+        self.files[(handle - FIRST_FILE_HANDLE) as usize] = None;
+        Ok(0)
+    }
+
+    /// Standard input is read until the end of the line
+    fn read(&mut self, handle: u16, count: u16) -> Result<Vec<u8>, DosError> {
+        let mut bytes = Vec::new();
+
+        match handle {
+            0 => {
+                while bytes.len() < count as usize && bytes.last() != Some(&b'\n') {
+                    match self.read_char() {
+                        END_OF_FILE => break,
+                        byte => bytes.push(byte),
+                    }
+                }
+            }
+            1..=4 => (),
+            _ => {
+                self.file(handle)?
+                    .take(count as u64)
+                    .read_to_end(&mut bytes)?;
+            }
+        }
+
+        Ok(bytes)
+    }
+
+    fn write(&mut self, handle: u16, bytes: &[u8]) -> Result<u16, DosError> {
+        match handle {
+            0 => return Err(DosError::AccessDenied),
+            1 | 2 => {
+                self.output.write_all(bytes)?;
+                self.output.flush()?;
+            }
+            3 | 4 => (),
+            _ => self.file(handle)?.write_all(bytes)?,
+        }
+
+        Ok(bytes.len() as u16)
+    }
+
+    /// Function is selected by AH
+    fn dos(&mut self, cpu: &mut CPU<'_>) {
+        let handle = cpu.register(Reg::B) as u16;
+        let count = cpu.register(Reg::C) as u16;
+
+        match high(cpu, Reg::A) {
+            0x00 => cpu.terminate(0),
+            0x01 => {
+                let char = self.read_char();
+                self.print(&[char]);
+                set_low(cpu, Reg::A, char)
+            }
+            0x07 | 0x08 => {
+                let char = self.read_char();
+                set_low(cpu, Reg::A, char)
+            }
+            0x02 => {
+                let char = low(cpu, Reg::D);
+                self.print(&[char]);
+                set_low(cpu, Reg::A, char)
+            }
+            0x09 => {
+                let text = string_at(cpu, b'$', u16::MAX);
+                self.print(&text);
+                set_low(cpu, Reg::A, b'$')
+            }
+            0x2C => {
+                let [hours, minutes, seconds, hundredths] = self.time();
+                set_halves(cpu, Reg::C, hours, minutes);
+                set_halves(cpu, Reg::D, seconds, hundredths);
+            }
+            0x3C => {
+                let mut options = OpenOptions::new();
+                options.read(true).write(true).create(true).truncate(true);
+
+                let result = self.open(cpu, &options);
+                set_result(cpu, result)
+            }
+            0x3D => {
+                let mode = low(cpu, Reg::A) & 0b11;
+                let mut options = OpenOptions::new();
+                options.read(mode != 1).write(mode != 0);
+
+                let result = self.open(cpu, &options);
+                set_result(cpu, result)
+            }
+            0x3E => {
+                let result = self.close(handle);
+                set_result(cpu, result)
+            }
+            0x3F => {
+                let result = self.read(handle, count).map(|bytes| {
+                    for (n, byte) in bytes.iter().enumerate() {
+                        cpu.write_memory(data_address(cpu, n as u16), &[*byte]);
+                    }
+
+                    bytes.len() as u16
+                });
+                set_result(cpu, result)
+            }
+            0x40 => {
+                let bytes: Vec<u8> = (0..count)
+                    .map(|n| cpu.memory()[data_address(cpu, n)])
+                    .collect();
+
+                let result = self.write(handle, &bytes);
+                set_result(cpu, result)
+            }
+            0x4C => cpu.terminate(low(cpu, Reg::A)),
+            _ => set_result(cpu, Err(DosError::InvalidFunction)),
+        }
+    }
+}
+
+impl<R: Read, W: Write> Services for DosServices<R, W> {
+    fn interrupt(&mut self, vector: u8, cpu: &mut CPU<'_>) -> bool {
+        match vector {
+            // Only teletype output of the video services prints anything
+            0x10 => {
+                if high(cpu, Reg::A) == 0x0E {
+                    self.print(&[low(cpu, Reg::A)]);
+                }
+            }
+            0x20 => cpu.terminate(0),
+            0x21 => self.dos(cpu),
+            _ => return false,
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sandboxed_paths() {
+        let services = DosServices::new(io::empty(), io::sink()).with_sandbox("/sandbox");
+
+        assert_eq!(
+            services.path(b"DATA\\IN.TXT"),
+            Ok(PathBuf::from("/sandbox/DATA/IN.TXT"))
+        );
+        assert_eq!(services.path(b"..\\secret"), Err(DosError::AccessDenied));
+        assert_eq!(services.path(b"/etc/passwd"), Err(DosError::AccessDenied));
+        assert_eq!(services.path(b"C:FILE"), Err(DosError::AccessDenied));
+
+        let services = DosServices::new(io::empty(), io::sink());
+        assert_eq!(services.path(b"FILE"), Err(DosError::AccessDenied));
+    }
+}
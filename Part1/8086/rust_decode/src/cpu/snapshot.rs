@@ -0,0 +1,290 @@
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::ops::Range;
+use std::{error, fmt};
+
+use strum::IntoEnumIterator;
+
+use super::clocks::{ClockCount, Clocks, Processor};
+use super::cpu::Reg;
+use super::flags::CpuFlags;
+use super::trace::{read_array, read_u16, read_u32, read_u8};
+use crate::MEMORY_SIZE;
+
+/// Start of every snapshot, the version of the format follows it
+pub const SNAPSHOT_MAGIC: [u8; 3] = *b"S86";
+
+pub const SNAPSHOT_VERSION: u8 = 1;
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    InvalidMagicError,
+    UnsupportedVersionError(u8),
+    /// Field of the snapshot holds a value the machine cannot have
+    InvalidDataError(&'static str),
+    IoError(io::Error),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidMagicError => write!(f, "not a snapshot of the machine"),
+            Self::UnsupportedVersionError(version) => write!(
+                f,
+                "snapshot of version {} is not supported, expected version {}",
+                version, SNAPSHOT_VERSION
+            ),
+            Self::InvalidDataError(what) => write!(f, "snapshot has invalid {}", what),
+            Self::IoError(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl error::Error for SnapshotError {}
+
+impl From<io::Error> for SnapshotError {
+    fn from(e: io::Error) -> Self {
+        Self::IoError(e)
+    }
+}
+
+/// Whole state of the machine, tracer and services are not part of it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub registers: HashMap<Reg, i16>,
+    pub flags: CpuFlags,
+    pub ip: u16,
+    /// Physical addresses of the loaded program
+    pub program: Range<usize>,
+    /// Physical addresses CS:IP may execute, the program with its PSP
+    pub code: Range<usize>,
+    pub origin: u16,
+    pub exit_code: Option<u8>,
+    pub halted: bool,
+    /// Processor the clocks are estimated for with the clocks counted so far
+    pub clocks: Option<(Processor, ClockCount)>,
+    pub memory: Vec<u8>,
+}
+
+/// Little endian fields after the magic and the version:
+/// registers u16 in the order of `Reg`, flags u16, ip u16, program start u32, end u32,
+/// code start u32, end u32, origin u16,
+/// state u8 (0 running, 1 terminated with the code, 2 halted) and the code u8,
+/// processor u8 (0 without clocks, 1 for 8086, 2 for 8088) and with clocks
+/// base u32, ea u32, penalty u32, total u64, then the whole 1MB of memory
+impl Snapshot {
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let mut header = Vec::from(SNAPSHOT_MAGIC);
+        header.push(SNAPSHOT_VERSION);
+
+        for reg in Reg::iter() {
+            let value = self.registers.get(&reg).copied().unwrap_or(0);
+            header.extend(value.to_le_bytes());
+        }
+
+        header.extend(self.flags.bits().to_le_bytes());
+        header.extend(self.ip.to_le_bytes());
+        header.extend((self.program.start as u32).to_le_bytes());
+        header.extend((self.program.end as u32).to_le_bytes());
+        header.extend((self.code.start as u32).to_le_bytes());
+        header.extend((self.code.end as u32).to_le_bytes());
+        header.extend(self.origin.to_le_bytes());
+        let state = match (self.exit_code, self.halted) {
+            (Some(_), _) => 1,
+            (None, true) => 2,
+            (None, false) => 0,
+        };
+        header.extend([state, self.exit_code.unwrap_or(0)]);
+
+        match self.clocks {
+            Some((processor, ClockCount { last, total })) => {
+                header.push(match processor {
+                    Processor::I8086 => 1,
+                    Processor::I8088 => 2,
+                });
+                header.extend(last.base.to_le_bytes());
+                header.extend(last.ea.to_le_bytes());
+                header.extend(last.penalty.to_le_bytes());
+                header.extend(total.to_le_bytes());
+            }
+            None => header.push(0),
+        }
+
+        writer.write_all(&header)?;
+        writer.write_all(&self.memory)?;
+        writer.flush()
+    }
+
+    /// Snapshot that ends early or continues after the memory has invalid length
+    pub fn read<R: Read>(mut reader: R) -> Result<Self, SnapshotError> {
+        Self::read_fields(&mut reader).map_err(|e| match e {
+            SnapshotError::IoError(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                SnapshotError::InvalidDataError("length")
+            }
+            e => e,
+        })
+    }
+
+    fn read_fields<R: Read>(reader: &mut R) -> Result<Self, SnapshotError> {
+        if read_array::<R, 3>(reader)? != SNAPSHOT_MAGIC {
+            return Err(SnapshotError::InvalidMagicError);
+        }
+
+        match read_u8(reader)? {
+            SNAPSHOT_VERSION => (),
+            version => return Err(SnapshotError::UnsupportedVersionError(version)),
+        }
+
+        let registers = Reg::iter()
+            .map(|reg| Ok((reg, read_u16(reader)? as i16)))
+            .collect::<io::Result<_>>()?;
+
+        let flags = CpuFlags::from_bits(read_u16(reader)?)
+            .ok_or(SnapshotError::InvalidDataError("flags"))?;
+        let ip = read_u16(reader)?;
+
+        let program = read_u32(reader)? as usize..read_u32(reader)? as usize;
+        if program.start > program.end || program.end > MEMORY_SIZE {
+            return Err(SnapshotError::InvalidDataError("program range"));
+        }
+
+        let code = read_u32(reader)? as usize..read_u32(reader)? as usize;
+        if code.start > program.start || code.end != program.end {
+            return Err(SnapshotError::InvalidDataError("code range"));
+        }
+
+        let origin = read_u16(reader)?;
+        let (exit_code, halted) = match read_array::<R, 2>(reader)? {
+            [0, _] => (None, false),
+            [1, code] => (Some(code), false),
+            [2, _] => (None, true),
+            _ => return Err(SnapshotError::InvalidDataError("state")),
+        };
+
+        let processor = match read_u8(reader)? {
+            0 => None,
+            1 => Some(Processor::I8086),
+            2 => Some(Processor::I8088),
+            _ => return Err(SnapshotError::InvalidDataError("processor")),
+        };
+
+        let clocks = match processor {
+            Some(processor) => Some((
+                processor,
+                ClockCount {
+                    last: Clocks {
+                        base: read_u32(reader)?,
+                        ea: read_u32(reader)?,
+                        penalty: read_u32(reader)?,
+                    },
+                    total: u64::from_le_bytes(read_array(reader)?),
+                },
+            )),
+            None => None,
+        };
+
+        let mut memory = vec![0; MEMORY_SIZE];
+        reader.read_exact(&mut memory)?;
+
+        if reader.read(&mut [0])? != 0 {
+            return Err(SnapshotError::InvalidDataError("length"));
+        }
+
+        Ok(Snapshot {
+            registers,
+            flags,
+            ip,
+            program,
+            code,
+            origin,
+            exit_code,
+            halted,
+            clocks,
+            memory,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn snapshot() -> Snapshot {
+        let mut memory = vec![0; MEMORY_SIZE];
+        memory[0x10100] = 0xC3;
+
+        Snapshot {
+            registers: Reg::iter().zip(1..).collect(),
+            flags: CpuFlags::C | CpuFlags::Z,
+            ip: 0x100,
+            program: 0x10100..0x10101,
+            code: 0x10000..0x10101,
+            origin: 0x100,
+            exit_code: None,
+            halted: false,
+            clocks: Some((
+                Processor::I8088,
+                ClockCount {
+                    last: Clocks {
+                        base: 8,
+                        ea: 6,
+                        penalty: 4,
+                    },
+                    total: 1234,
+                },
+            )),
+            memory,
+        }
+    }
+
+    fn written(snapshot: &Snapshot) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        snapshot.write(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let mut snapshot = snapshot();
+        assert_eq!(Snapshot::read(&written(&snapshot)[..]).unwrap(), snapshot);
+
+        snapshot.clocks = None;
+        snapshot.exit_code = Some(3);
+        assert_eq!(Snapshot::read(&written(&snapshot)[..]).unwrap(), snapshot);
+
+        snapshot.exit_code = None;
+        snapshot.halted = true;
+        assert_eq!(Snapshot::read(&written(&snapshot)[..]).unwrap(), snapshot);
+    }
+
+    #[test]
+    fn test_invalid_snapshots() {
+        let bytes = written(&snapshot());
+
+        let mut other = bytes.clone();
+        other[0] = b'T';
+        assert!(matches!(
+            Snapshot::read(&other[..]),
+            Err(SnapshotError::InvalidMagicError)
+        ));
+
+        let mut other = bytes.clone();
+        other[3] = 2;
+        assert!(matches!(
+            Snapshot::read(&other[..]),
+            Err(SnapshotError::UnsupportedVersionError(2))
+        ));
+
+        assert!(matches!(
+            Snapshot::read(&bytes[..bytes.len() - 1]),
+            Err(SnapshotError::InvalidDataError("length"))
+        ));
+
+        let mut other = bytes;
+        other.push(0);
+        assert!(matches!(
+            Snapshot::read(&other[..]),
+            Err(SnapshotError::InvalidDataError("length"))
+        ));
+    }
+}
@@ -0,0 +1,413 @@
+use std::io::{self, Read, Write};
+
+use clap::ValueEnum;
+use serde::Serialize;
+use strum::IntoEnumIterator;
+
+use super::clocks::{ClockCount, Clocks};
+use super::cpu::Reg;
+use super::flags::CpuFlags;
+use crate::disassemble::disassemble_next_instruction;
+use crate::InstructionBuffer;
+
+/// Register value before and after the change, an instruction may change a register twice
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegisterChange {
+    pub reg: Reg,
+    pub old: u16,
+    pub new: u16,
+}
+
+/// Byte at the physical address before and after the instruction wrote it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryWrite {
+    pub address: usize,
+    pub old: u8,
+    pub new: u8,
+}
+
+/// What one executed instruction did
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceStep {
+    pub address: usize,
+    pub bytes: Vec<u8>,
+    pub instruction: String,
+    pub mnemonic: String,
+    pub registers: Vec<RegisterChange>,
+    pub old_flags: CpuFlags,
+    pub flags: CpuFlags,
+    pub memory: Vec<MemoryWrite>,
+    pub next_ip: usize,
+    /// Present when clocks are estimated
+    pub clocks: Option<ClockCount>,
+}
+
+/// Receives every executed instruction. Failed writes are kept until `finish` so the
+/// execution is not interrupted by them
+pub trait Tracer {
+    fn trace(&mut self, step: &TraceStep);
+
+    /// Flushes the output and returns the first error of the trace
+    fn finish(&mut self) -> io::Result<()>;
+}
+
+/// Writer that remembers its first error and ignores writes after it
+struct Output<W: Write> {
+    writer: W,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> Output<W> {
+    fn new(writer: W) -> Self {
+        Output {
+            writer,
+            error: None,
+        }
+    }
+
+    fn write<F: FnOnce(&mut W) -> io::Result<()>>(&mut self, write: F) {
+        if self.error.is_none() {
+            self.error = write(&mut self.writer).err();
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => self.writer.flush(),
+        }
+    }
+}
+
+/// Trace printed the same way as the reference simulator does, every instruction starts
+/// on a new line
+pub struct TextTracer<W: Write> {
+    output: Output<W>,
+}
+
+impl<W: Write> TextTracer<W> {
+    pub fn new(writer: W) -> Self {
+        TextTracer {
+            output: Output::new(writer),
+        }
+    }
+}
+
+impl<W: Write> Tracer for TextTracer<W> {
+    fn trace(&mut self, step: &TraceStep) {
+        self.output.write(|w| {
+            write!(w, "\n{} ; ", step.instruction)?;
+
+            if let Some(clocks) = step.clocks {
+                write!(w, "{} | ", clocks)?;
+            }
+
+            for change in &step.registers {
+                write!(w, "{}:{:#x}->{:#x} ", change.reg, change.old, change.new)?;
+            }
+
+            write!(w, "ip:{:#x}->{:#x}", step.address, step.next_ip)?;
+
+            if step.old_flags != step.flags {
+                write!(w, " flags:{}->{}", step.old_flags, step.flags)?;
+            }
+
+            Ok(())
+        })
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.output.finish()
+    }
+}
+
+#[derive(Serialize)]
+struct JsonRegister {
+    register: String,
+    old: u16,
+    new: u16,
+}
+
+#[derive(Serialize)]
+struct JsonWrite {
+    address: usize,
+    old: u8,
+    new: u8,
+}
+
+#[derive(Serialize)]
+struct JsonClocks {
+    base: u32,
+    ea: u32,
+    penalty: u32,
+    step: u32,
+    total: u64,
+}
+
+#[derive(Serialize)]
+struct JsonStep<'s> {
+    address: usize,
+    bytes: String,
+    mnemonic: &'s str,
+    instruction: &'s str,
+    registers: Vec<JsonRegister>,
+    old_flags: String,
+    flags: String,
+    memory: Vec<JsonWrite>,
+    next_ip: usize,
+    clocks: Option<JsonClocks>,
+}
+
+/// One JSON object per line and instruction, bytes are written as hexadecimal string
+pub struct JsonTracer<W: Write> {
+    output: Output<W>,
+}
+
+impl<W: Write> JsonTracer<W> {
+    pub fn new(writer: W) -> Self {
+        JsonTracer {
+            output: Output::new(writer),
+        }
+    }
+}
+
+impl<W: Write> Tracer for JsonTracer<W> {
+    fn trace(&mut self, step: &TraceStep) {
+        let json = JsonStep {
+            address: step.address,
+            bytes: step
+                .bytes
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect(),
+            mnemonic: &step.mnemonic,
+            instruction: &step.instruction,
+            registers: step
+                .registers
+                .iter()
+                .map(|change| JsonRegister {
+                    register: change.reg.to_string(),
+                    old: change.old,
+                    new: change.new,
+                })
+                .collect(),
+            old_flags: step.old_flags.to_string(),
+            flags: step.flags.to_string(),
+            memory: step
+                .memory
+                .iter()
+                .map(|write| JsonWrite {
+                    address: write.address,
+                    old: write.old,
+                    new: write.new,
+                })
+                .collect(),
+            next_ip: step.next_ip,
+            clocks: step.clocks.map(|ClockCount { last, total }| JsonClocks {
+                base: last.base,
+                ea: last.ea,
+                penalty: last.penalty,
+                step: last.total(),
+                total,
+            }),
+        };
+
+        self.output.write(|w| {
+            serde_json::to_writer(&mut *w, &json)?;
+            writeln!(w)
+        })
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.output.finish()
+    }
+}
+
+/// Start of the binary trace, the last byte is the version of the format
+pub const BINARY_TRACE_MAGIC: [u8; 4] = *b"T86\x01";
+
+/// Little endian records after the magic:
+/// address u32, next ip u32, length u8, bytes of the instruction, flags before and after u16,
+/// count of register changes u8 and for each register index u8, old u16, new u16,
+/// count of memory writes u32 and for each address u32, old u8, new u8,
+/// clocks present u8 and if present base u32, ea u32, penalty u32, total u64.
+/// Text of the instruction is not stored, it is decoded from its bytes
+pub struct BinaryTracer<W: Write> {
+    output: Output<W>,
+}
+
+impl<W: Write> BinaryTracer<W> {
+    pub fn new(writer: W) -> Self {
+        let mut output = Output::new(writer);
+        output.write(|w| w.write_all(&BINARY_TRACE_MAGIC));
+
+        BinaryTracer { output }
+    }
+}
+
+impl<W: Write> Tracer for BinaryTracer<W> {
+    fn trace(&mut self, step: &TraceStep) {
+        let mut record = Vec::new();
+
+        record.extend((step.address as u32).to_le_bytes());
+        record.extend((step.next_ip as u32).to_le_bytes());
+        record.push(step.bytes.len() as u8);
+        record.extend(&step.bytes);
+        record.extend(step.old_flags.bits().to_le_bytes());
+        record.extend(step.flags.bits().to_le_bytes());
+
+        record.push(step.registers.len() as u8);
+        for change in &step.registers {
+            record.push(Reg::iter().position(|reg| reg == change.reg).unwrap() as u8);
+            record.extend(change.old.to_le_bytes());
+            record.extend(change.new.to_le_bytes());
+        }
+
+        record.extend((step.memory.len() as u32).to_le_bytes());
+        for write in &step.memory {
+            record.extend((write.address as u32).to_le_bytes());
+            record.extend([write.old, write.new]);
+        }
+
+        match step.clocks {
+            Some(ClockCount { last, total }) => {
+                record.push(1);
+                record.extend(last.base.to_le_bytes());
+                record.extend(last.ea.to_le_bytes());
+                record.extend(last.penalty.to_le_bytes());
+                record.extend(total.to_le_bytes());
+            }
+            None => record.push(0),
+        }
+
+        self.output.write(|w| w.write_all(&record))
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.output.finish()
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+pub(super) fn read_array<R: Read, const N: usize>(reader: &mut R) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    reader.read_exact(&mut bytes)?;
+
+    Ok(bytes)
+}
+
+pub(super) fn read_u8<R: Read>(reader: &mut R) -> io::Result<u8> {
+    Ok(read_array::<R, 1>(reader)?[0])
+}
+
+pub(super) fn read_u16<R: Read>(reader: &mut R) -> io::Result<u16> {
+    Ok(u16::from_le_bytes(read_array(reader)?))
+}
+
+pub(super) fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    Ok(u32::from_le_bytes(read_array(reader)?))
+}
+
+/// Record of the binary trace, none at the end of the trace
+fn read_step<R: Read>(reader: &mut R) -> io::Result<Option<TraceStep>> {
+    let address = match read_array::<R, 4>(reader) {
+        Ok(bytes) => u32::from_le_bytes(bytes) as usize,
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let next_ip = read_u32(reader)? as usize;
+
+    let mut bytes = vec![0; read_u8(reader)? as usize];
+    reader.read_exact(&mut bytes)?;
+
+    let instruction = disassemble_next_instruction(&mut InstructionBuffer::from_bytes(&bytes))
+        .map_err(|e| invalid_data(&e.to_string()))?;
+
+    let old_flags = CpuFlags::from_bits_retain(read_u16(reader)?);
+    let flags = CpuFlags::from_bits_retain(read_u16(reader)?);
+
+    let registers = (0..read_u8(reader)?)
+        .map(|_| {
+            let reg = Reg::iter()
+                .nth(read_u8(reader)? as usize)
+                .ok_or_else(|| invalid_data("unknown register"))?;
+
+            Ok(RegisterChange {
+                reg,
+                old: read_u16(reader)?,
+                new: read_u16(reader)?,
+            })
+        })
+        .collect::<io::Result<_>>()?;
+
+    let memory = (0..read_u32(reader)?)
+        .map(|_| {
+            Ok(MemoryWrite {
+                address: read_u32(reader)? as usize,
+                old: read_u8(reader)?,
+                new: read_u8(reader)?,
+            })
+        })
+        .collect::<io::Result<_>>()?;
+
+    let clocks = match read_u8(reader)? {
+        0 => None,
+        _ => Some(ClockCount {
+            last: Clocks {
+                base: read_u32(reader)?,
+                ea: read_u32(reader)?,
+                penalty: read_u32(reader)?,
+            },
+            total: u64::from_le_bytes(read_array(reader)?),
+        }),
+    };
+
+    Ok(Some(TraceStep {
+        address,
+        bytes,
+        instruction: instruction.to_string(),
+        mnemonic: instruction.operation().to_string(),
+        registers,
+        old_flags,
+        flags,
+        memory,
+        next_ip,
+        clocks,
+    }))
+}
+
+/// Steps of the trace written by `BinaryTracer`
+pub fn read_binary_trace<R: Read>(mut reader: R) -> io::Result<Vec<TraceStep>> {
+    if read_array::<R, 4>(&mut reader)? != BINARY_TRACE_MAGIC {
+        return Err(invalid_data("not a binary trace of this version"));
+    }
+
+    let mut steps = Vec::new();
+
+    while let Some(step) = read_step(&mut reader)? {
+        steps.push(step);
+    }
+
+    Ok(steps)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum TraceFormat {
+    Text,
+    /// JSON Lines
+    Json,
+    Binary,
+}
+
+impl TraceFormat {
+    pub fn tracer<'w, W: Write + 'w>(self, writer: W) -> Box<dyn Tracer + 'w> {
+        match self {
+            TraceFormat::Text => Box::new(TextTracer::new(writer)),
+            TraceFormat::Json => Box::new(JsonTracer::new(writer)),
+            TraceFormat::Binary => Box::new(BinaryTracer::new(writer)),
+        }
+    }
+}
@@ -0,0 +1,622 @@
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::error;
+use std::fmt::{self, Write};
+use std::ops::Range;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use strum::IntoEnumIterator;
+
+use crate::assemble::{number, register};
+use crate::assembled_instruction::Operation;
+use crate::cpu::cpu::{CpuState, Reg, RegPart, CPU};
+use crate::cpu::dump::hex_dump;
+use crate::disassemble::{disassemble_bytes_resilient, Decoded, DisassemblyError};
+use crate::InstructionBuffer;
+
+/// Executed instructions that can be stepped back, the oldest are forgotten first
+const HISTORY_LIMIT: usize = 1 << 16;
+
+const BYTES_PER_LINE: usize = 16;
+
+const HELP: &str = "\
+s, step [n]                 execute n instructions
+n, next                     step over CALL
+c, continue                 run until breakpoint, watchpoint, end of the program or Ctrl-C
+rs, back [n]                step back n instructions
+b, break [location]         break at address or label, list breakpoints without location
+d, delete <location>        delete breakpoint
+w, watch [watch]            stop when register or memory changes, list watchpoints without watch
+unwatch <watch>             watch is register, address or range start..end
+r, registers                print registers and flags
+x, memory <address> [n]     print n bytes of memory
+set <register> <value>      change register or ip
+write <address> <byte>...   change memory, the change is not undone by stepping back
+l, list [n]                 disassemble n instructions around ip
+q, quit";
+
+#[derive(Debug)]
+pub enum DebuggerError {
+    UnknownCommandError(String),
+    InvalidArgumentError(String),
+    UndefinedLabelError(String),
+    EmptyHistoryError,
+    ExecutionError(DisassemblyError),
+}
+
+impl fmt::Display for DebuggerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownCommandError(name) => write!(f, "unknown command '{}', try help", name),
+            Self::InvalidArgumentError(text) => write!(f, "invalid argument '{}'", text),
+            Self::UndefinedLabelError(label) => write!(f, "undefined label '{}'", label),
+            Self::EmptyHistoryError => write!(f, "no executed instruction to step back"),
+            Self::ExecutionError(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl error::Error for DebuggerError {}
+
+impl From<DisassemblyError> for DebuggerError {
+    fn from(e: DisassemblyError) -> Self {
+        Self::ExecutionError(e)
+    }
+}
+
+fn invalid(text: &str) -> DebuggerError {
+    DebuggerError::InvalidArgumentError(text.to_string())
+}
+
+/// Addresses and counts are not negative
+fn address(text: &str) -> Result<usize, DebuggerError> {
+    number(text)
+        .filter(|value| *value >= 0)
+        .map(|value| value as usize)
+        .ok_or_else(|| invalid(text))
+}
+
+/// Breakpoint place given by the address in the program or by the label of the disassembly
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Location {
+    Address(usize),
+    Label(String),
+}
+
+impl FromStr for Location {
+    type Err = DebuggerError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match number(text) {
+            Some(_) => address(text).map(Location::Address),
+            None => Ok(Location::Label(text.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Watchpoint {
+    Register(Reg),
+    /// Physical addresses of the watched bytes
+    Memory(Range<usize>),
+}
+
+impl FromStr for Watchpoint {
+    type Err = DebuggerError;
+
+    /// Halves of registers are watched as the whole register
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        if let Some((reg, _)) = register(text) {
+            return Ok(Watchpoint::Register(reg));
+        }
+
+        let range = match text.split_once("..") {
+            Some((start, end)) => address(start)?..address(end)?,
+            None => address(text)?..address(text)? + 1,
+        };
+
+        match range.is_empty() {
+            true => Err(invalid(text)),
+            false => Ok(Watchpoint::Memory(range)),
+        }
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Register(reg) => write!(f, "{}", reg),
+            Self::Memory(range) => write!(f, "[{:#x}..{:#x}]", range.start, range.end),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Step(usize),
+    Next,
+    Continue,
+    Back(usize),
+    Break(Option<Location>),
+    Delete(Location),
+    Watch(Option<Watchpoint>),
+    Unwatch(Watchpoint),
+    Registers,
+    Memory(usize, usize),
+    SetRegister(Reg, RegPart, i16),
+    SetIp(usize),
+    Write(usize, Vec<u8>),
+    List(usize),
+    Help,
+    Quit,
+}
+
+impl Command {
+    /// Commands that execute instructions and print their trace
+    pub fn executes(&self) -> bool {
+        matches!(self, Command::Step(_) | Command::Next | Command::Continue)
+    }
+}
+
+impl FromStr for Command {
+    type Err = DebuggerError;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let mut words = line.split_whitespace();
+        let name = words.next().unwrap_or_default();
+        let args: Vec<&str> = words.collect();
+
+        let count = |default: usize| match args.as_slice() {
+            [] => Ok(default),
+            [count] => address(count),
+            _ => Err(invalid(&args.join(" "))),
+        };
+        let none = |command: Command| match args.is_empty() {
+            true => Ok(command),
+            false => Err(invalid(&args.join(" "))),
+        };
+
+        match (name, args.as_slice()) {
+            ("s" | "step", _) => count(1).map(Command::Step),
+            ("n" | "next", _) => none(Command::Next),
+            ("c" | "continue", _) => none(Command::Continue),
+            ("rs" | "back", _) => count(1).map(Command::Back),
+            ("b" | "break", []) => Ok(Command::Break(None)),
+            ("b" | "break", [location]) => Ok(Command::Break(Some(location.parse()?))),
+            ("d" | "delete", [location]) => Ok(Command::Delete(location.parse()?)),
+            ("w" | "watch", []) => Ok(Command::Watch(None)),
+            ("w" | "watch", [watch]) => Ok(Command::Watch(Some(watch.parse()?))),
+            ("unwatch", [watch]) => Ok(Command::Unwatch(watch.parse()?)),
+            ("r" | "registers", _) => none(Command::Registers),
+            ("x" | "memory", [start]) => Ok(Command::Memory(address(start)?, BYTES_PER_LINE)),
+            ("x" | "memory", [start, length]) => {
+                Ok(Command::Memory(address(start)?, address(length)?))
+            }
+            ("set", ["ip", value]) => Ok(Command::SetIp(address(value)?)),
+            ("set", [name, value]) => {
+                let (reg, part) = register(name).ok_or_else(|| invalid(name))?;
+                let value = number(value)
+                    .filter(|value| (-0x8000..=0xFFFF).contains(value))
+                    .ok_or_else(|| invalid(value))?;
+
+                Ok(Command::SetRegister(reg, part, value as i16))
+            }
+            ("write", [start, bytes @ ..]) if !bytes.is_empty() => {
+                let bytes = bytes
+                    .iter()
+                    .map(|byte| {
+                        number(byte)
+                            .filter(|value| (-0x80..=0xFF).contains(value))
+                            .map(|value| value as u8)
+                            .ok_or_else(|| invalid(byte))
+                    })
+                    .collect::<Result<_, _>>()?;
+
+                Ok(Command::Write(address(start)?, bytes))
+            }
+            ("l" | "list", _) => count(5).map(Command::List),
+            ("h" | "help", _) => none(Command::Help),
+            ("q" | "quit", _) => none(Command::Quit),
+            ("b" | "break" | "d" | "delete" | "w" | "watch" | "unwatch", _)
+            | ("x" | "memory" | "set" | "write", _) => Err(invalid(line.trim())),
+            (name, _) => Err(DebuggerError::UnknownCommandError(name.to_string())),
+        }
+    }
+}
+
+/// CPU state before the instruction and the old values of the bytes it wrote
+struct Step {
+    state: CpuState,
+    writes: Vec<(usize, u8)>,
+}
+
+/// Executes the program under control of the commands, every executed instruction is
+/// recorded so it can be stepped back
+pub struct Debugger<'a> {
+    cpu: CPU<'a>,
+    listing: Vec<Decoded>,
+    /// IP of the first line of the listing
+    origin: usize,
+    labels: HashMap<String, usize>,
+    breakpoints: BTreeSet<usize>,
+    watchpoints: Vec<Watchpoint>,
+    history: VecDeque<Step>,
+    /// Set from outside, e.g. by Ctrl-C, to stop running instructions
+    interrupted: Arc<AtomicBool>,
+}
+
+impl<'a> Debugger<'a> {
+    pub fn new(mut cpu: CPU<'a>) -> Self {
+        let listing = disassemble_bytes_resilient(InstructionBuffer::from_bytes(cpu.program()));
+        let origin = cpu.program_origin();
+        let labels = listing
+            .iter()
+            .filter_map(|line| match line {
+                Decoded::Instruction(instr) => instr
+                    .label()
+                    .map(|label| (label.to_string(), ip_of(origin, instr.offset()))),
+                Decoded::Data(_, _) => None,
+            })
+            .collect();
+
+        cpu.record_memory_writes();
+
+        Debugger {
+            cpu,
+            listing,
+            origin,
+            labels,
+            breakpoints: BTreeSet::new(),
+            watchpoints: Vec::new(),
+            history: VecDeque::new(),
+            interrupted: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Flag that stops continue and next before the following instruction when it is set
+    pub fn interrupt_flag(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.interrupted)
+    }
+
+    pub fn cpu(&self) -> &CPU<'a> {
+        &self.cpu
+    }
+
+    /// Text to print after the command, empty when the trace says it all
+    pub fn run(&mut self, command: Command) -> Result<String, DebuggerError> {
+        match command {
+            Command::Step(count) => self.resume(Some(count), None),
+            Command::Next => self.next(),
+            Command::Continue => self.resume(None, None),
+            Command::Back(count) => self.back(count),
+            Command::Break(Some(location)) => {
+                let address = self.resolve(location)?;
+                self.breakpoints.insert(address);
+
+                Ok(format!("breakpoint at {}", self.describe(address)))
+            }
+            Command::Break(None) => Ok(self
+                .breakpoints
+                .iter()
+                .map(|address| self.describe(*address))
+                .collect::<Vec<_>>()
+                .join("\n")),
+            Command::Delete(location) => {
+                let address = self.resolve(location)?;
+
+                match self.breakpoints.remove(&address) {
+                    true => Ok(format!("deleted breakpoint at {}", self.describe(address))),
+                    false => Ok(format!("no breakpoint at {}", self.describe(address))),
+                }
+            }
+            Command::Watch(Some(watchpoint)) => {
+                let message = format!("watching {}", watchpoint);
+
+                if !self.watchpoints.contains(&watchpoint) {
+                    self.watchpoints.push(watchpoint);
+                }
+
+                Ok(message)
+            }
+            Command::Watch(None) => Ok(self
+                .watchpoints
+                .iter()
+                .map(Watchpoint::to_string)
+                .collect::<Vec<_>>()
+                .join("\n")),
+            Command::Unwatch(watchpoint) => {
+                self.watchpoints.retain(|watched| *watched != watchpoint);
+
+                Ok(format!("stopped watching {}", watchpoint))
+            }
+            Command::Registers => Ok(self.registers()),
+            Command::Memory(start, length) => self.memory(start, length),
+            Command::SetRegister(reg, part, value) => {
+                let content = part.merge(self.cpu.register(reg), value);
+                self.cpu.set_register(reg, content);
+
+                Ok(format!("{}: {:#06x}", reg, content))
+            }
+            Command::SetIp(ip) => {
+                self.cpu.set_ip(ip);
+
+                Ok(format!("ip: {}", self.describe(ip)))
+            }
+            Command::Write(start, bytes) => {
+                self.cpu
+                    .memory_mut()
+                    .get_mut(start..start + bytes.len())
+                    .ok_or_else(|| invalid(&format!("{:#x}", start)))?
+                    .copy_from_slice(&bytes);
+
+                self.memory(start, bytes.len())
+            }
+            Command::List(around) => self.list(around),
+            Command::Help => Ok(HELP.to_string()),
+            Command::Quit => Ok(String::new()),
+        }
+    }
+
+    fn resolve(&self, location: Location) -> Result<usize, DebuggerError> {
+        match location {
+            Location::Address(address) => Ok(address),
+            Location::Label(label) => self
+                .labels
+                .get(&label)
+                .copied()
+                .ok_or(DebuggerError::UndefinedLabelError(label)),
+        }
+    }
+
+    /// Address with the label of the instruction at it
+    fn describe(&self, address: usize) -> String {
+        match self.labels.iter().find(|(_, at)| **at == address) {
+            Some((label, _)) => format!("{:#06x} ({})", address, label),
+            None => format!("{:#06x}", address),
+        }
+    }
+
+    fn watched(&self, watchpoint: &Watchpoint) -> Vec<u8> {
+        match watchpoint {
+            Watchpoint::Register(reg) => self.cpu.register(*reg).to_le_bytes().to_vec(),
+            Watchpoint::Memory(range) => self
+                .cpu
+                .memory()
+                .get(range.clone())
+                .unwrap_or_default()
+                .to_vec(),
+        }
+    }
+
+    /// Failed instruction leaves the CPU as it was before it
+    fn execute(&mut self) -> Result<(), DebuggerError> {
+        let state = self.cpu.state();
+
+        if let Err(e) = self.cpu.execute_next_instruction() {
+            let writes = self.cpu.take_memory_writes();
+            self.cpu.undo_memory_writes(&writes);
+            self.cpu.restore(state);
+
+            return Err(e.into());
+        }
+
+        if self.history.len() == HISTORY_LIMIT {
+            self.history.pop_front();
+        }
+
+        let writes = self.cpu.take_memory_writes();
+        self.history.push_back(Step { state, writes });
+
+        Ok(())
+    }
+
+    /// Runs up to the count of instructions or until IP reaches the address, breakpoints,
+    /// watchpoints and the end of the program stop the run earlier
+    fn resume(
+        &mut self,
+        count: Option<usize>,
+        until: Option<usize>,
+    ) -> Result<String, DebuggerError> {
+        let mut executed = 0;
+        // Interrupt before the command started is not meant for it
+        self.interrupted.store(false, Ordering::Relaxed);
+
+        loop {
+            if let Some(reason) = self.cpu.stop_reason() {
+                return Ok(reason.to_string());
+            }
+
+            if count == Some(executed) {
+                return Ok(String::new());
+            }
+
+            let before: Vec<Vec<u8>> = self.watchpoints.iter().map(|w| self.watched(w)).collect();
+            self.execute()?;
+            executed += 1;
+
+            for (watchpoint, old) in self.watchpoints.iter().zip(before) {
+                let new = self.watched(watchpoint);
+
+                if new != old {
+                    return Ok(format!(
+                        "watchpoint {} changed from {} to {}",
+                        watchpoint,
+                        hex(&old),
+                        hex(&new)
+                    ));
+                }
+            }
+
+            let ip = self.cpu.ip();
+
+            if self.interrupted.swap(false, Ordering::Relaxed) {
+                return Ok(format!("interrupted at {}", self.describe(ip)));
+            }
+
+            if until == Some(ip) {
+                return Ok(String::new());
+            }
+
+            if self.breakpoints.contains(&ip) {
+                return Ok(format!("breakpoint at {}", self.describe(ip)));
+            }
+        }
+    }
+
+    /// Called procedure runs until it returns to the instruction after the call
+    fn next(&mut self) -> Result<String, DebuggerError> {
+        if self.cpu.is_finished() {
+            return self.resume(Some(1), None);
+        }
+
+        let instruction = self.cpu.next_instruction()?;
+
+        match instruction.operation() {
+            Operation::CALL | Operation::CALLF => {
+                self.resume(None, Some(instruction.offset() + instruction.length()))
+            }
+            _ => self.resume(Some(1), None),
+        }
+    }
+
+    fn back(&mut self, count: usize) -> Result<String, DebuggerError> {
+        if self.history.is_empty() {
+            return Err(DebuggerError::EmptyHistoryError);
+        }
+
+        for _ in 0..count {
+            let Some(step) = self.history.pop_back() else {
+                break;
+            };
+
+            self.cpu.undo_memory_writes(&step.writes);
+            self.cpu.restore(step.state);
+        }
+
+        Ok(format!("back at {}", self.describe(self.cpu.ip())))
+    }
+
+    fn registers(&self) -> String {
+        let mut text = String::new();
+
+        for reg in Reg::iter() {
+            let value = self.cpu.register(reg);
+            writeln!(text, "{}: {:#06x} ({})", reg, value, value).unwrap();
+        }
+
+        write!(
+            text,
+            "ip: {}\nflags: {}",
+            self.describe(self.cpu.ip()),
+            self.cpu.flags()
+        )
+        .unwrap();
+        text
+    }
+
+    fn memory(&self, start: usize, length: usize) -> Result<String, DebuggerError> {
+        let bytes = self
+            .cpu
+            .memory()
+            .get(start..start.saturating_add(length))
+            .ok_or_else(|| invalid(&format!("{:#x}", start)))?;
+
+        Ok(hex_dump(bytes, start))
+    }
+
+    /// Lines of the program before and after IP, IP inside of a listed instruction is decoded
+    /// on its own
+    fn list(&mut self, around: usize) -> Result<String, DebuggerError> {
+        let ip = self.cpu.ip();
+
+        if let Some(reason) = self.cpu.stop_reason() {
+            return Ok(reason.to_string());
+        }
+
+        let at = |line: &Decoded| ip_of(self.origin, line.offset());
+
+        let Some(current) = self.listing.iter().position(|line| at(line) == ip) else {
+            return Ok(format!("=> {:05x}  {}", ip, self.cpu.next_instruction()?));
+        };
+
+        let start = current.saturating_sub(around);
+        let end = (current + around + 1).min(self.listing.len());
+        let mut text = String::new();
+
+        for line in &self.listing[start..end] {
+            if let Decoded::Instruction(instr) = line {
+                if let Some(label) = instr.label() {
+                    writeln!(text, "{}:", label).unwrap();
+                }
+            }
+
+            let marker = match at(line) {
+                offset if offset == ip => "=>",
+                offset if self.breakpoints.contains(&offset) => " *",
+                _ => "  ",
+            };
+
+            writeln!(text, "{} {:05x}  {}", marker, at(line), line).unwrap();
+        }
+
+        Ok(text.trim_end().to_string())
+    }
+}
+
+/// Offsets of the listing are counted from the start of the program
+fn ip_of(origin: usize, offset: usize) -> usize {
+    (origin + offset) as u16 as usize
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!("s".parse::<Command>().unwrap(), Command::Step(1));
+        assert_eq!("step 0x10".parse::<Command>().unwrap(), Command::Step(16));
+        assert_eq!(
+            "b label_2".parse::<Command>().unwrap(),
+            Command::Break(Some(Location::Label("label_2".to_string())))
+        );
+        assert_eq!(
+            "watch 0x100..0x102".parse::<Command>().unwrap(),
+            Command::Watch(Some(Watchpoint::Memory(0x100..0x102)))
+        );
+        assert_eq!(
+            "w al".parse::<Command>().unwrap(),
+            Command::Watch(Some(Watchpoint::Register(Reg::A)))
+        );
+        assert_eq!(
+            "set ah -1".parse::<Command>().unwrap(),
+            Command::SetRegister(Reg::A, RegPart::High, -1)
+        );
+        assert_eq!(
+            "write 10 1 0xff".parse::<Command>().unwrap(),
+            Command::Write(10, vec![1, 0xFF])
+        );
+
+        assert!(matches!(
+            "jump".parse::<Command>(),
+            Err(DebuggerError::UnknownCommandError(_))
+        ));
+        assert!(matches!(
+            "set ax 0x10000".parse::<Command>(),
+            Err(DebuggerError::InvalidArgumentError(_))
+        ));
+        assert!(matches!(
+            "watch 5..5".parse::<Command>(),
+            Err(DebuggerError::InvalidArgumentError(_))
+        ));
+    }
+}
@@ -1,166 +1,240 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::{error, fmt};
+
 use crate::assembled_instruction::AssembledInstructionLookupError;
 use crate::instruction::instruction::{DecodingError, Instruction};
-use crate::{BufferEndReachedError, InstructionBuffer, MEMORY_SIZE};
+use crate::instruction::prefix::{Prefix, Prefixes};
+use crate::{BufferEndReachedError, InstructionBuffer};
 
 #[derive(Debug)]
-pub enum DisassemblyError {
+pub enum DisassemblyErrorKind {
     LookupError(AssembledInstructionLookupError),
     BufferError(BufferEndReachedError),
     DecodeError(DecodingError),
 }
 
-impl From<AssembledInstructionLookupError> for DisassemblyError {
+impl From<AssembledInstructionLookupError> for DisassemblyErrorKind {
     fn from(e: AssembledInstructionLookupError) -> Self {
-        DisassemblyError::LookupError(e)
+        DisassemblyErrorKind::LookupError(e)
     }
 }
 
-impl From<DecodingError> for DisassemblyError {
+impl From<DecodingError> for DisassemblyErrorKind {
     fn from(e: DecodingError) -> Self {
-        DisassemblyError::DecodeError(e)
+        DisassemblyErrorKind::DecodeError(e)
     }
 }
 
-impl From<BufferEndReachedError> for DisassemblyError {
+impl From<BufferEndReachedError> for DisassemblyErrorKind {
     fn from(e: BufferEndReachedError) -> Self {
-        DisassemblyError::BufferError(e)
+        DisassemblyErrorKind::BufferError(e)
     }
 }
 
-pub type DisassemblyResult<T> = Result<T, DisassemblyError>;
+impl fmt::Display for DisassemblyErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::LookupError(e) => write!(f, "broken instruction table: {:?}", e),
+            Self::BufferError(_) => write!(f, "program ends in the middle of instruction"),
+            Self::DecodeError(e) => write!(f, "{}", e),
+        }
+    }
+}
 
-pub fn disassemble_next_instruction(
-    buffer: &mut InstructionBuffer,
-) -> DisassemblyResult<Instruction> {
-    let mut instr = Instruction::new(buffer.next_byte()?)?;
+/// Failure to decode the instruction starting at `offset` of the program
+#[derive(Debug)]
+pub struct DisassemblyError {
+    pub kind: DisassemblyErrorKind,
+    pub offset: usize,
+    /// Bytes read from the start of the instruction until the failure
+    pub bytes: Vec<u8>,
+    /// Instruction as far as it was decoded, none when the opcode is not known
+    pub instruction: Option<Box<Instruction>>,
+}
 
-    let n_of_bytes_needed = instr.continue_disassembly(buffer.next_byte()?)?;
+impl DisassemblyError {
+    /// Instruction that ran out of bytes takes the rest of the program
+    pub(crate) fn new(
+        kind: DisassemblyErrorKind,
+        buffer: &InstructionBuffer,
+        offset: usize,
+        instruction: Option<Instruction>,
+    ) -> Self {
+        let end = match kind {
+            DisassemblyErrorKind::BufferError(_) => buffer.bytes_loaded,
+            _ => buffer.last_read,
+        };
 
-    if n_of_bytes_needed == 0 {
-        return Ok(instr);
+        DisassemblyError {
+            kind,
+            offset,
+            bytes: buffer.buf[offset..end.max(offset)].to_vec(),
+            instruction: instruction.map(Box::new),
+        }
     }
-
-    instr.finalize_disassembly(buffer.next_n_bytes(n_of_bytes_needed.into())?)?;
-
-    Ok(instr)
 }
 
-pub fn disassemble_bytes_in(mut buffer: InstructionBuffer) -> DisassemblyResult<Vec<Instruction>> {
-    let mut instructions: Vec<Instruction> = Vec::with_capacity(MEMORY_SIZE / 2);
+impl fmt::Display for DisassemblyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at {:#06X}", self.kind, self.offset)?;
 
-    while !buffer.is_at_the_end() {
-        instructions.push(disassemble_next_instruction(&mut buffer)?);
-    }
+        for (n, byte) in self.bytes.iter().enumerate() {
+            write!(f, "{}{:02X}", if n == 0 { ": " } else { " " }, byte)?;
+        }
 
-    Ok(instructions)
+        Ok(())
+    }
 }
 
-#[cfg(test)]
-mod test {
-    use super::*;
+impl error::Error for DisassemblyError {}
 
-    fn test_instruction(bytes: Vec<u8>, instruction_str: &str) -> () {
-        let mut buffer = InstructionBuffer {
-            buf: bytes.clone(),
-            last_read: 0,
-            bytes_loaded: bytes.len(),
-        };
+pub type DisassemblyResult<T> = Result<T, DisassemblyError>;
 
-        let (instruction, bytes_processed) = disassemble_next_instruction(&mut buffer).unwrap();
+pub fn disassemble_next_instruction(
+    buffer: &mut InstructionBuffer,
+) -> DisassemblyResult<Instruction> {
+    let offset = buffer.last_read;
 
-        println!("{:?}", instruction);
+    let (prefixes, opcode) =
+        read_prefixes(buffer).map_err(|e| DisassemblyError::new(e.into(), buffer, offset, None))?;
 
-        assert_eq!(bytes_processed, bytes.len());
-        assert_eq!(format!("{}", instruction), instruction_str);
-    }
+    let mut instr = Instruction::new(opcode)
+        .map_err(|e| DisassemblyError::new(e.into(), buffer, offset, None))?;
 
-    #[test]
-    fn test_memory_mode_instruction() {
-        test_instruction(vec![0x8A, 0x00], "mov al, [bx + si]")
-    }
+    let decoded = decode_rest(&mut instr, buffer, prefixes);
+    instr.set_position(offset, buffer.last_read - offset);
 
-    #[test]
-    fn test_memory_mode_instruction_displacement() {
-        test_instruction(vec![0x8A, 0x80, 0x87, 0x13], "mov al, [bx + si+4999]")
+    match decoded {
+        Ok(()) => Ok(instr),
+        Err(kind) => Err(DisassemblyError::new(kind, buffer, offset, Some(instr))),
     }
+}
 
-    #[test]
-    fn test_mov_a() {
-        test_instruction(vec![0x8b, 0x41, 0xdb], "mov ax, [bx + di-37]")
-    }
+/// Prefixes preceding the opcode and the opcode itself
+fn read_prefixes(buffer: &mut InstructionBuffer) -> Result<(Prefixes, u8), BufferEndReachedError> {
+    let mut prefixes = Prefixes::default();
+    let mut byte = buffer.next_byte()?;
 
-    #[test]
-    fn test_immediate_mov_mem_to_acc() {
-        test_instruction(vec![0xa1, 0xfb, 0x09], "mov ax, [2555]")
+    while let Some(prefix) = Prefix::new(byte) {
+        prefixes.add(prefix);
+        byte = buffer.next_byte()?;
     }
 
-    #[test]
-    fn test_immediate_acc_to_mem() {
-        test_instruction(vec![0xa3, 0x0f, 0x00], "mov [15], ax")
-    }
+    Ok((prefixes, byte))
+}
 
-    #[test]
-    fn test_immediate_mov_direct_addr() {
-        test_instruction(vec![0x8b, 0x2e, 0x05, 0x00], "mov bp, [5]")
-    }
+fn decode_rest(
+    instr: &mut Instruction,
+    buffer: &mut InstructionBuffer,
+    prefixes: Prefixes,
+) -> Result<(), DisassemblyErrorKind> {
+    if !instr.is_decoded() {
+        let n_of_bytes_needed = instr.continue_disassembly(buffer.next_byte()?)?;
 
-    #[test]
-    fn test_immediate_to_register() {
-        test_instruction(vec![0xBA, 0x6C, 0x0F], "mov dx, 3948")
+        if n_of_bytes_needed != 0 {
+            instr.finalize_disassembly(buffer.next_n_bytes(n_of_bytes_needed)?)?;
+        }
     }
 
-    #[test]
-    fn test_immediate_to_memory() {
-        test_instruction(vec![0xC6, 0x03, 0x07], "mov [bp + di], byte 7")
-    }
+    instr.set_prefixes(prefixes);
 
-    #[test]
-    fn test_immediate_add() {
-        test_instruction(vec![0x83, 0xc6, 0x02], "add si, 2")
-    }
+    Ok(())
+}
 
-    #[test]
-    fn test_immediate_add_a() {
-        test_instruction(vec![0x03, 0x18], "add bx, [bx + si]")
-    }
+pub fn disassemble_bytes_in(mut buffer: InstructionBuffer) -> DisassemblyResult<Vec<Instruction>> {
+    let mut instructions: Vec<Instruction> = Vec::new();
 
-    #[test]
-    fn test_immediate_add_b() {
-        test_instruction(vec![0x03, 0x18], "add bx, [bx + si]")
+    while !buffer.is_at_the_end() {
+        instructions.push(disassemble_next_instruction(&mut buffer)?);
     }
 
-    #[test]
-    fn test_immediate_add_c() {
-        test_instruction(vec![0x02, 0x7A, 0x04], "add bh, [bp + si+4]")
-    }
+    label_jump_targets(instructions.iter_mut().collect());
 
-    #[test]
-    fn test_immediate_add_d() {
-        test_instruction(vec![0x05, 0xe8, 0x03], "add ax, 1000")
-    }
+    Ok(instructions)
+}
 
-    #[test]
-    fn test_immediate_sub_a() {
-        test_instruction(vec![0x83, 0xee, 0x02], "sub si, 2")
-    }
+/// Part of the program decoded by the resilient disassembly
+#[derive(Debug)]
+pub enum Decoded {
+    Instruction(Instruction),
+    /// First byte of the instruction that failed to decode
+    Data(u8, DisassemblyError),
+}
 
-    #[test]
-    fn test_immediate_sub_b() {
-        test_instruction(vec![0x2d, 0xe8, 0x03], "sub ax, 1000")
+impl Decoded {
+    /// Position in the program
+    pub fn offset(&self) -> usize {
+        match self {
+            Decoded::Instruction(instr) => instr.offset(),
+            Decoded::Data(_, error) => error.offset,
+        }
     }
+}
 
-    #[test]
-    fn test_immediate_cmp() {
-        test_instruction(vec![0x3b, 0x18], "cmp bx, [bx + si]")
+/// Data is written so that the assembler reproduces the same byte
+impl fmt::Display for Decoded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Decoded::Instruction(instr) => write!(f, "{}", instr),
+            Decoded::Data(byte, error) => write!(f, "db {:#04x} ; {}", byte, error.kind),
+        }
     }
+}
 
-    #[test]
-    fn test_immediate_cmp_a() {
-        test_instruction(vec![0x3d, 0xe8, 0x03], "cmp ax, 1000")
-    }
+/// Undecodable byte is kept as data and decoding resumes at the byte after it
+pub fn disassemble_bytes_resilient(mut buffer: InstructionBuffer) -> Vec<Decoded> {
+    let mut decoded: Vec<Decoded> = Vec::new();
+
+    while !buffer.is_at_the_end() {
+        decoded.push(match disassemble_next_instruction(&mut buffer) {
+            Ok(instr) => Decoded::Instruction(instr),
+            Err(error) => {
+                buffer.last_read = error.offset + 1;
+                Decoded::Data(buffer.buf[error.offset], error)
+            }
+        });
+    }
+
+    label_jump_targets(
+        decoded
+            .iter_mut()
+            .filter_map(|decoded| match decoded {
+                Decoded::Instruction(instr) => Some(instr),
+                Decoded::Data(_, _) => None,
+            })
+            .collect(),
+    );
+
+    decoded
+}
 
-    #[test]
-    fn test_immediate_cmp_b() {
-        test_instruction(vec![0x83, 0x3e, 0xe2, 0x12, 0x1d], "cmp word [4834], 29")
+/// Second pass names targets of jumps in order of their position in the program,
+/// targets that are not at the start of an instruction keep their displacement
+fn label_jump_targets(mut instructions: Vec<&mut Instruction>) {
+    let starts: HashSet<usize> = instructions.iter().map(|instr| instr.offset()).collect();
+
+    let targets: BTreeSet<usize> = instructions
+        .iter()
+        .filter_map(|instr| instr.jump_target())
+        .filter(|target| starts.contains(target))
+        .collect();
+
+    let labels: HashMap<usize, String> = targets
+        .into_iter()
+        .enumerate()
+        .map(|(n, target)| (target, format!("label_{}", n)))
+        .collect();
+
+    for instruction in instructions.iter_mut() {
+        if let Some(label) = labels.get(&instruction.offset()) {
+            instruction.set_label(label.clone());
+        }
+
+        if let Some(label) = instruction
+            .jump_target()
+            .and_then(|target| labels.get(&target))
+        {
+            instruction.set_jump_label(label.clone());
+        }
     }
 }
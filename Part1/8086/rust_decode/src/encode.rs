@@ -0,0 +1,512 @@
+use std::error;
+use std::fmt;
+
+use crate::assembled_instruction::{
+    definitions_of, AssembledInstruction, BitFlag, BitOrder, BitUsage, Bits, Byte, Operation,
+};
+use crate::cpu::cpu::{Access, CpuOperand, EffectiveAddress, Reg, RegPart};
+use crate::disassemble::disassemble_next_instruction;
+use crate::instruction::instruction::{DecodingError, Instruction};
+use crate::instruction::operand::{OperandType, Size};
+use crate::instruction::prefix::{Prefix, Prefixes};
+use crate::InstructionBuffer;
+
+#[derive(Debug)]
+pub enum EncodingError {
+    /// None of the definitions of the operation accepts the arguments
+    UnsupportedArgumentsError(Operation),
+    /// Memory operand without any register needs byte or word specifier
+    MissingSizeError(Operation),
+    DecodingError(DecodingError),
+}
+
+impl fmt::Display for EncodingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnsupportedArgumentsError(operation) => {
+                write!(f, "no encoding of {} accepts the operands", operation)
+            }
+            Self::MissingSizeError(operation) => {
+                write!(f, "{} needs byte or word specifier", operation)
+            }
+            Self::DecodingError(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl error::Error for EncodingError {}
+
+impl From<DecodingError> for EncodingError {
+    fn from(e: DecodingError) -> Self {
+        Self::DecodingError(e)
+    }
+}
+
+/// Operand the way it is written in the assembly
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Argument {
+    Register(Reg, RegPart),
+    /// Memory with the segment override written in front of it
+    Memory(Access, Option<Reg>),
+    Immediate(i32),
+    /// Position of the jump target from the start of the program, negative before it
+    Target(i64),
+    /// Segment and offset of the far jump or call
+    FarPointer(u16, u16),
+}
+
+/// Instruction to be encoded, destination is the first of the arguments
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Statement {
+    pub prefixes: Prefixes,
+    pub operation: Operation,
+    pub arguments: Vec<Argument>,
+    /// Size given by the specifier, registers give the size on their own
+    pub size: Option<Size>,
+}
+
+impl Statement {
+    pub fn new(operation: Operation, arguments: Vec<Argument>) -> Self {
+        Statement {
+            prefixes: Prefixes::default(),
+            operation,
+            arguments,
+            size: None,
+        }
+    }
+
+    /// Immediates are stored unsigned in the size the instruction works with
+    pub fn from_instruction(instr: &Instruction) -> Result<Self, DecodingError> {
+        let (dst, src) = instr.operands_sorted();
+        let mut arguments = vec![];
+
+        for operand in [dst, src] {
+            let argument = match operand.operand_type {
+                None | Some(OperandType::NotUsed) => continue,
+                Some(OperandType::Immediate(_)) => {
+                    let data = operand.signed_data()?;
+
+                    Argument::Immediate(match immediate_size(instr) {
+                        Size::BYTE => data as u8 as i32,
+                        Size::WORD => data as u16 as i32,
+                    })
+                }
+                Some(OperandType::Jump(_)) => Argument::Target(
+                    instr
+                        .jump_position()
+                        .ok_or(DecodingError::FieldNotYetDecodedError)?,
+                ),
+                Some(OperandType::FarPointer) => Argument::FarPointer(
+                    operand.signed_data()? as u16,
+                    operand.signed_displacement()? as u16,
+                ),
+                _ => match operand.parse_for_cpu(instr.size())? {
+                    CpuOperand::Register(reg, part) => Argument::Register(reg, part),
+                    CpuOperand::Memory(access, segment, _) => Argument::Memory(access, segment),
+                    _ => return Err(DecodingError::Error("Unexpected operand".to_string())),
+                },
+            };
+
+            arguments.push(argument);
+        }
+
+        // Memory operand carries the segment override instead of the prefixes
+        let mut prefixes = instr.prefixes();
+        if instr.has_memory_operand() {
+            prefixes.segment = None;
+        }
+
+        // Jumps keep the size of their displacement
+        let size = match dst.operand_type {
+            Some(OperandType::Jump(size)) => Some(size),
+            _ => instr.has_size().then(|| instr.size()),
+        };
+
+        Ok(Statement {
+            prefixes,
+            operation: instr.operation(),
+            arguments,
+            size,
+        })
+    }
+
+    fn has_register(&self) -> bool {
+        self.arguments
+            .iter()
+            .any(|argument| matches!(argument, Argument::Register(..)))
+    }
+
+    fn has_memory(&self) -> bool {
+        self.arguments
+            .iter()
+            .any(|argument| matches!(argument, Argument::Memory(..)))
+    }
+}
+
+/// Byte immediate extended by the S field works with the whole word
+fn immediate_size(instr: &Instruction) -> Size {
+    let (dst, src) = instr.operands_sorted();
+
+    let word = [dst, src].iter().any(|operand| {
+        matches!(
+            operand.operand_type,
+            Some(OperandType::Immediate(Size::WORD))
+        )
+    });
+
+    if word || instr.is_sign_extended() {
+        Size::WORD
+    } else {
+        Size::BYTE
+    }
+}
+
+/// Values chosen for the fields of a single encoding
+#[derive(Debug, Clone, Copy)]
+struct Fields {
+    flags: BitFlag,
+    reg: u8,
+    sr: u8,
+    mode: u8,
+    rm: u8,
+    data: i32,
+    displacement: i32,
+}
+
+impl Fields {
+    fn value_of(&self, bits: &Bits) -> u8 {
+        let part = |value: i32, bit_order: BitOrder| match bit_order {
+            BitOrder::LOW => value as u8,
+            BitOrder::HIGH => (value >> 8) as u8,
+        };
+
+        match bits.usage {
+            BitUsage::LITERAL | BitUsage::Extension => bits.value.unwrap_or(0),
+            BitUsage::Flag(flag) => self.flags.is_flag_toogled(flag) as u8,
+            BitUsage::REG => self.reg,
+            BitUsage::SR => self.sr,
+            BitUsage::MOD => self.mode,
+            BitUsage::RM => self.rm,
+            BitUsage::Data(bit_order) => part(self.data, bit_order),
+            BitUsage::Disp(bit_order) => part(self.displacement, bit_order),
+            BitUsage::Operand(_) | BitUsage::PLACEHOLDER => 0,
+        }
+    }
+
+    fn encode(&self, byte: &Byte) -> u8 {
+        byte.bits.iter().flatten().fold(0, |encoded, bits| {
+            encoded | bits.encode_value(self.value_of(bits))
+        })
+    }
+}
+
+pub(crate) fn register_code(reg: Reg, part: RegPart) -> Option<u8> {
+    let code = match reg {
+        Reg::A | Reg::Es => 0,
+        Reg::C | Reg::Cs => 1,
+        Reg::D | Reg::Ss => 2,
+        Reg::B | Reg::Ds => 3,
+        Reg::Sp => 4,
+        Reg::Bp => 5,
+        Reg::Si => 6,
+        Reg::Di => 7,
+    };
+
+    // Only the first four registers have byte halves
+    match part {
+        RegPart::Whole => Some(code),
+        RegPart::Low if code < 4 => Some(code),
+        RegPart::High if code < 4 => Some(code + 4),
+        _ => None,
+    }
+}
+
+fn is_segment(reg: Reg) -> bool {
+    matches!(reg, Reg::Es | Reg::Cs | Reg::Ss | Reg::Ds)
+}
+
+/// Shortest MOD and RM of the operand with its displacement
+fn mod_rm(argument: &Argument) -> Option<(u8, u8, i32)> {
+    let (rm, displacement) = match argument {
+        Argument::Register(reg, part) if !is_segment(*reg) => {
+            return register_code(*reg, *part).map(|code| (0b11, code, 0));
+        }
+        Argument::Memory(Access::Direct(address), _) => {
+            return Some((0b00, 0b110, *address as i32))
+        }
+        Argument::Memory(Access::Address(address), _) => match *address {
+            EffectiveAddress::BxSi(d) => (0b000, d),
+            EffectiveAddress::BxDi(d) => (0b001, d),
+            EffectiveAddress::BpSi(d) => (0b010, d),
+            EffectiveAddress::BpDi(d) => (0b011, d),
+            EffectiveAddress::Si(d) => (0b100, d),
+            EffectiveAddress::Di(d) => (0b101, d),
+            EffectiveAddress::Bp(d) => (0b110, d),
+            EffectiveAddress::Bx(d) => (0b111, d),
+        },
+        _ => return None,
+    };
+
+    // [bp] without displacement would be the direct address
+    let mode = match displacement {
+        0 if rm != 0b110 => 0b00,
+        -128..=127 => 0b01,
+        _ => 0b10,
+    };
+
+    Some((mode, rm, displacement as i32))
+}
+
+/// Prefixes are written in their order, segment override of the memory operand follows
+/// the others unless its place is known
+fn prefix_bytes(statement: &Statement) -> Vec<u8> {
+    let mut prefixes = statement.prefixes;
+
+    let segment = statement
+        .arguments
+        .iter()
+        .find_map(|argument| match argument {
+            Argument::Memory(_, Some(segment)) => register_code(*segment, RegPart::Whole),
+            _ => None,
+        });
+
+    if segment.is_some() {
+        prefixes.segment = segment;
+    }
+
+    prefixes.in_order().into_iter().map(Prefix::byte).collect()
+}
+
+/// Explicit bits of the first two bytes which are chosen by the encoder
+fn explicit_bits(definition: &AssembledInstruction) -> impl Iterator<Item = Bits> + '_ {
+    definition.bytes[..2]
+        .iter()
+        .flatten()
+        .flat_map(|byte| byte.bits.iter().flatten().copied())
+        .filter(|bits| !bits.is_implied())
+}
+
+/// All values of the fields the definition could be encoded with
+fn candidate_fields(statement: &Statement, definition: &AssembledInstruction) -> Vec<Fields> {
+    let bits: Vec<Bits> = explicit_bits(definition).collect();
+    let has = |usage: BitUsage| bits.iter().any(|bits| bits.is_bit_usage(&usage));
+
+    let flags: Vec<BitFlag> = bits
+        .iter()
+        .filter_map(|bits| match bits.usage {
+            BitUsage::Flag(flag) => Some(flag),
+            _ => None,
+        })
+        .collect();
+
+    let registers: Vec<u8> = statement
+        .arguments
+        .iter()
+        .filter_map(|argument| match argument {
+            Argument::Register(reg, part) if !is_segment(*reg) => register_code(*reg, *part),
+            _ => None,
+        })
+        .collect();
+
+    let segments: Vec<u8> = statement
+        .arguments
+        .iter()
+        .filter_map(|argument| match argument {
+            Argument::Register(reg, _) if is_segment(*reg) => register_code(*reg, RegPart::Whole),
+            _ => None,
+        })
+        .collect();
+
+    let data = statement
+        .arguments
+        .iter()
+        .find_map(|argument| match argument {
+            Argument::Immediate(data) => Some(*data),
+            Argument::FarPointer(segment, _) => Some(*segment as i32),
+            _ => None,
+        });
+
+    let displacement = statement
+        .arguments
+        .iter()
+        .find_map(|argument| match argument {
+            Argument::Memory(Access::Direct(address), _) => Some(*address as i32),
+            Argument::FarPointer(_, offset) => Some(*offset as i32),
+            _ => None,
+        });
+
+    let choices = |needed: bool, values: Vec<u8>| if needed { values } else { vec![0] };
+    let registers = choices(has(BitUsage::REG), registers);
+    let segments = choices(has(BitUsage::SR), segments);
+
+    let mod_rms: Vec<(u8, u8, i32)> = if has(BitUsage::MOD) || has(BitUsage::RM) {
+        statement.arguments.iter().filter_map(mod_rm).collect()
+    } else {
+        vec![(0, 0, displacement.unwrap_or(0))]
+    };
+
+    let mut candidates = vec![];
+
+    for set in 0..1u8 << flags.len() {
+        let flags = flags
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| set >> i & 1 == 1)
+            .fold(BitFlag::NOTHING, |all, (_, flag)| all | *flag);
+
+        for &reg in &registers {
+            for &sr in &segments {
+                for &(mode, rm, displacement) in &mod_rms {
+                    candidates.push(Fields {
+                        flags,
+                        reg,
+                        sr,
+                        mode,
+                        rm,
+                        data: data.unwrap_or(0),
+                        displacement,
+                    });
+                }
+            }
+        }
+    }
+
+    candidates
+}
+
+/// Bytes following the opcode are laid out the way the decoder is going to read them
+fn encode_fields(definition: &AssembledInstruction, fields: &Fields) -> Option<Vec<u8>> {
+    let first_byte = fields.encode(definition.bytes[0].as_ref()?);
+    let mut instr = Instruction::new(first_byte).ok()?;
+    let mut bytes = vec![first_byte];
+
+    if !instr.is_decoded() {
+        let second_byte = fields.encode(definition.bytes[1].as_ref()?);
+        instr.continue_disassembly(second_byte).ok()?;
+        bytes.push(second_byte);
+
+        for byte in instr.bytes_to_finalize() {
+            bytes.push(fields.encode(&byte));
+        }
+    }
+
+    Some(bytes)
+}
+
+fn fits(value: i32, size: Size) -> bool {
+    match size {
+        Size::BYTE => (-0x80..=0xFF).contains(&value),
+        Size::WORD => (-0x8000..=0xFFFF).contains(&value),
+    }
+}
+
+/// Decoder is the judge of whether the bytes mean what the statement says
+fn decodes_to(bytes: &[u8], statement: &Statement, offset: usize) -> bool {
+    let mut buffer = InstructionBuffer::from_bytes(bytes);
+
+    let Ok(mut instr) = disassemble_next_instruction(&mut buffer) else {
+        return false;
+    };
+
+    if buffer.position() != bytes.len() {
+        return false;
+    }
+
+    instr.set_position(offset, bytes.len());
+
+    let Ok(decoded) = Statement::from_instruction(&instr) else {
+        return false;
+    };
+
+    let size = immediate_size(&instr);
+    let arguments_match =
+        statement.arguments.len() == decoded.arguments.len()
+            && statement.arguments.iter().zip(&decoded.arguments).all(
+                |(requested, decoded)| match (requested, decoded) {
+                    (Argument::Immediate(requested), Argument::Immediate(decoded)) => {
+                        let mask = match size {
+                            Size::BYTE => 0xFF,
+                            Size::WORD => 0xFFFF,
+                        };
+
+                        fits(*requested, size) && requested & mask == *decoded
+                    }
+                    (requested, decoded) => requested == decoded,
+                },
+            );
+
+    let size_matches = match (statement.size, decoded.size) {
+        (Some(requested), Some(decoded)) => requested == decoded,
+        _ => true,
+    };
+
+    let prefixes_match = statement.prefixes.lock == decoded.prefixes.lock
+        && statement.prefixes.repeat == decoded.prefixes.repeat
+        && statement.prefixes.segment == decoded.prefixes.segment;
+
+    decoded.operation == statement.operation && arguments_match && size_matches && prefixes_match
+}
+
+/// Shortest encoding of the statement placed at the offset, earlier definitions win ties
+pub fn encode(statement: &Statement, offset: usize) -> Result<Vec<u8>, EncodingError> {
+    let prefixes = prefix_bytes(statement);
+    let target = statement
+        .arguments
+        .iter()
+        .find_map(|argument| match argument {
+            Argument::Target(target) => Some(*target),
+            _ => None,
+        });
+
+    let mut best: Option<(Vec<u8>, &AssembledInstruction)> = None;
+
+    for definition in definitions_of(statement.operation) {
+        for mut fields in candidate_fields(statement, definition) {
+            let Some(mut bytes) = encode_fields(definition, &fields) else {
+                continue;
+            };
+
+            // Jumps are relative to the end of the instruction
+            if let Some(target) = target {
+                let end = (offset + prefixes.len() + bytes.len()) as i64;
+                fields.displacement = (target - end) as i32;
+
+                match encode_fields(definition, &fields) {
+                    Some(relative) => bytes = relative,
+                    None => continue,
+                }
+            }
+
+            let bytes = [prefixes.as_slice(), &bytes].concat();
+
+            let shorter = best
+                .as_ref()
+                .is_none_or(|(best, _)| bytes.len() < best.len());
+            if shorter && decodes_to(&bytes, statement, offset) {
+                best = Some((bytes, definition));
+            }
+        }
+    }
+
+    let (bytes, definition) = best.ok_or(EncodingError::UnsupportedArgumentsError(
+        statement.operation,
+    ))?;
+
+    let explicit_size =
+        explicit_bits(definition).any(|bits| bits.is_bit_usage(&BitUsage::Flag(BitFlag::W)));
+
+    if explicit_size
+        && statement.size.is_none()
+        && statement.has_memory()
+        && !statement.has_register()
+    {
+        return Err(EncodingError::MissingSizeError(statement.operation));
+    }
+
+    Ok(bytes)
+}
+
+/// Decoded instruction encoded again at its own offset
+pub fn encode_instruction(instr: &Instruction) -> Result<Vec<u8>, EncodingError> {
+    encode(&Statement::from_instruction(instr)?, instr.offset())
+}
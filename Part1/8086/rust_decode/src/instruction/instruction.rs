@@ -1,8 +1,7 @@
 use super::operand::{Displacement, Operand, OperandType, OperandTypeError, Size};
+use super::prefix::Prefixes;
 use crate::assembled_instruction::*;
-use core::panic;
 use std::fmt;
-use std::iter::IntoIterator;
 
 #[derive(Debug)]
 pub enum DecodingError {
@@ -11,9 +10,41 @@ pub enum DecodingError {
     FieldNotYetDecodedError,
     InstructionNotRecognizedError(String),
     UnexpectedDecodedValueError(u8),
+    UnknownOpcodeError(u8),
+    UnknownExtensionError(u8, u8),
+    /// Register given by the mod=11 form of an instruction that works only with memory
+    MemoryOperandRequiredError(u8, Option<u8>),
     Error(String),
 }
 
+impl fmt::Display for DecodingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidBitUsageError(message)
+            | Self::InstructionNotRecognizedError(message)
+            | Self::Error(message) => write!(f, "{}", message),
+            Self::FieldAlreadyDecodedError => write!(f, "field decoded twice"),
+            Self::FieldNotYetDecodedError => write!(f, "field used before it was decoded"),
+            Self::UnexpectedDecodedValueError(value) => {
+                write!(f, "unexpected decoded value {:#b}", value)
+            }
+            Self::UnknownOpcodeError(opcode) => write!(f, "unknown opcode {:#04X}", opcode),
+            Self::UnknownExtensionError(opcode, extension) => {
+                write!(f, "unknown opcode {:#04X} /{}", opcode, extension)
+            }
+            Self::MemoryOperandRequiredError(opcode, extension) => {
+                write!(f, "opcode {:#04X}", opcode)?;
+
+                if let Some(extension) = extension {
+                    write!(f, " /{}", extension)?;
+                }
+
+                write!(f, " needs memory operand")
+            }
+        }
+    }
+}
+
 impl From<AssembledInstructionLookupError> for DecodingError {
     fn from(_e: AssembledInstructionLookupError) -> Self {
         DecodingError::InstructionNotRecognizedError("Could not find instruction".to_string())
@@ -26,47 +57,55 @@ impl From<OperandTypeError> for DecodingError {
     }
 }
 
+const NOT_USED: Operand = Operand {
+    operand_type: Some(OperandType::NotUsed),
+    value: None,
+    displacement: None,
+    data: None,
+    segment: None,
+};
+
 #[derive(Debug)]
 pub struct Instruction {
     operand_a: Option<Operand>,
     operand_b: Option<Operand>,
     flags: BitFlag,
-    ass_instr: AssembledInstruction,
+    opcode: u8,
+    bytes_decoded: u8,
+    ass_instr: &'static AssembledInstruction,
+    prefixes: Prefixes,
+    offset: usize,
+    length: usize,
+    label: Option<String>,
+    jump_label: Option<String>,
 }
 
 impl Instruction {
     pub fn new(byte: u8) -> Result<Self, DecodingError> {
-        let ass_instr = get_assembled_instruction(byte)?;
-        let first_byte: Byte = ass_instr.bytes[0].unwrap();
+        let ass_instr = get_assembled_instruction(byte).map_err(|e| match e {
+            AssembledInstructionLookupError::InstructionUndefinedError => {
+                DecodingError::UnknownOpcodeError(byte)
+            }
+            e => e.into(),
+        })?;
+        let first_byte: Byte =
+            ass_instr.bytes[0].ok_or(AssembledInstructionLookupError::IncompleteDefinitionError)?;
 
         let mut instr = Instruction {
             operand_a: None,
             operand_b: None,
             flags: BitFlag::NOTHING,
+            opcode: byte,
+            bytes_decoded: 0,
             ass_instr,
+            prefixes: Prefixes::default(),
+            offset: 0,
+            length: 1,
+            label: None,
+            jump_label: None,
         };
 
-        let mut lit_val: Option<u8> = None;
-        // In First byte only flags, reg, and Literal bits are expeected
-        for bits in first_byte.bits.iter().flatten() {
-            let decoded_value: u8 = bits.decode_value(byte);
-
-            match bits.usage {
-                BitUsage::LITERAL => Ok(lit_val = Some(decoded_value)),
-                BitUsage::Flag(flag) => instr.set_flag(flag, decoded_value),
-                BitUsage::REG => instr.set_reg_operand(decoded_value),
-                u => Err(
-                    DecodingError::InvalidBitUsageError(
-                        format!(
-                        "Invalid BitUsage {:?}\nOnly Literal, Flag and Reg Field are expected in first byte", u
-                    )
-                    )
-                ),
-            }?;
-        }
-
-        // flags must be resolved before this
-        lit_val.map(|l_val| instr.handle_literal_in_first_byte(l_val));
+        instr.decode_byte(first_byte, byte)?;
 
         Ok(instr)
     }
@@ -75,218 +114,362 @@ impl Instruction {
         self.ass_instr.operation
     }
 
+    pub fn prefixes(&self) -> Prefixes {
+        self.prefixes
+    }
+
+    pub fn size(&self) -> Size {
+        Size::new(self.flags)
+    }
+
+    /// Instructions without W field have no size, e.g. jumps
+    pub fn has_size(&self) -> bool {
+        self.ass_instr.includes_bits(W)
+    }
+
+    /// Byte immediate extended to word by the S field
+    pub fn is_sign_extended(&self) -> bool {
+        self.flags.is_flag_toogled(BitFlag::S)
+    }
+
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn length(&self) -> usize {
+        self.length
+    }
+
+    /// Position of the instruction in the program including its prefixes
+    pub fn set_position(&mut self, offset: usize, length: usize) {
+        self.offset = offset;
+        self.length = length;
+    }
+
+    /// Relative jumps are counted from the end of the instruction
+    pub fn jump_position(&self) -> Option<i64> {
+        let (dst, _) = self.operands_sorted();
+
+        match dst.operand_type {
+            Some(OperandType::Jump(_)) => {
+                let end = (self.offset + self.length) as i64;
+                Some(end + dst.signed_displacement().ok()? as i64)
+            }
+            _ => None,
+        }
+    }
+
+    /// Position of the jump target when it is not before the start of the program
+    pub fn jump_target(&self) -> Option<usize> {
+        self.jump_position()?.try_into().ok()
+    }
+
+    pub fn label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+
+    /// Label defined at this instruction
+    pub fn set_label(&mut self, label: String) {
+        self.label = Some(label);
+    }
+
+    /// Label printed instead of the displacement of the jump
+    pub fn set_jump_label(&mut self, label: String) {
+        self.jump_label = Some(label);
+    }
+
+    /// Single byte instructions are fully decoded by `Instruction::new`
+    pub fn is_decoded(&self) -> bool {
+        self.ass_instr
+            .bytes
+            .get(self.bytes_decoded as usize)
+            .is_none_or(|byte| byte.is_none())
+    }
+
     pub fn continue_disassembly(&mut self, byte: u8) -> Result<usize, DecodingError> {
+        let extension = self.ass_instr.extension_in(byte);
+
+        // Operation of the opcode group is known only once its second byte is read
+        if let Some(extension) = extension {
+            self.ass_instr = get_assembled_instruction_in_group(self.opcode, extension).map_err(
+                |e| match e {
+                    AssembledInstructionLookupError::InstructionUndefinedError => {
+                        DecodingError::UnknownExtensionError(self.opcode, extension)
+                    }
+                    e => e.into(),
+                },
+            )?;
+        }
+
         let second_byte: Byte = self.ass_instr.bytes[1]
             .ok_or(DecodingError::InvalidBitUsageError("Exp".to_string()))?;
 
+        self.decode_byte(second_byte, byte)?;
+
+        // Addresses are loaded from memory or computed from it, registers have neither
+        let needs_memory = matches!(
+            self.operation(),
+            Operation::LEA | Operation::LDS | Operation::LES
+        ) || (self.operation().is_far() && self.ass_instr.includes_bits(MOD));
+
+        if needs_memory && byte >> 6 == 0b11 {
+            return Err(DecodingError::MemoryOperandRequiredError(
+                self.opcode,
+                extension,
+            ));
+        }
+
+        Ok(self.bytes_to_finalize().len())
+    }
+
+    fn decode_byte(&mut self, byte_expected: Byte, byte_given: u8) -> Result<(), DecodingError> {
         let mut mode: Option<u8> = None;
         let mut rm: Option<u8> = None;
 
-        // In First byte only flags, reg, and Literal bits are expeected
-        for bits in second_byte.bits.iter().flatten() {
-            let decoded_value = bits.decode_value(byte);
+        for bits in byte_expected.bits.iter().flatten() {
+            let decoded_value = bits.decode_value(byte_given);
 
             match bits.usage {
-                BitUsage::LITERAL => self.handle_literal_in_second_byte(decoded_value),
+                BitUsage::LITERAL => self.handle_literal(bits, decoded_value, byte_given),
+                BitUsage::Extension => Ok(()),
                 BitUsage::Flag(flag) => self.set_flag(flag, decoded_value),
                 BitUsage::REG => self.set_reg_operand(decoded_value),
-                BitUsage::Data(bit_order) => self.set_immediate_operand(Some(decoded_value.into()), bit_order),
+                BitUsage::SR => self.set_segment_reg_operand(decoded_value),
+                BitUsage::Data(bit_order) => self.set_immediate_operand(decoded_value, bit_order),
                 BitUsage::Disp(bit_order) => self.set_displacement(decoded_value, bit_order),
-                BitUsage::RM => Ok(rm = Some(decoded_value)),
-                BitUsage::MOD => Ok(mode = Some(decoded_value)),
-                u => return Err(
-                    DecodingError::InvalidBitUsageError(
-                        format!(
-                        "Invalid BitUsage {:?}\nOnly Literal, Flag, Reg, Rm, Mod fields are expected in second byte", u
-                    )
-                    )
-                ),
+                BitUsage::RM => {
+                    rm = Some(decoded_value);
+                    Ok(())
+                }
+                BitUsage::MOD => {
+                    mode = Some(decoded_value);
+                    Ok(())
+                }
+                BitUsage::Operand(implied) => self.set_implied_operand(implied),
+                u => Err(DecodingError::InvalidBitUsageError(format!(
+                    "Invalid BitUsage {:?}\nIt is not expected in any of instruction bytes",
+                    u
+                ))),
             }?;
         }
 
-        self.set_rm_operand(rm, mode)?;
+        self.bytes_decoded += 1;
 
-        Ok(self.additional_byte_count().into())
-    }
-
-    fn handle_literal_in_first_byte(&mut self, decoded_value: u8) -> Result<(), DecodingError> {
-        match decoded_value {
-            0b1010000 => {
-                self.set_reg_operand(0)?;
-                self.set_rm_operand(Some(0b110), Some(0b00))?; // direct acces
-                self.set_flag(BitFlag::D, 1)
-            }
-            0b1010001 => {
-                self.set_reg_operand(0)?;
-                self.set_rm_operand(Some(0b110), Some(0b00)) // direct acces
-            }
-            0b0000010 => {
-                self.set_reg_operand(0)?;
-                self.set_immediate_operand(None, BitOrder::LOW)
-            }
-            0b0010110 => {
-                self.set_reg_operand(0)?;
-                self.set_immediate_operand(None, BitOrder::LOW)
-            }
-            0b0011110 => {
-                self.set_reg_operand(0)?;
-                self.set_immediate_operand(None, BitOrder::LOW)
-            }
-            _ => Ok(()),
-        }
+        self.set_rm_operand(rm, mode)
     }
 
-    fn handle_literal_in_second_byte(&mut self, decoded_value: u8) -> Result<(), DecodingError> {
-        // Decide whether to use ADD, SUB, CMP for instruction beginning with 0b100000 literal
-        if self.ass_instr.bytes[0]
-            .map(|byte| byte.bits[0].map(|bit| bit.value).unwrap())
-            .unwrap()
-            .unwrap()
-            == 0b100000
-        {
-            match decoded_value {
-                0b000 => {
-                    self.ass_instr = *ADD_INSTR;
-                }
-                0b101 => {
-                    self.ass_instr = *SUB_INSTR;
-                }
-                0b111 => {
-                    self.ass_instr = *CMP_INSTR;
-                }
-                _ => (),
-            }
+    /// Literal of the first byte was already matched during the instruction lookup,
+    /// literals of the other bytes are reserved bits of the reg field e.g. in MOV to segment
+    /// register, so the encoding is reported the same way as an unknown extension
+    fn handle_literal(
+        &self,
+        bits: &Bits,
+        decoded_value: u8,
+        byte_given: u8,
+    ) -> Result<(), DecodingError> {
+        match bits.value {
+            _ if self.bytes_decoded == 0 => Ok(()),
+            Some(value) if value == decoded_value => Ok(()),
+            _ => Err(DecodingError::UnknownExtensionError(
+                self.opcode,
+                byte_given >> 3 & 0b111,
+            )),
         }
-
-        self.set_immediate_operand(None, BitOrder::LOW)
     }
 
     fn should_process_bits(&self, bits: Bits) -> bool {
         match bits.usage {
             BitUsage::Data(bit_order) => match bit_order {
                 BitOrder::LOW => true,
-                BitOrder::HIGH => self.flags & BitFlag::W == BitFlag::W,
+                BitOrder::HIGH => {
+                    self.flags.is_flag_toogled(BitFlag::W)
+                        && !self.flags.is_flag_toogled(BitFlag::S)
+                }
             },
             BitUsage::Disp(bit_order) => {
+                // Displacement of jumps and calls is always present
+                if !self.ass_instr.includes_bits(MOD) {
+                    return true;
+                }
+
                 let type_b = self
                     .operand_b
                     .as_ref()
-                    .unwrap()
-                    .operand_type
-                    .as_ref()
-                    .unwrap();
+                    .and_then(|operand| operand.operand_type.as_ref());
 
                 match type_b {
-                    OperandType::Register(_) => false,
-                    OperandType::Memory(Displacement::NO) => false,
-                    OperandType::Memory(Displacement::YES(size)) => match bit_order {
+                    Some(OperandType::Memory(Displacement::YES(size))) => match bit_order {
                         BitOrder::LOW => true,
                         BitOrder::HIGH => matches!(size, Size::WORD),
                     },
-                    OperandType::DirectAccess(_) => true,
-                    _ => panic!("Expected only memory Operand"),
+                    Some(OperandType::DirectAccess(_)) => true,
+                    _ => false,
                 }
             }
             _ => true,
         }
     }
 
-    fn bytes_to_finalize(&self) -> Vec<Byte> {
+    pub(crate) fn bytes_to_finalize(&self) -> Vec<Byte> {
         let mut a: Vec<Byte> = vec![];
 
-        for byte in self.ass_instr.bytes[2..].into_iter() {
-            match byte {
-                Some(b) => {
-                    if self.should_process_bits(b.bits[0].unwrap()) {
-                        a.push(*b);
-                    }
-                }
-                None => (),
+        for byte in self.ass_instr.bytes[self.bytes_decoded as usize..]
+            .iter()
+            .flatten()
+        {
+            if byte.bits[0].is_some_and(|bits| self.should_process_bits(bits)) {
+                a.push(*byte);
             }
         }
 
         a
     }
 
-    pub fn finalize_disassembly(&mut self, bytes_given: Vec<u8>) -> Result<(), DecodingError> {
+    pub fn finalize_disassembly(&mut self, bytes_given: &[u8]) -> Result<(), DecodingError> {
         let instruction_bytes = self.bytes_to_finalize();
 
         for (byte_given, byte_expected) in bytes_given.iter().zip(instruction_bytes) {
             for bits in byte_expected.bits.iter().flatten() {
                 let decoded_value = bits.decode_value(*byte_given);
 
-                if self.should_process_bits(*bits) {
-                    match bits.usage {
-                        BitUsage::Data(bit_order) => {
-                            self.set_immediate_operand(Some(decoded_value.into()), bit_order)
-                        }
-                        BitUsage::Disp(bit_order) => self.set_displacement(decoded_value, bit_order),
-                        u => Err(
-                            DecodingError::InvalidBitUsageError(
-                                format!(
-                                "Invalid BitUsage {:?}\nOnly Data or Displacement fields expctded on rest of bytes", u
-                                )
+                match bits.usage {
+                    BitUsage::Data(bit_order) => {
+                        self.set_immediate_operand(decoded_value, bit_order)
+                    }
+                    BitUsage::Disp(bit_order) => self.set_displacement(decoded_value, bit_order),
+                    u => Err(
+                        DecodingError::InvalidBitUsageError(
+                            format!(
+                            "Invalid BitUsage {:?}\nOnly Data or Displacement fields expctded on rest of bytes", u
                             )
-                        ),
-                    }?;
-                }
+                        )
+                    ),
+                }?;
             }
         }
         Ok(())
     }
 
     fn set_flag(&mut self, flag: BitFlag, value: u8) -> Result<(), DecodingError> {
-        if value == 1 {
-            Ok(self.flags = self.flags | flag)
-        } else if value == 0 {
-            Ok(())
-        } else {
-            Err(DecodingError::UnexpectedDecodedValueError(value))
+        match value {
+            1 => self.flags |= flag,
+            0 => (),
+            _ => return Err(DecodingError::UnexpectedDecodedValueError(value)),
+        }
+
+        // V flag decides whether shifts and rotates are by 1 or by CL
+        if flag == BitFlag::V {
+            return self.set_implied_operand(match value {
+                1 => ImpliedOperand::Cl,
+                _ => ImpliedOperand::One,
+            });
         }
+
+        Ok(())
     }
 
     fn set_rm_operand(&mut self, rm: Option<u8>, mode: Option<u8>) -> Result<(), DecodingError> {
         match (rm, mode) {
             (None, None) => Ok(()),
             (Some(rm), Some(mode)) => match &self.operand_b {
-                Some(_) => panic!(),
-                None => Ok(self.operand_b = Some(Operand::rm(rm, mode, self.flags)?)),
+                Some(_) => Err(DecodingError::FieldAlreadyDecodedError),
+                None => {
+                    self.operand_b = Some(Operand::rm(rm, mode, self.flags)?);
+                    Ok(())
+                }
             },
-            _ => panic!("Both RM and mode needs to be specifed"),
+            _ => Err(DecodingError::Error(
+                "Both RM and mode need to be specified".to_string(),
+            )),
         }
     }
 
     fn set_reg_operand(&mut self, reg: u8) -> Result<(), DecodingError> {
         match &self.operand_a {
-            Some(_) => panic!(),
-            None => Ok(self.operand_a = Some(Operand::reg(reg, self.flags)?)),
+            Some(_) => Err(DecodingError::FieldAlreadyDecodedError),
+            None => {
+                self.operand_a = Some(Operand::reg(reg, Size::new(self.flags)));
+                Ok(())
+            }
         }
     }
 
-    fn additional_byte_count(&self) -> u8 {
-        self.operand_a
-            .as_ref()
-            .map(|op| op.n_bytes_needed(self.flags, &self.ass_instr))
-            .unwrap()
-            + self
-                .operand_b
-                .as_ref()
-                .map(|op| op.n_bytes_needed(self.flags, &self.ass_instr))
-                .unwrap()
+    fn set_segment_reg_operand(&mut self, sr: u8) -> Result<(), DecodingError> {
+        match &self.operand_a {
+            Some(_) => Err(DecodingError::FieldAlreadyDecodedError),
+            None => {
+                self.operand_a = Some(Operand::segment_reg(sr));
+                Ok(())
+            }
+        }
+    }
+
+    /// Prefixes are decoded before the instruction and applied once it is complete
+    pub fn set_prefixes(&mut self, prefixes: Prefixes) {
+        self.prefixes = prefixes;
+
+        if let Some(sr) = prefixes.segment {
+            self.set_segment_override(sr);
+        }
+    }
+
+    /// Segment override prefix applies to the memory operand of the instruction. String
+    /// instructions and XLAT use it for their implicit memory operand, 8086 ignores it on
+    /// the other instructions, so it stays a prefix
+    fn set_segment_override(&mut self, sr: u8) {
+        let operand = [self.operand_a.as_mut(), self.operand_b.as_mut()]
+            .into_iter()
+            .flatten()
+            .find(|operand| operand.is_memory());
+
+        if let Some(operand) = operand {
+            operand.segment = Some(sr);
+        }
+    }
+
+    /// Memory operand is written with the segment override instead of the prefixes
+    pub fn has_memory_operand(&self) -> bool {
+        [self.operand_a.as_ref(), self.operand_b.as_ref()]
+            .into_iter()
+            .flatten()
+            .any(Operand::is_memory)
+    }
+
+    fn set_implied_operand(&mut self, implied: ImpliedOperand) -> Result<(), DecodingError> {
+        let operand = match implied {
+            ImpliedOperand::Dx => Operand::reg(0b010, Size::WORD),
+            ImpliedOperand::Cl => Operand::reg(0b001, Size::BYTE),
+            ImpliedOperand::One => Operand::immediate(1, Size::BYTE),
+        };
+
+        match (&self.operand_a, &self.operand_b) {
+            (None, _) => {
+                self.operand_a = Some(operand);
+                Ok(())
+            }
+            (_, None) => {
+                self.operand_b = Some(operand);
+                Ok(())
+            }
+            _ => Err(DecodingError::FieldAlreadyDecodedError),
+        }
     }
 
     pub fn operands_sorted(&self) -> (&Operand, &Operand) {
         let (dst, src): (&Operand, &Operand);
 
-        let op_a = self.operand_a.as_ref().unwrap();
-        let op_b = self.operand_b.as_ref().unwrap();
-
-        let b_type = op_b.operand_type.as_ref().unwrap();
+        let op_a = self.operand_a.as_ref().unwrap_or(&NOT_USED);
+        let op_b = self.operand_b.as_ref().unwrap_or(&NOT_USED);
 
-        if self.flags.is_flag_toogled(BitFlag::D) {
+        if matches!(op_b.operand_type, Some(OperandType::NotUsed)) {
             src = op_b;
             dst = op_a;
-        } else if matches!(b_type, OperandType::Immediate(_)) {
+        } else if matches!(op_a.operand_type, Some(OperandType::NotUsed)) {
+            src = op_a;
+            dst = op_b;
+        } else if self.flags.is_flag_toogled(BitFlag::D)
+            || matches!(op_b.operand_type, Some(OperandType::Immediate(_)))
+        {
             src = op_b;
             dst = op_a;
         } else {
@@ -302,111 +485,182 @@ impl Instruction {
         displacement_decoded: u8,
         bit_order: BitOrder,
     ) -> Result<(), DecodingError> {
-        match (&self.operand_a, &self.operand_b) {
-            (None, None) => {
-                let (operand_a, operand_b) = Operand::jump_operands(displacement_decoded);
-                self.operand_a = Some(operand_a);
-                self.operand_b = Some(operand_b);
-                return Ok(());
-            }
-            _ => (),
+        if let (None, None) = (&self.operand_a, &self.operand_b) {
+            self.operand_a = Some(if self.ass_instr.includes_bits(DATA_HI) {
+                Operand::far_pointer(displacement_decoded)
+            } else if self.ass_instr.includes_bits(DISP_HI) {
+                Operand::jump(displacement_decoded, Size::WORD)
+            } else {
+                Operand::jump(displacement_decoded, Size::BYTE)
+            });
+
+            return Ok(());
         }
 
-        let operand_b = self.operand_b.as_mut().expect("Operand must be set");
-        let operand_a = self.operand_a.as_mut().expect("Operand Must be set");
-        let operand_b_type = operand_b
-            .operand_type
-            .as_ref()
-            .expect("operand type must be known");
-        let operand_a_type = operand_a.operand_type.as_ref().unwrap();
+        let operand = [self.operand_a.as_mut(), self.operand_b.as_mut()]
+            .into_iter()
+            .flatten()
+            .find(|operand| {
+                matches!(
+                    operand.operand_type,
+                    Some(
+                        OperandType::Memory(_)
+                            | OperandType::DirectAccess(_)
+                            | OperandType::Jump(_)
+                            | OperandType::FarPointer
+                    )
+                )
+            })
+            .ok_or(DecodingError::Error(
+                "No opearnds are memory cannot set displacement".to_string(),
+            ))?;
 
-        match (operand_b_type, operand_a_type) {
-            (OperandType::Memory(_), OperandType::Memory(_)) => {
-                panic!("Both operands are memory, aborting")
-            }
-            (OperandType::Memory(_), _) => {
-                operand_b.set_displacement(displacement_decoded, bit_order)
-            }
-            (_, OperandType::Memory(_)) => {
-                operand_a.set_displacement(displacement_decoded, bit_order)
-            }
-            (OperandType::DirectAccess(_), _) => {
-                operand_b.set_displacement(displacement_decoded, bit_order)
-            }
-            (_, OperandType::DirectAccess(_)) => {
-                operand_a.set_displacement(displacement_decoded, bit_order)
-            }
-            (_, _) => panic!("No opearnds are memory cannot set displacement"),
-        }
+        operand.set_displacement(displacement_decoded, bit_order)
     }
+
     fn set_immediate_operand(
         &mut self,
-        data: Option<u8>,
+        data: u8,
         bit_order: BitOrder,
     ) -> Result<(), DecodingError> {
+        let far_pointer = [self.operand_a.as_mut(), self.operand_b.as_mut()]
+            .into_iter()
+            .flatten()
+            .find(|operand| matches!(operand.operand_type, Some(OperandType::FarPointer)));
+
+        // Segment of the far pointer is stored as its data
+        if let Some(operand) = far_pointer {
+            return operand.set_data(data, bit_order);
+        }
+
+        if bit_order == BitOrder::HIGH {
+            return self.set_data(data, bit_order);
+        }
+
+        let size =
+            if self.flags.is_flag_toogled(BitFlag::S) || !self.ass_instr.includes_bits(DATA_HI) {
+                Size::BYTE
+            } else {
+                Size::new(self.flags)
+            };
+
         match (&self.operand_a, &self.operand_b) {
-            (Some(_), None) => Ok(self.operand_b = Some(Operand::immediate(data, self.flags)?)),
-            (None, Some(_)) => Ok(self.operand_a = Some(Operand::immediate(data, self.flags)?)),
-            (None, None) => Ok(self.operand_a = Some(Operand::immediate(data, self.flags)?)),
-            _ => self.set_data(
-                data.expect("Both operands are set tryied to set data but it was none"),
-                bit_order,
-            ),
+            (None, _) => {
+                self.operand_a = Some(Operand::immediate(data, size));
+                Ok(())
+            }
+            (_, None) => {
+                self.operand_b = Some(Operand::immediate(data, size));
+                Ok(())
+            }
+            _ => Err(DecodingError::FieldAlreadyDecodedError),
         }
     }
 
     fn set_data(&mut self, data: u8, bit_order: BitOrder) -> Result<(), DecodingError> {
-        let operand_b = self.operand_b.as_mut().expect("Operand must be set");
-        let operand_a = self.operand_a.as_mut().expect("Operand Must be set");
-        let operand_b_type = operand_b.operand_type.as_ref().unwrap();
-        let operand_a_type = operand_a.operand_type.as_ref().unwrap();
-
-        match (operand_a_type, operand_b_type) {
-            (OperandType::Immediate(_), OperandType::Immediate(_)) => panic!(),
-            (OperandType::Immediate(_), _) => Ok(operand_a.set_data(data, bit_order).unwrap()),
-            (_, OperandType::Immediate(_)) => Ok(operand_b.set_data(data, bit_order).unwrap()),
-            (_, _) => panic!("No opearnds are immediate cannot set data"),
+        let operand = [self.operand_a.as_mut(), self.operand_b.as_mut()]
+            .into_iter()
+            .flatten()
+            .find(|operand| matches!(operand.operand_type, Some(OperandType::Immediate(_))))
+            .ok_or(DecodingError::Error(
+                "No opearnds are immediate cannot set data".to_string(),
+            ))?;
+
+        operand.set_data(data, bit_order)
+    }
+
+    fn size_specifier(&self, dst: &Operand, src: &Operand) -> Option<String> {
+        if !(dst.is_memory() || src.is_memory()) {
+            return None;
+        }
+
+        if self.operation().is_far() {
+            return Some("far".to_string());
         }
+
+        // Count of shifts and rotates does not tell the size of the operation
+        let size_known =
+            !self.ass_instr.includes_bits(V) && (dst.is_register() || src.is_register());
+
+        (!size_known).then(|| Size::new(self.flags).to_string())
     }
 }
 
 impl fmt::Display for Instruction {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Segment override is printed with the memory operand when there is one
+        let mut prefixes = self.prefixes;
+        if self.has_memory_operand() {
+            prefixes.segment = None;
+        }
+
+        write!(f, "{}", prefixes)?;
+
+        if self.operation().is_string() {
+            let suffix = match Size::new(self.flags) {
+                Size::BYTE => "b",
+                Size::WORD => "w",
+            };
+
+            return write!(f, "{}{}", self.operation(), suffix);
+        }
+
         let (dst, src) = self.operands_sorted();
 
         let mut src_size: String = "".to_string();
         let mut dst_size: String = "".to_string();
 
-        match (
-            dst.operand_type.as_ref().unwrap(),
-            src.operand_type.as_ref().unwrap(),
-        ) {
-            (OperandType::Memory(_), OperandType::Immediate(size)) => {
-                if self.operation() == Operation::MOV {
-                    src_size = format!("{:} ", size)
-                } else {
-                    dst_size = format!("{:} ", size)
-                }
-            }
-            (OperandType::DirectAccess(_), OperandType::Immediate(size)) => {
-                if self.operation() == Operation::CMP {
-                    dst_size = format!("{:} ", size)
-                } else {
-                    src_size = format!("{:} ", size)
-                }
+        if let Some(size) = self.size_specifier(dst, src) {
+            if self.operation() == Operation::MOV {
+                src_size = format!("{:} ", size)
+            } else {
+                dst_size = format!("{:} ", size)
             }
-            _ => (),
-        };
+        }
 
-        write!(
-            f,
-            "{} {}{}, {}{}",
-            self.operation(),
-            dst_size,
-            dst,
-            src_size,
-            src
-        )
+        // Jumps without label are written relative to the start of the instruction
+        if let Some(OperandType::Jump(size)) = dst.operand_type {
+            let displacement = dst.signed_displacement().map_err(|_| fmt::Error)? as i64;
+
+            // Assemblers shorten near jumps whose target is in reach of the short one, which
+            // is one byte shorter
+            let near = self.operation() == Operation::JMP
+                && size == Size::WORD
+                && i8::try_from(displacement + 1).is_ok();
+            let distance = if near { "near " } else { "" };
+
+            return match &self.jump_label {
+                Some(label) => write!(f, "{} {}{}", self.operation(), distance, label),
+                None => write!(
+                    f,
+                    "{} {}${:+}",
+                    self.operation(),
+                    distance,
+                    self.length as i64 + displacement
+                ),
+            };
+        }
+
+        // AAM and AAD are always assembled with base 10 when written without operand
+        let default_operand = matches!(self.operation(), Operation::AAM | Operation::AAD)
+            && dst.signed_data().ok() == Some(10);
+
+        match (&dst.operand_type, &src.operand_type) {
+            (Some(OperandType::NotUsed), _) => write!(f, "{}", self.operation()),
+            _ if default_operand => write!(f, "{}", self.operation()),
+            (_, Some(OperandType::NotUsed)) => {
+                write!(f, "{} {}{}", self.operation(), dst_size, dst)
+            }
+            _ => write!(
+                f,
+                "{} {}{}, {}{}",
+                self.operation(),
+                dst_size,
+                dst,
+                src_size,
+                src
+            ),
+        }
     }
 }
 
@@ -481,4 +735,22 @@ mod test {
 
         assert_eq!(format!("{}", instr), "mov bx, cx")
     }
+
+    #[test]
+    fn test_single_byte_instruction() {
+        let instr = Instruction::new(0b01010011).unwrap();
+
+        assert!(instr.is_decoded());
+        assert_eq!(format!("{}", instr), "push bx")
+    }
+
+    #[test]
+    fn test_group_instruction_continue_disassembly() {
+        let mut instr = Instruction::new(0b11110111).unwrap();
+
+        let bytes_needed = instr.continue_disassembly(0b00011111).unwrap();
+
+        assert!(matches!(instr.operation(), Operation::NEG));
+        assert_eq!(bytes_needed, 0);
+    }
 }
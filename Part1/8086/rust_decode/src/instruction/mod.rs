@@ -1,2 +1,3 @@
 pub mod instruction;
 pub mod operand;
+pub mod prefix;
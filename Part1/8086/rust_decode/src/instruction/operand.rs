@@ -1,7 +1,7 @@
 use std::{collections::HashMap, fmt};
 
-use crate::assembled_instruction::{AssembledInstruction, BitFlag, BitOrder, S};
-use crate::cpu::cpu::{Access, CpuOperand, EffectiveAddress, Reg};
+use crate::assembled_instruction::{BitFlag, BitOrder};
+use crate::cpu::cpu::{Access, CpuOperand, EffectiveAddress, Reg, RegPart};
 use crate::instruction::instruction::DecodingError;
 
 #[derive(Debug)]
@@ -9,6 +9,7 @@ pub enum OperandToStrError {
     RegisterValueError,
     EffectiveAddrValueError,
     UnexpctedOperandError,
+    MissingValueError,
 }
 
 #[derive(Debug)]
@@ -34,8 +35,10 @@ pub enum OperandType {
     Register(Size),
     Memory(Displacement),
     DirectAccess(Displacement),
+    SegmentRegister,
     Immediate(Size),
-    Jump,
+    Jump(Size),
+    FarPointer,
     NotUsed,
 }
 
@@ -47,20 +50,25 @@ impl OperandType {
         data: Option<i16>,
     ) -> Result<String, OperandToStrError> {
         match self {
-            Self::Register(size) => Ok(reg_encoding_table(*size)
-                .get(&value.unwrap())
+            Self::Register(size) => Ok(value
+                .and_then(|value| reg_encoding_table(*size).get(&value))
+                .ok_or(OperandToStrError::RegisterValueError)?
+                .to_string()),
+            Self::SegmentRegister => Ok(value
+                .and_then(|value| SEGMENT_REG.get(&value))
                 .ok_or(OperandToStrError::RegisterValueError)?
                 .to_string()),
             Self::Memory(displacement) => {
-                let eff_addr = EFFECTIVE_ADDR
-                    .get(&value.unwrap())
+                let eff_addr = value
+                    .and_then(|value| EFFECTIVE_ADDR.get(&value))
                     .ok_or(OperandToStrError::EffectiveAddrValueError)?
                     .to_string();
 
                 match displacement {
                     Displacement::NO => Ok(format!("[{}]", eff_addr)),
                     Displacement::YES(_) => {
-                        let disp_val = displacement_value.expect("Displacement should have value");
+                        let disp_val =
+                            displacement_value.ok_or(OperandToStrError::MissingValueError)?;
 
                         if disp_val == 0 {
                             Ok(format!("[{}]", eff_addr))
@@ -72,36 +80,24 @@ impl OperandType {
             }
             Self::DirectAccess(_) => Ok(format!(
                 "[{}]",
-                displacement_value.expect("Disaplacment should have valeu")
+                displacement_value.ok_or(OperandToStrError::MissingValueError)?
+            )),
+            Self::Immediate(_) => Ok(format!(
+                "{}",
+                data.ok_or(OperandToStrError::MissingValueError)?
+            )),
+            Self::Jump(_) => Ok(format!(
+                "{}",
+                displacement_value.ok_or(OperandToStrError::MissingValueError)?
+            )),
+            Self::FarPointer => Ok(format!(
+                "{}:{}",
+                data.ok_or(OperandToStrError::MissingValueError)? as u16,
+                displacement_value.ok_or(OperandToStrError::MissingValueError)? as u16
             )),
-            Self::Immediate(_) => {
-                Ok(format!("{}", data.expect("Immediate must have data")).to_string())
-            }
-            Self::Jump => Ok(format!("{}", displacement_value.unwrap())),
             Self::NotUsed => Ok("".to_string()),
         }
     }
-
-    pub fn total_bytes_required(&self, flags: BitFlag, ass_instr: &AssembledInstruction) -> u8 {
-        match self {
-            OperandType::Register(_) => 0,
-            OperandType::Immediate(size) => {
-                if ass_instr.includes_bits(S) {
-                    if flags.is_flag_toogled(BitFlag::S) {
-                        size.byte_count() - 1
-                    } else {
-                        size.byte_count()
-                    }
-                } else {
-                    size.byte_count()
-                }
-            }
-            OperandType::Memory(displacement) => displacement.byte_count(),
-            OperandType::DirectAccess(_) => 2,
-            OperandType::Jump => 0,
-            OperandType::NotUsed => 0,
-        }
-    }
 }
 
 impl OperandType {
@@ -143,6 +139,8 @@ lazy_static! {
         (0b110, "dh"),
         (0b111, "bh"),
     ]);
+    pub(crate) static ref SEGMENT_REG: HashMap<u8, &'static str> =
+        HashMap::from([(0b00, "es"), (0b01, "cs"), (0b10, "ss"), (0b11, "ds"),]);
     static ref EFFECTIVE_ADDR: HashMap<u8, &'static str> = HashMap::from([
         (0b000, "bx + si"),
         (0b001, "bx + di"),
@@ -202,12 +200,10 @@ impl Size {
 
 impl fmt::Display for Size {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let s: String;
-
-        match self {
-            Self::WORD => s = "word".to_string(),
-            Self::BYTE => s = "byte".to_string(),
-        }
+        let s: String = match self {
+            Self::WORD => "word".to_string(),
+            Self::BYTE => "byte".to_string(),
+        };
 
         write!(f, "{}", s)
     }
@@ -228,6 +224,7 @@ pub struct Operand {
     pub value: Option<u8>,
     pub displacement: Option<i16>,
     pub data: Option<i16>,
+    pub segment: Option<u8>,
 }
 
 impl Operand {
@@ -237,16 +234,18 @@ impl Operand {
             value,
             displacement: None,
             data: None,
+            segment: None,
         }
     }
 
-    pub fn immediate(data: Option<u8>, flags: BitFlag) -> Result<Self, OperandTypeError> {
-        Ok(Operand {
-            operand_type: Some(OperandType::Immediate(Size::new(flags))),
+    pub fn immediate(data: u8, size: Size) -> Self {
+        Operand {
+            operand_type: Some(OperandType::Immediate(size)),
             value: None,
             displacement: None,
-            data: data.map(|d| d.into()),
-        })
+            data: Some(data.into()),
+            segment: None,
+        }
     }
 
     pub fn rm(rm: u8, mode: u8, flags: BitFlag) -> Result<Self, OperandTypeError> {
@@ -255,33 +254,62 @@ impl Operand {
             value: Some(rm),
             displacement: None,
             data: None,
+            segment: None,
         })
     }
 
-    pub fn jump_operands(displacement: u8) -> (Operand, Operand) {
-        let op_a = Operand {
-            operand_type: Some(OperandType::Jump),
+    pub fn jump(displacement: u8, size: Size) -> Self {
+        Operand {
+            operand_type: Some(OperandType::Jump(size)),
             value: None,
             displacement: Some(displacement.into()),
             data: None,
-        };
-        let op_b = Operand {
-            operand_type: Some(OperandType::NotUsed),
+            segment: None,
+        }
+    }
+
+    pub fn far_pointer(offset: u8) -> Self {
+        Operand {
+            operand_type: Some(OperandType::FarPointer),
             value: None,
-            displacement: None,
+            displacement: Some(offset.into()),
             data: None,
-        };
+            segment: None,
+        }
+    }
 
-        (op_a, op_b)
+    pub fn reg(value: u8, size: Size) -> Self {
+        Operand {
+            operand_type: Some(OperandType::Register(size)),
+            value: Some(value),
+            displacement: None,
+            data: None,
+            segment: None,
+        }
     }
 
-    pub fn reg(value: u8, flags: BitFlag) -> Result<Self, OperandTypeError> {
-        Ok(Operand {
-            operand_type: Some(OperandType::Register(Size::new(flags))),
+    pub fn segment_reg(value: u8) -> Self {
+        Operand {
+            operand_type: Some(OperandType::SegmentRegister),
             value: Some(value),
             displacement: None,
             data: None,
-        })
+            segment: None,
+        }
+    }
+
+    pub fn is_register(&self) -> bool {
+        matches!(
+            self.operand_type,
+            Some(OperandType::Register(_) | OperandType::SegmentRegister)
+        )
+    }
+
+    pub fn is_memory(&self) -> bool {
+        matches!(
+            self.operand_type,
+            Some(OperandType::Memory(_) | OperandType::DirectAccess(_))
+        )
     }
 
     pub fn signed_data(&self) -> Result<i16, DecodingError> {
@@ -291,13 +319,16 @@ impl Operand {
                     let u_data = self.data.ok_or(DecodingError::FieldNotYetDecodedError)?;
 
                     if u_data & 0x80 > 0 {
-                        Ok((u_data | 0b11111111 << 8).try_into().unwrap())
+                        Ok(u_data | 0b11111111 << 8)
                     } else {
-                        Ok((u_data | 0x0000).try_into().unwrap())
+                        Ok(u_data)
                     }
                 }
                 Size::WORD => self.data.ok_or(DecodingError::FieldNotYetDecodedError),
             },
+            Some(OperandType::FarPointer) => {
+                self.data.ok_or(DecodingError::FieldNotYetDecodedError)
+            }
             Some(_) => Err(OperandTypeError::UncompatibleOperandTypeError.into()),
             None => Err(DecodingError::FieldNotYetDecodedError),
         }
@@ -308,9 +339,9 @@ impl Operand {
             let u_disp = displacement.ok_or(DecodingError::FieldNotYetDecodedError)?;
 
             if u_disp & 0x80 > 0 {
-                Ok((u_disp | 0b11111111 << 8).try_into().unwrap())
+                Ok(u_disp | 0b11111111 << 8)
             } else {
-                Ok((u_disp | 0x0000).try_into().unwrap())
+                Ok(u_disp)
             }
         }
 
@@ -324,7 +355,10 @@ impl Operand {
             Some(OperandType::DirectAccess(_)) => self
                 .displacement
                 .ok_or(DecodingError::FieldNotYetDecodedError),
-            Some(OperandType::Jump) => sign_extend(self.displacement),
+            Some(OperandType::Jump(Size::BYTE)) => sign_extend(self.displacement),
+            Some(OperandType::Jump(Size::WORD)) | Some(OperandType::FarPointer) => self
+                .displacement
+                .ok_or(DecodingError::FieldNotYetDecodedError),
             Some(_) => Err(OperandTypeError::UncompatibleOperandTypeError.into()),
             None => Err(DecodingError::FieldNotYetDecodedError),
         }
@@ -334,11 +368,17 @@ impl Operand {
         match bit_order {
             BitOrder::LOW => match self.data {
                 Some(_) => Err(DecodingError::FieldAlreadyDecodedError),
-                None => Ok(self.data = Some(data_decoded.into())),
+                None => {
+                    self.data = Some(data_decoded.into());
+                    Ok(())
+                }
             },
             BitOrder::HIGH => match self.data {
                 None => Err(DecodingError::FieldNotYetDecodedError),
-                Some(data) => Ok(self.data = Some(data | (data_decoded as i16) << 8)),
+                Some(data) => {
+                    self.data = Some(data | (data_decoded as i16) << 8);
+                    Ok(())
+                }
             },
         }
     }
@@ -351,77 +391,77 @@ impl Operand {
         match bit_order {
             BitOrder::LOW => match self.displacement {
                 Some(_) => Err(DecodingError::FieldAlreadyDecodedError),
-                None => Ok(self.displacement = Some(displacement_decoded.into())),
+                None => {
+                    self.displacement = Some(displacement_decoded.into());
+                    Ok(())
+                }
             },
             BitOrder::HIGH => match self.displacement {
                 None => Err(DecodingError::FieldNotYetDecodedError),
                 Some(data) => {
-                    Ok(self.displacement = Some(data | (displacement_decoded as i16) << 8))
+                    self.displacement = Some(data | (displacement_decoded as i16) << 8);
+                    Ok(())
                 }
             },
         }
     }
 
-    pub fn n_bytes_needed(&self, flags: BitFlag, ass_instr: &AssembledInstruction) -> u8 {
-        self.operand_type
-            .as_ref()
-            .map(|op_type| {
-                let bytes_required = op_type.total_bytes_required(flags, ass_instr);
-
-                match op_type {
-                    OperandType::Register(_) => bytes_required,
-                    OperandType::Memory(Displacement::YES(_)) => match self.displacement {
-                        Some(_) => bytes_required - 1,
-                        None => bytes_required,
-                    },
-                    OperandType::Memory(Displacement::NO) => bytes_required,
-                    OperandType::Immediate(_) => match self.data {
-                        Some(_) => bytes_required - 1,
-                        None => bytes_required,
-                    },
-                    OperandType::DirectAccess(Displacement::YES(_)) => match self.displacement {
-                        Some(_) => bytes_required - 1,
-                        None => bytes_required,
-                    },
-                    _ => bytes_required,
-                }
-            })
-            .unwrap()
-    }
-
-    pub fn parse_for_cpu(&self) -> CpuOperand {
-        match self
+    /// Size of the instruction applies to memory operands, registers have their own
+    pub fn parse_for_cpu(&self, size: Size) -> Result<CpuOperand, DecodingError> {
+        let operand_type = self
             .operand_type
             .as_ref()
-            .expect("But, operand type must be known before parsing for CPU")
-        {
-            OperandType::Register(_) => CpuOperand::Register(Reg::new(self.value.unwrap())),
-            OperandType::Memory(_) => CpuOperand::Memory(Access::Address(EffectiveAddress::new(
-                self.value.unwrap(),
-                self.displacement,
-            ))),
-            OperandType::Immediate(_) => CpuOperand::Immediate(self.data.unwrap()),
-            OperandType::DirectAccess(_) => CpuOperand::Memory(Access::Direct(
-                self.displacement.unwrap().try_into().unwrap(),
-            )),
-            OperandType::Jump => CpuOperand::Jump(self.signed_displacement().unwrap()),
+            .ok_or(DecodingError::FieldNotYetDecodedError)?;
+        let value = self.value.ok_or(DecodingError::FieldNotYetDecodedError);
+        let segment = self.segment.map(Reg::segment).transpose()?;
+
+        Ok(match operand_type {
+            OperandType::Register(size) => CpuOperand::register(value?, *size)?,
+            OperandType::SegmentRegister => {
+                CpuOperand::Register(Reg::segment(value?)?, RegPart::Whole)
+            }
+            OperandType::Memory(_) => CpuOperand::Memory(
+                Access::Address(EffectiveAddress::new(
+                    value?,
+                    self.signed_displacement().ok(),
+                )?),
+                segment,
+                size,
+            ),
+            // Byte immediates of word instructions are sign extended by 8086
+            OperandType::Immediate(_) => CpuOperand::Immediate(self.signed_data()?),
+            OperandType::DirectAccess(_) => CpuOperand::Memory(
+                Access::Direct(self.signed_displacement()? as u16),
+                segment,
+                size,
+            ),
+            OperandType::Jump(_) => CpuOperand::Jump(self.signed_displacement()?),
+            OperandType::FarPointer => CpuOperand::FarPointer(
+                self.signed_data()? as u16,
+                self.signed_displacement()? as u16,
+            ),
             OperandType::NotUsed => CpuOperand::NotUsed,
-        }
+        })
     }
 }
 
 impl fmt::Display for Operand {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Operands of partially decoded instructions may be missing some of their fields
         let operand_str: String = self
             .operand_type
             .as_ref()
-            .unwrap()
+            .ok_or(fmt::Error)?
             .to_str(
                 self.value,
                 self.signed_displacement().ok(),
                 self.signed_data().ok(),
             )
-            .unwrap();
+            .map_err(|_| fmt::Error)?;
+
+        if let Some(segment) = self.segment {
+            write!(f, "{}:", SEGMENT_REG.get(&segment).ok_or(fmt::Error)?)?;
+        }
 
         write!(f, "{}", operand_str)
     }
@@ -436,6 +476,7 @@ mod test {
         let op = Operand {
             operand_type: Some(OperandType::Memory(Displacement::YES(Size::BYTE))),
             data: None,
+            segment: None,
             displacement: Some(0x00db),
             value: None,
         };
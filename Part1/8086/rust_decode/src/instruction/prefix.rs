@@ -0,0 +1,163 @@
+use std::fmt;
+
+use super::operand::SEGMENT_REG;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Repeat {
+    Rep,
+    Repne,
+}
+
+impl fmt::Display for Repeat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Repeat::Rep => write!(f, "rep"),
+            Repeat::Repne => write!(f, "repne"),
+        }
+    }
+}
+
+/// Byte preceding the opcode that modifies the instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Prefix {
+    Lock,
+    Repeat(Repeat),
+    Segment(u8),
+}
+
+impl Prefix {
+    pub fn new(byte: u8) -> Option<Self> {
+        match byte {
+            0xF0 => Some(Prefix::Lock),
+            0xF2 => Some(Prefix::Repeat(Repeat::Repne)),
+            0xF3 => Some(Prefix::Repeat(Repeat::Rep)),
+            // Segment override prefix has form 001 SR 110
+            _ if byte & 0b11100111 == 0b00100110 => Some(Prefix::Segment(byte >> 3 & 0b11)),
+            _ => None,
+        }
+    }
+
+    pub fn byte(self) -> u8 {
+        match self {
+            Prefix::Lock => 0xF0,
+            Prefix::Repeat(Repeat::Repne) => 0xF2,
+            Prefix::Repeat(Repeat::Rep) => 0xF3,
+            Prefix::Segment(sr) => 0b00100110 | sr << 3,
+        }
+    }
+
+    fn kind(self) -> PrefixKind {
+        match self {
+            Prefix::Lock => PrefixKind::Lock,
+            Prefix::Repeat(_) => PrefixKind::Repeat,
+            Prefix::Segment(_) => PrefixKind::Segment,
+        }
+    }
+}
+
+impl fmt::Display for Prefix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Prefix::Lock => write!(f, "lock"),
+            Prefix::Repeat(repeat) => write!(f, "{}", repeat),
+            Prefix::Segment(sr) => write!(f, "{}", SEGMENT_REG.get(sr).ok_or(fmt::Error)?),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PrefixKind {
+    Lock,
+    Repeat,
+    Segment,
+}
+
+/// All prefixes of a single instruction, later prefix of the same kind wins
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Prefixes {
+    pub lock: bool,
+    pub repeat: Option<Repeat>,
+    pub segment: Option<u8>,
+    /// Kinds of the added prefixes in the order of their first appearance
+    order: [Option<PrefixKind>; 3],
+}
+
+impl Prefixes {
+    pub fn add(&mut self, prefix: Prefix) {
+        match prefix {
+            Prefix::Lock => self.lock = true,
+            Prefix::Repeat(repeat) => self.repeat = Some(repeat),
+            Prefix::Segment(sr) => self.segment = Some(sr),
+        }
+
+        let kind = prefix.kind();
+        if !self.order.contains(&Some(kind)) {
+            if let Some(free) = self.order.iter_mut().find(|kind| kind.is_none()) {
+                *free = Some(kind);
+            }
+        }
+    }
+
+    /// Prefixes in the order they were added, the ones set directly follow in the order
+    /// lock, repeat and segment
+    pub fn in_order(&self) -> Vec<Prefix> {
+        let defaults = [PrefixKind::Lock, PrefixKind::Repeat, PrefixKind::Segment];
+        let mut kinds: Vec<PrefixKind> = Vec::new();
+
+        for kind in self.order.iter().flatten().chain(&defaults) {
+            if !kinds.contains(kind) {
+                kinds.push(*kind);
+            }
+        }
+
+        kinds
+            .into_iter()
+            .filter_map(|kind| match kind {
+                PrefixKind::Lock => self.lock.then_some(Prefix::Lock),
+                PrefixKind::Repeat => self.repeat.map(Prefix::Repeat),
+                PrefixKind::Segment => self.segment.map(Prefix::Segment),
+            })
+            .collect()
+    }
+}
+
+/// Every prefix is followed by a space, so the mnemonic can be written right after them
+impl fmt::Display for Prefixes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for prefix in self.in_order() {
+            write!(f, "{} ", prefix)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_prefix_new() {
+        assert_eq!(Prefix::new(0xF0), Some(Prefix::Lock));
+        assert_eq!(Prefix::new(0xF2), Some(Prefix::Repeat(Repeat::Repne)));
+        assert_eq!(Prefix::new(0xF3), Some(Prefix::Repeat(Repeat::Rep)));
+        assert_eq!(Prefix::new(0x26), Some(Prefix::Segment(0)));
+        assert_eq!(Prefix::new(0x3E), Some(Prefix::Segment(3)));
+        assert_eq!(Prefix::new(0xA4), None);
+    }
+
+    #[test]
+    fn test_prefix_order() {
+        let mut prefixes = Prefixes::default();
+        for byte in [0x26, 0xF3, 0xF2] {
+            prefixes.add(Prefix::new(byte).unwrap());
+        }
+
+        assert_eq!(prefixes.to_string(), "es repne ");
+        let bytes: Vec<u8> = prefixes.in_order().into_iter().map(Prefix::byte).collect();
+        assert_eq!(bytes, [0x26, 0xF2]);
+
+        prefixes.lock = true;
+        assert_eq!(prefixes.to_string(), "es repne lock ");
+    }
+}
@@ -0,0 +1,168 @@
+//! Decoder and simulator of the 8086 instruction set
+
+#![allow(dead_code)]
+#![allow(
+    clippy::upper_case_acronyms,
+    clippy::enum_variant_names,
+    clippy::module_inception
+)]
+
+#[macro_use]
+extern crate lazy_static;
+extern crate bitflags;
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::ops::Deref;
+use std::path::Path;
+
+use memmap2::Mmap;
+
+mod assemble;
+mod assembled_instruction;
+mod cpu;
+mod debugger;
+mod disassemble;
+mod encode;
+mod instruction;
+mod loader;
+
+pub use assemble::{assemble, AssemblyError, AssemblyErrorKind};
+pub use assembled_instruction::Operation;
+pub use cpu::clocks::{ClockCount, Clocks, Processor};
+pub use cpu::cpu::{Reg, StopReason, CPU};
+pub use cpu::dump::{hex_dump, parse_address, parse_range, DumpError, DumpFormat, MemoryDump};
+pub use cpu::flags::CpuFlags;
+pub use cpu::services::{DosServices, Services};
+pub use cpu::snapshot::{Snapshot, SnapshotError, SNAPSHOT_MAGIC, SNAPSHOT_VERSION};
+pub use cpu::trace::{
+    read_binary_trace, BinaryTracer, JsonTracer, MemoryWrite, RegisterChange, TextTracer,
+    TraceFormat, TraceStep, Tracer, BINARY_TRACE_MAGIC,
+};
+pub use debugger::{Command, Debugger, DebuggerError, Location, Watchpoint};
+pub use disassemble::{
+    disassemble_bytes_in, disassemble_bytes_resilient, disassemble_next_instruction, Decoded,
+    DisassemblyError, DisassemblyErrorKind, DisassemblyResult,
+};
+pub use encode::{encode, encode_instruction, Argument, EncodingError, Statement};
+pub use instruction::instruction::{DecodingError, Instruction};
+pub use loader::{Executable, LoadError, ProgramFormat, PSP_SEGMENT, PSP_SIZE};
+
+const MEMORY_SIZE: usize = 1024 * 1024; //BYTES
+const MEMORY_MASK: usize = MEMORY_SIZE - 1;
+
+/// Bytes of the program, either borrowed from the caller or owned by the buffer
+enum Program<'a> {
+    Borrowed(&'a [u8]),
+    Owned(Vec<u8>),
+    Mapped(Mmap),
+}
+
+impl Deref for Program<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Program::Borrowed(bytes) => bytes,
+            Program::Owned(bytes) => bytes,
+            Program::Mapped(map) => map,
+        }
+    }
+}
+
+pub struct InstructionBuffer<'a> {
+    buf: Program<'a>,
+    last_read: usize,
+    bytes_loaded: usize,
+}
+
+#[derive(Debug)]
+pub struct BufferEndReachedError;
+
+impl<'a> InstructionBuffer<'a> {
+    /// Only the first 1MB of the program is addressable
+    fn with_program(buf: Program<'a>) -> Self {
+        let bytes_loaded = buf.len().min(MEMORY_SIZE);
+
+        InstructionBuffer {
+            buf,
+            last_read: 0,
+            bytes_loaded,
+        }
+    }
+
+    pub fn from_bytes(bytes: &'a [u8]) -> Self {
+        Self::with_program(Program::Borrowed(bytes))
+    }
+
+    pub fn from_reader<R: Read>(reader: R) -> io::Result<Self> {
+        let mut bytes = Vec::new();
+        reader.take(MEMORY_SIZE as u64).read_to_end(&mut bytes)?;
+
+        Ok(Self::with_program(Program::Owned(bytes)))
+    }
+
+    /// The file must not be modified while the buffer is alive
+    pub fn map_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = File::open(path)?;
+
+        // Empty files cannot be mapped
+        if file.metadata()?.len() == 0 {
+            return Ok(Self::with_program(Program::Owned(Vec::new())));
+        }
+
+        // SAFETY: the mapping is read only and the file is expected to stay unchanged
+        let map = unsafe { Mmap::map(&file)? };
+
+        Ok(Self::with_program(Program::Mapped(map)))
+    }
+
+    /// Loaded bytes of the program
+    pub fn program(&self) -> &[u8] {
+        &self.buf[..self.bytes_loaded]
+    }
+
+    pub fn position(&self) -> usize {
+        self.last_read
+    }
+
+    pub fn next_n_bytes(&mut self, n: usize) -> Result<&[u8], BufferEndReachedError> {
+        let last_read = self.last_read;
+        let read_until = last_read + n;
+
+        if read_until > self.bytes_loaded {
+            return Err(BufferEndReachedError);
+        }
+
+        self.last_read = read_until;
+        Ok(&self.buf[last_read..read_until])
+    }
+
+    pub fn next_byte(&mut self) -> Result<u8, BufferEndReachedError> {
+        Ok(self.next_n_bytes(1)?[0])
+    }
+
+    pub fn jump_by(&mut self, n: i16) {
+        let new = self.last_read as i64 + n as i64;
+
+        if new < 0 {
+            panic!("Instruction Buffer overflow")
+        } else {
+            self.last_read = new as usize
+        }
+    }
+
+    /// Jumps to the absolute position in the program
+    pub fn jump_to(&mut self, position: usize) {
+        self.last_read = position
+    }
+
+    pub fn is_at_the_end(&self) -> bool {
+        self.last_read >= self.bytes_loaded
+    }
+}
+
+/// Decodes the whole program, jumps to instructions in it are labeled
+pub fn disassemble(bytes: &[u8]) -> DisassemblyResult<Vec<Instruction>> {
+    disassemble_bytes_in(InstructionBuffer::from_bytes(bytes))
+}
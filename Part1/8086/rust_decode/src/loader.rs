@@ -0,0 +1,327 @@
+use std::path::Path;
+use std::{error, fmt};
+
+use clap::ValueEnum;
+
+use crate::MEMORY_SIZE;
+
+/// Segment of the program segment prefix of DOS programs, memory below is left to interrupt
+/// vectors and handlers
+pub const PSP_SEGMENT: u16 = 0x1000;
+
+pub const PSP_SIZE: usize = 0x100;
+
+/// Conventional memory ends where the video memory starts
+const MEMORY_END_SEGMENT: u16 = 0xA000;
+
+/// .COM program has to fit into one segment together with its PSP and the initial stack
+const COM_MAX_SIZE: usize = 0x10000 - PSP_SIZE - 2;
+
+const MZ_HEADER_SIZE: usize = 0x1C;
+const PAGE_SIZE: usize = 512;
+
+#[derive(Debug)]
+pub enum LoadError {
+    TruncatedHeaderError,
+    InvalidSignatureError,
+    /// Image, relocation table or relocated word lies outside of the file or the image
+    InvalidLayoutError(&'static str),
+    ProgramTooLargeError(usize),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TruncatedHeaderError => write!(f, "file is shorter than the MZ header"),
+            Self::InvalidSignatureError => write!(f, "missing MZ signature"),
+            Self::InvalidLayoutError(what) => write!(f, "{} is outside of the file", what),
+            Self::ProgramTooLargeError(size) => {
+                write!(f, "program of {} bytes does not fit into memory", size)
+            }
+        }
+    }
+}
+
+impl error::Error for LoadError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ProgramFormat {
+    /// Code executed from address 0 without any setup
+    Raw,
+    Com,
+    Exe,
+}
+
+impl ProgramFormat {
+    /// MZ signature marks .EXE files, .COM files are recognized by their extension
+    pub fn detect<P: AsRef<Path>>(path: P, bytes: &[u8]) -> Self {
+        let extension = path
+            .as_ref()
+            .extension()
+            .map(|extension| extension.to_ascii_lowercase());
+
+        match (bytes.get(..2), extension) {
+            (Some(b"MZ" | b"ZM"), _) => ProgramFormat::Exe,
+            (_, Some(extension)) if extension == "com" => ProgramFormat::Com,
+            _ => ProgramFormat::Raw,
+        }
+    }
+}
+
+/// Program prepared to be placed into memory with the registers it starts with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Executable {
+    pub format: ProgramFormat,
+    /// Bytes of the program with relocations applied
+    pub image: Vec<u8>,
+    /// Physical address of the first byte of the image
+    pub load_address: usize,
+    /// PSP placed at DS:0 of DOS programs
+    pub psp: Option<[u8; PSP_SIZE]>,
+    pub cs: u16,
+    pub ip: u16,
+    pub ss: u16,
+    pub sp: u16,
+    pub ds: u16,
+}
+
+fn word_at(bytes: &[u8], index: usize) -> u16 {
+    u16::from_le_bytes([bytes[index], bytes[index + 1]])
+}
+
+/// INT 20h at the start terminates programs returning to it, INT 21h at 0x50 is the far
+/// call entry to DOS and the command tail is empty
+fn program_segment_prefix() -> [u8; PSP_SIZE] {
+    let mut psp = [0; PSP_SIZE];
+
+    psp[0x00..0x02].copy_from_slice(&[0xCD, 0x20]);
+    psp[0x02..0x04].copy_from_slice(&MEMORY_END_SEGMENT.to_le_bytes());
+    psp[0x50..0x53].copy_from_slice(&[0xCD, 0x21, 0xCB]);
+    psp[0x81] = 0x0D;
+
+    psp
+}
+
+impl Executable {
+    /// Only the first 1MB of the program is loaded, all registers are zero
+    pub fn raw(bytes: &[u8]) -> Self {
+        Executable {
+            format: ProgramFormat::Raw,
+            image: bytes[..bytes.len().min(MEMORY_SIZE)].to_vec(),
+            load_address: 0,
+            psp: None,
+            cs: 0,
+            ip: 0,
+            ss: 0,
+            sp: 0,
+            ds: 0,
+        }
+    }
+
+    /// All segments point at the PSP, the code follows it at 0x100 and the stack starts at
+    /// the end of the segment with zero on it, so RET jumps to INT 20h
+    pub fn com(bytes: &[u8]) -> Result<Self, LoadError> {
+        if bytes.len() > COM_MAX_SIZE {
+            return Err(LoadError::ProgramTooLargeError(bytes.len()));
+        }
+
+        Ok(Executable {
+            format: ProgramFormat::Com,
+            image: bytes.to_vec(),
+            load_address: ((PSP_SEGMENT as usize) << 4) + PSP_SIZE,
+            psp: Some(program_segment_prefix()),
+            cs: PSP_SEGMENT,
+            ip: PSP_SIZE as u16,
+            ss: PSP_SEGMENT,
+            sp: 0xFFFE,
+            ds: PSP_SEGMENT,
+        })
+    }
+
+    /// Image follows the PSP, segments of the relocation table and the header are relative to
+    /// the start of the image
+    pub fn exe(bytes: &[u8]) -> Result<Self, LoadError> {
+        if bytes.len() < MZ_HEADER_SIZE {
+            return Err(LoadError::TruncatedHeaderError);
+        }
+
+        if !matches!(&bytes[..2], b"MZ" | b"ZM") {
+            return Err(LoadError::InvalidSignatureError);
+        }
+
+        let header = |index: usize| word_at(bytes, index);
+
+        // Last page is used only partially unless the count is zero
+        let file_size = match (header(0x04) as usize, header(0x02) as usize) {
+            (0, _) => 0,
+            (pages, 0) => pages * PAGE_SIZE,
+            (pages, last) => (pages - 1) * PAGE_SIZE + last,
+        };
+        let header_size = header(0x08) as usize * 16;
+
+        if file_size > bytes.len() || header_size > file_size {
+            return Err(LoadError::InvalidLayoutError("image"));
+        }
+
+        let mut image = bytes[header_size..file_size].to_vec();
+        let load_segment = PSP_SEGMENT + (PSP_SIZE >> 4) as u16;
+        let load_address = (load_segment as usize) << 4;
+
+        let minimum_size = image.len() + header(0x0A) as usize * 16;
+        if load_address + minimum_size > (MEMORY_END_SEGMENT as usize) << 4 {
+            return Err(LoadError::ProgramTooLargeError(minimum_size));
+        }
+
+        let table = header(0x18) as usize;
+        let count = header(0x06) as usize;
+
+        if table + count * 4 > bytes.len() {
+            return Err(LoadError::InvalidLayoutError("relocation table"));
+        }
+
+        // Every entry points at a segment in the image that is shifted by the load segment
+        for entry in bytes[table..table + count * 4].chunks(4) {
+            let index = ((word_at(entry, 2) as usize) << 4) + word_at(entry, 0) as usize;
+
+            if index + 2 > image.len() {
+                return Err(LoadError::InvalidLayoutError("relocation"));
+            }
+
+            let segment = word_at(&image, index).wrapping_add(load_segment);
+            image[index..index + 2].copy_from_slice(&segment.to_le_bytes());
+        }
+
+        Ok(Executable {
+            format: ProgramFormat::Exe,
+            image,
+            load_address,
+            psp: Some(program_segment_prefix()),
+            cs: header(0x16).wrapping_add(load_segment),
+            ip: header(0x14),
+            ss: header(0x0E).wrapping_add(load_segment),
+            sp: header(0x10),
+            ds: PSP_SEGMENT,
+        })
+    }
+
+    pub fn parse(bytes: &[u8], format: ProgramFormat) -> Result<Self, LoadError> {
+        match format {
+            ProgramFormat::Raw => Ok(Self::raw(bytes)),
+            ProgramFormat::Com => Self::com(bytes),
+            ProgramFormat::Exe => Self::exe(bytes),
+        }
+    }
+
+    /// Offset of the first byte of the image in the code segment the program starts in
+    pub fn origin(&self) -> u16 {
+        self.load_address.wrapping_sub((self.cs as usize) << 4) as u16
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Header of 2 paragraphs with one relocation, code starts at 1:2 with the stack at 3:100
+    fn exe_header(image: &[u8]) -> Vec<u8> {
+        let size = 32 + image.len();
+        let mut bytes = vec![0; 32];
+
+        for (index, value) in [
+            (0x02, size % PAGE_SIZE),
+            (0x04, size.div_ceil(PAGE_SIZE)),
+            (0x06, 1),
+            (0x08, 2),
+            (0x0A, 0x10),
+            (0x0E, 3),
+            (0x10, 0x100),
+            (0x14, 2),
+            (0x16, 1),
+            (0x18, 0x1C),
+            // Relocated word is at 1:3
+            (0x1C, 3),
+            (0x1E, 1),
+        ] {
+            bytes[index..index + 2].copy_from_slice(&(value as u16).to_le_bytes());
+        }
+
+        bytes[..2].copy_from_slice(b"MZ");
+        bytes.extend(image);
+        bytes
+    }
+
+    #[test]
+    fn test_detect_format() {
+        assert_eq!(
+            ProgramFormat::detect("a.exe", b"MZ\x90"),
+            ProgramFormat::Exe
+        );
+        assert_eq!(ProgramFormat::detect("a.bin", b"ZM"), ProgramFormat::Exe);
+        assert_eq!(ProgramFormat::detect("A.COM", b"\x90"), ProgramFormat::Com);
+        assert_eq!(
+            ProgramFormat::detect("listing_0037", b"\x89"),
+            ProgramFormat::Raw
+        );
+    }
+
+    #[test]
+    fn test_load_com() {
+        let executable = Executable::com(&[0xC3]).unwrap();
+
+        assert_eq!(executable.load_address, 0x10100);
+        assert_eq!(executable.origin(), 0x100);
+        assert_eq!((executable.cs, executable.ip), (0x1000, 0x100));
+        assert_eq!((executable.ss, executable.sp), (0x1000, 0xFFFE));
+        assert_eq!(executable.psp.unwrap()[..2], [0xCD, 0x20]);
+
+        assert!(matches!(
+            Executable::com(&vec![0; 0x10000]),
+            Err(LoadError::ProgramTooLargeError(0x10000))
+        ));
+    }
+
+    #[test]
+    fn test_load_exe() {
+        // Trailing byte is beyond the size given by the header
+        let mut image = vec![0; 0x20];
+        image[0x13..0x15].copy_from_slice(&[0x05, 0x00]);
+        let mut bytes = exe_header(&image);
+        bytes.push(0xFF);
+
+        let executable = Executable::exe(&bytes).unwrap();
+
+        assert_eq!(executable.image.len(), 0x20);
+        assert_eq!(executable.load_address, 0x10100);
+        assert_eq!(executable.image[0x13..0x15], [0x15, 0x10]);
+        assert_eq!((executable.cs, executable.ip), (0x1011, 2));
+        assert_eq!((executable.ss, executable.sp), (0x1013, 0x100));
+        assert_eq!(executable.ds, PSP_SEGMENT);
+        assert_eq!(executable.origin(), 0xFFF0);
+    }
+
+    #[test]
+    fn test_invalid_exe() {
+        assert!(matches!(
+            Executable::exe(b"MZ"),
+            Err(LoadError::TruncatedHeaderError)
+        ));
+        assert!(matches!(
+            Executable::exe(&[0; 32]),
+            Err(LoadError::InvalidSignatureError)
+        ));
+
+        let mut bytes = exe_header(&[0; 0x20]);
+        bytes.truncate(40);
+        assert!(matches!(
+            Executable::exe(&bytes),
+            Err(LoadError::InvalidLayoutError("image"))
+        ));
+
+        // Relocation points past the end of the image
+        let bytes = exe_header(&[0; 0x12]);
+        assert!(matches!(
+            Executable::exe(&bytes),
+            Err(LoadError::InvalidLayoutError("relocation"))
+        ));
+    }
+}
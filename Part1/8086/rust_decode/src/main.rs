@@ -1,116 +1,355 @@
-#![allow(dead_code)]
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::ops::Range;
+use std::process;
+use std::sync::atomic::Ordering;
 
-#[macro_use]
-extern crate lazy_static;
-extern crate bitflags;
+use clap::Parser;
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
 
-use std::fs;
-use std::io::{self, Read};
+use rust_decode::{
+    assemble, disassemble_bytes_in, disassemble_bytes_resilient, parse_address, parse_range,
+    Command, Debugger, Decoded, DisassemblyResult, DosServices, DumpFormat, Executable,
+    InstructionBuffer, MemoryDump, Processor, ProgramFormat, Snapshot, StopReason, TraceFormat,
+    CPU,
+};
 
-use clap::Parser;
+#[derive(Parser, Debug)]
+#[command(version, about, long_about=None)]
+struct Args {
+    path: String,
 
-mod assembled_instruction;
-mod cpu;
-mod disassemble;
-mod instruction;
+    #[arg(short, long)]
+    exec: bool,
 
-use cpu::cpu::CPU;
-use disassemble::disassemble_bytes_in;
+    /// Dump the memory after the execution
+    #[arg(short, long)]
+    dump: bool,
 
-const MEMORY_SIZE: usize = 1024 * 1024; //BYTES
-const MEMORY_MASK: usize = MEMORY_SIZE - 1;
+    /// File the memory is dumped to
+    #[arg(long, value_name = "PATH", default_value = "memory_dump.data")]
+    dump_output: String,
 
-pub struct InstructionBuffer {
-    buf: Vec<u8>,
-    last_read: usize,
-    bytes_loaded: usize,
-}
+    /// Dumped addresses written as start..end, the whole memory by default
+    #[arg(long, value_name = "RANGE", value_parser = range)]
+    dump_range: Option<Range<usize>>,
 
-#[derive(Debug)]
-pub struct BufferEndReachedError;
+    #[arg(long, value_enum, default_value_t = DumpFormat::Raw)]
+    dump_format: DumpFormat,
 
-impl InstructionBuffer {
-    pub fn new(file_name: &str) -> Result<Self, io::Error> {
-        let mut f = fs::File::open(file_name)?;
-        let file_size = fs::metadata(file_name)?.len();
+    /// Width in pixels of the dumped image
+    #[arg(long, value_name = "PIXELS", default_value_t = 64)]
+    dump_width: usize,
 
-        let mut buf: Vec<u8> = vec![0; MEMORY_SIZE];
+    /// Estimate clocks of executed instructions for the given processor
+    #[arg(short, long, value_enum)]
+    clocks: Option<Processor>,
 
-        f.read(&mut buf)?;
+    /// Write bytes that cannot be decoded as data instead of stopping
+    #[arg(short, long)]
+    resilient: bool,
 
-        Ok(InstructionBuffer {
-            buf,
-            last_read: 0,
-            bytes_loaded: file_size as usize & MEMORY_MASK,
-        })
-    }
+    /// Assemble the source at the path and write its bytes to the given file
+    #[arg(short, long, value_name = "OUTPUT")]
+    assemble: Option<String>,
 
-    pub fn next_n_bytes(&mut self, n: usize) -> Result<Vec<u8>, BufferEndReachedError> {
-        let last_read = self.last_read;
-        let read_until = last_read + n;
+    /// Execute the program step by step in an interactive debugger
+    #[arg(long)]
+    debug: bool,
 
-        if read_until > self.bytes_loaded {
-            return Err(BufferEndReachedError);
-        }
+    /// Format of the trace of executed instructions
+    ///
+    /// Output of the DOS program goes to the standard error while a binary or JSON trace is
+    /// written to the standard output
+    #[arg(long, value_enum, default_value_t = TraceFormat::Text)]
+    trace: TraceFormat,
 
-        self.last_read = read_until;
-        Ok(self.buf[last_read..read_until]
-            .iter()
-            .map(|bit| *bit)
-            .collect::<Vec<u8>>())
-    }
+    /// Write the trace to the file instead of the standard output
+    #[arg(long, value_name = "PATH")]
+    trace_output: Option<String>,
 
-    pub fn next_byte(&mut self) -> Result<u8, BufferEndReachedError> {
-        Ok(self.next_n_bytes(1)?[0])
-    }
+    /// Format of the executed program, detected from its header and extension by default
+    #[arg(long, value_enum)]
+    format: Option<ProgramFormat>,
+
+    /// Input of the DOS program read instead of the standard input
+    #[arg(long, value_name = "PATH")]
+    input: Option<String>,
 
-    pub fn jump_by(&mut self, n: i16) -> () {
-        let new = self.last_read as i64 + n as i64;
+    /// Directory the DOS program can open files in, no files can be opened without it
+    #[arg(long, value_name = "DIRECTORY")]
+    sandbox: Option<String>,
+
+    /// Stop the execution after the given number of instructions
+    #[arg(long, value_name = "COUNT")]
+    stop_after: Option<usize>,
+
+    /// Stop the execution once IP reaches the address, the first instruction is always executed
+    #[arg(long, value_name = "ADDRESS", value_parser = address)]
+    stop_at: Option<usize>,
+
+    /// Save the state of the machine to the file once the execution stops
+    #[arg(long, value_name = "PATH")]
+    snapshot: Option<String>,
+
+    /// Continue the execution of the program from the saved state
+    #[arg(long, value_name = "PATH")]
+    resume: Option<String>,
+}
 
-        if new < 0 {
-            panic!("Instruction Buffer overflow")
-        } else {
-            self.last_read = new as usize
+fn address(text: &str) -> Result<usize, String> {
+    parse_address(text).ok_or_else(|| format!("expected address, found '{}'", text))
+}
+
+fn range(text: &str) -> Result<Range<usize>, String> {
+    parse_range(text).ok_or_else(|| format!("expected start..end, found '{}'", text))
+}
+
+/// Runs until the program ends or one of the stop conditions is met
+fn execute(
+    cpu: &mut CPU,
+    stop_after: Option<usize>,
+    stop_at: Option<usize>,
+) -> DisassemblyResult<()> {
+    let mut executed = 0;
+
+    while !cpu.is_finished() && stop_after != Some(executed) {
+        if executed != 0 && stop_at == Some(cpu.ip()) {
+            break;
         }
-    }
 
-    pub fn is_at_the_end(&self) -> bool {
-        self.last_read >= self.bytes_loaded
+        cpu.execute_next_instruction()?;
+        executed += 1;
     }
+
+    Ok(())
 }
 
-#[derive(Parser, Debug)]
-#[command(version, about, long_about=None)]
-struct Args {
-    path: String,
+/// Empty line repeats the last command that executed instructions
+fn debug(cpu: CPU) -> rustyline::Result<()> {
+    let mut debugger = Debugger::new(cpu);
+    let mut editor = DefaultEditor::new()?;
 
-    #[arg(short, long)]
-    exec: bool,
+    // Ctrl-C at the prompt quits, while instructions run it stops them
+    let interrupted = debugger.interrupt_flag();
+    ctrlc::set_handler(move || interrupted.store(true, Ordering::Relaxed))
+        .expect("Setting of Ctrl-C handler failed");
+    let mut last = String::from("step");
 
-    #[arg(short, long)]
-    dump: bool,
+    loop {
+        let line = match editor.readline("(8086) ") {
+            Ok(line) => line.trim().to_string(),
+            Err(ReadlineError::Interrupted | ReadlineError::Eof) => return Ok(()),
+            Err(e) => return Err(e),
+        };
+
+        let line = match line.is_empty() {
+            true => last.clone(),
+            false => {
+                editor.add_history_entry(&line)?;
+                line
+            }
+        };
+
+        let command = match line.parse::<Command>() {
+            Ok(Command::Quit) => return Ok(()),
+            Ok(command) => command,
+            Err(e) => {
+                eprintln!("error: {}", e);
+                continue;
+            }
+        };
+
+        let executes = command.executes();
+        if executes {
+            last = line;
+        }
+
+        let output = debugger.run(command);
+
+        // Trace of executed instructions does not end with a new line
+        if executes {
+            println!();
+        }
+
+        match output {
+            Ok(output) if output.is_empty() => (),
+            Ok(output) => println!("{}", output),
+            Err(e) => eprintln!("error: {}", e),
+        }
+    }
 }
 
 fn main() {
     let args = Args::parse();
 
-    let buffer = InstructionBuffer::new(&args.path).expect("Loading instruction to buffer failed");
+    if let Some(output) = &args.assemble {
+        let source = fs::read_to_string(&args.path).expect("Reading of the source failed");
+
+        let bytes = assemble(&source).unwrap_or_else(|e| {
+            eprintln!("error: {}", e);
+            process::exit(1);
+        });
+
+        fs::write(output, bytes).expect("Writing of the output failed");
+        return;
+    }
+
+    let buffer =
+        InstructionBuffer::map_file(&args.path).expect("Loading instruction to buffer failed");
+
+    if args.exec || args.debug {
+        let program = buffer.program();
+        let format = args
+            .format
+            .unwrap_or_else(|| ProgramFormat::detect(&args.path, program));
+
+        let executable = Executable::parse(program, format).unwrap_or_else(|e| {
+            eprintln!("error: {}", e);
+            process::exit(1);
+        });
+
+        let mut cpu = CPU::load(&executable);
+
+        if format != ProgramFormat::Raw {
+            let input: Box<dyn Read> = match &args.input {
+                Some(path) => Box::new(File::open(path).expect("Opening of the input failed")),
+                None => Box::new(io::stdin()),
+            };
+
+            // Machine readable trace on the standard output is kept apart from the program output
+            let machine_trace = args.trace != TraceFormat::Text && args.trace_output.is_none();
+            let output: Box<dyn Write> = if machine_trace && !args.debug {
+                Box::new(io::stderr())
+            } else {
+                Box::new(io::stdout())
+            };
+
+            let mut services = DosServices::new(input, output);
+            if let Some(sandbox) = &args.sandbox {
+                services = services.with_sandbox(sandbox);
+            }
+
+            cpu.set_services(Box::new(services));
+        }
+
+        if let Some(processor) = args.clocks {
+            cpu.estimate_clocks(processor);
+        }
+
+        if let Some(path) = &args.resume {
+            let file = File::open(path).expect("Opening of the snapshot failed");
+
+            match Snapshot::read(BufReader::new(file)) {
+                Ok(snapshot) => cpu.restore_snapshot(snapshot),
+                Err(e) => {
+                    eprintln!("error: loading of the snapshot failed: {}", e);
+                    process::exit(1);
+                }
+            }
+        }
+
+        if args.debug {
+            debug(cpu).expect("Reading of the commands failed");
+            return;
+        }
+
+        let writer: Box<dyn Write> = match &args.trace_output {
+            Some(path) => Box::new(BufWriter::new(
+                File::create(path).expect("Creating of the trace failed"),
+            )),
+            // Output of the program is written to the standard output as well in the text trace
+            None => Box::new(io::stdout()),
+        };
+        cpu.set_tracer(args.trace.tracer(writer));
+
+        let executed = execute(&mut cpu, args.stop_after, args.stop_at);
+
+        if let Err(e) = cpu.finish_trace() {
+            eprintln!("error: writing of the trace failed: {}", e);
+            process::exit(1);
+        }
 
-    if args.exec {
-        let mut cpu = CPU::new(buffer);
+        // Registers would break the machine readable trace on the standard output
+        if args.trace == TraceFormat::Text || args.trace_output.is_some() {
+            println!("{}", cpu);
+        }
 
-        cpu.execute_instructions().unwrap();
+        // Machine stopped at the failing instruction is saved as well to inspect it
+        if let Some(path) = &args.snapshot {
+            let file = File::create(path).expect("Creating of the snapshot failed");
 
-        println!("{}", cpu);
+            if let Err(e) = cpu.snapshot().write(BufWriter::new(file)) {
+                eprintln!("error: writing of the snapshot failed: {}", e);
+                process::exit(1);
+            }
+        }
+
+        if let Err(e) = executed {
+            eprintln!("error: {}", e);
+            process::exit(1);
+        }
 
         if args.dump {
-            cpu.dump_memory().expect("Dumping of memory failed")
+            let dump = MemoryDump {
+                range: args.dump_range.unwrap_or(MemoryDump::default().range),
+                format: args.dump_format,
+                width: args.dump_width,
+            };
+            let file = File::create(&args.dump_output).expect("Creating of the dump failed");
+
+            if let Err(e) = cpu.dump_memory(&dump, BufWriter::new(file)) {
+                eprintln!("error: dumping of memory failed: {}", e);
+                process::exit(1);
+            }
+        }
+
+        match cpu.stop_reason() {
+            // Program terminated by DOS services exits with its own code
+            Some(StopReason::Exited(code)) => process::exit(code as i32),
+            Some(reason @ StopReason::LeftProgram(_)) => {
+                eprintln!("error: {}", reason);
+                process::exit(1);
+            }
+            _ => (),
+        }
+    } else if args.resilient {
+        let decoded = disassemble_bytes_resilient(buffer);
+
+        println!("bits 16");
+
+        for line in decoded.iter() {
+            if let Decoded::Instruction(instruction) = line {
+                if let Some(label) = instruction.label() {
+                    println!("{}:", label);
+                }
+            }
+
+            println!("{}", line);
+        }
+
+        let undecoded = decoded
+            .iter()
+            .filter(|line| matches!(line, Decoded::Data(_, _)))
+            .count();
+
+        if undecoded != 0 {
+            eprintln!("{} bytes could not be decoded", undecoded);
         }
     } else {
+        let instructions = disassemble_bytes_in(buffer).unwrap_or_else(|e| {
+            eprintln!("error: {}", e);
+            process::exit(1);
+        });
+
         println!("bits 16");
 
-        for instruction in disassemble_bytes_in(buffer).expect("Disassembly of Instructions failed")
-        {
+        for instruction in instructions {
+            if let Some(label) = instruction.label() {
+                println!("{}:", label);
+            }
+
             println!("{}", instruction);
         }
     }
@@ -0,0 +1,176 @@
+use rust_decode::{
+    assemble, disassemble, encode, encode_instruction, Argument, AssemblyErrorKind, EncodingError,
+    Operation, Statement,
+};
+
+fn test_assembly(source: &str, bytes: Vec<u8>) {
+    let assembled = assemble(source).unwrap();
+
+    assert_eq!(assembled, bytes, "{}", source);
+}
+
+/// Text printed by the disassembler assembles back to the same bytes
+fn test_round_trip(bytes: Vec<u8>) {
+    let source: String = disassemble(&bytes)
+        .unwrap()
+        .iter()
+        .map(|instruction| match instruction.label() {
+            Some(label) => format!("{}:\n{}\n", label, instruction),
+            None => format!("{}\n", instruction),
+        })
+        .collect();
+
+    assert_eq!(
+        assemble(&format!("bits 16\n{}", source)).unwrap(),
+        bytes,
+        "{}",
+        source
+    );
+}
+
+#[test]
+fn test_shortest_encoding() {
+    test_assembly("mov cx, bx", vec![0x89, 0xD9]);
+    test_assembly("add ax, 5", vec![0x83, 0xC0, 0x05]);
+    test_assembly("add al, 9", vec![0x04, 0x09]);
+    test_assembly("add cx, 1000", vec![0x81, 0xC1, 0xE8, 0x03]);
+    test_assembly("mov ax, [2555]", vec![0xA1, 0xFB, 0x09]);
+    test_assembly("inc cx", vec![0x41]);
+    test_assembly("xchg ax, dx", vec![0x92]);
+    test_assembly("mov [bp], byte 7", vec![0xC6, 0x46, 0x00, 0x07]);
+    test_assembly("mov es, [bp + di-300]", vec![0x8E, 0x83, 0xD4, 0xFE]);
+    test_assembly("lea si, [bp+2]", vec![0x8D, 0x76, 0x02]);
+}
+
+#[test]
+fn test_specifiers_and_prefixes() {
+    test_assembly("shl word [bx + si+4], 1", vec![0xD1, 0x60, 0x04]);
+    test_assembly("test byte [bx], 5", vec![0xF6, 0x07, 0x05]);
+    test_assembly("mov ax, es:[bx]", vec![0x26, 0x8B, 0x07]);
+    test_assembly("rep movsb", vec![0xF3, 0xA4]);
+    test_assembly("es rep cmpsb", vec![0x26, 0xF3, 0xA6]);
+    test_assembly("rep es cmpsb", vec![0xF3, 0x26, 0xA6]);
+    test_assembly("cs xlatb", vec![0x2E, 0xD7]);
+    test_assembly("cs inc ax", vec![0x2E, 0x40]);
+    test_assembly("es lock inc word [bx]", vec![0x26, 0xF0, 0xFF, 0x07]);
+    test_assembly("lock not byte [bx]", vec![0xF0, 0xF6, 0x17]);
+    test_assembly("call far [bx]", vec![0xFF, 0x1F]);
+    test_assembly("call 4660:22136", vec![0x9A, 0x78, 0x56, 0x34, 0x12]);
+    test_assembly(
+        "int 33\naam\nout 44, ax",
+        vec![0xCD, 0x21, 0xD4, 0x0A, 0xE7, 0x2C],
+    );
+}
+
+#[test]
+fn test_labels_and_jumps() {
+    test_assembly(
+        "bits 16\nstart:\n  dec cx ; count down\n  jnz start\nloop $+2",
+        vec![0x49, 0x75, 0xFD, 0xE2, 0x00],
+    );
+
+    // Jump over more than 127 bytes needs the near form
+    let far_away = format!("jmp end\n{}end: hlt", "nop\n".repeat(200));
+    let assembled = assemble(&far_away).unwrap();
+    assert_eq!(assembled[..3], [0xE9, 0xC8, 0x00]);
+
+    let near = format!("jmp end\n{}end: hlt", "nop\n".repeat(10));
+    assert_eq!(assemble(&near).unwrap()[..2], [0xEB, 0x0A]);
+
+    // Distance written with the jump keeps its form
+    let near = format!("jmp near end\n{}end: hlt", "nop\n".repeat(10));
+    assert_eq!(assemble(&near).unwrap()[..3], [0xE9, 0x0A, 0x00]);
+    test_assembly("jmp near $+3", vec![0xE9, 0x00, 0x00]);
+
+    // Only the displacement is checked, the target may be before the program
+    test_assembly("jmp $-80", vec![0xEB, 0xAE]);
+
+    test_assembly(
+        "db 1, 0x20, 'a'\ndw 1000",
+        vec![0x01, 0x20, 0x61, 0xE8, 0x03],
+    );
+}
+
+#[test]
+fn test_round_trips() {
+    test_round_trip(vec![
+        0xB9, 0x03, 0x00, 0xBB, 0xE8, 0x03, 0x83, 0xC3, 0x0A, 0x83, 0xE9, 0x01, 0x75, 0xF8,
+    ]);
+    test_round_trip(vec![
+        0x8B, 0x41, 0xDB, 0x8A, 0x80, 0x87, 0x13, 0xC6, 0x03, 0x07, 0xC7, 0x85, 0x85, 0x03, 0x5B,
+        0x01, 0x26, 0x89, 0x0E, 0x05, 0x00, 0xF3, 0xA5, 0xE8, 0x00, 0x01, 0xEA, 0x00, 0x00, 0xFF,
+        0xFF, 0xC2, 0x08, 0x00, 0xD2, 0x0F, 0xE4, 0xC8,
+    ]);
+    test_round_trip(vec![0xE9, 0x00, 0x00, 0x26, 0xF3, 0xA6, 0xF3, 0x26, 0xA6]);
+    test_round_trip(vec![0x90, 0x75, 0xAE, 0xE9, 0x10, 0xFC]);
+    test_round_trip(vec![0x2E, 0x40, 0x26, 0xF3, 0xAA]);
+}
+
+#[test]
+fn test_encode_instruction() {
+    let bytes = vec![
+        0x00, 0xF4, 0x8A, 0x06, 0xC5, 0x31, 0xFF, 0xC1, 0x83, 0xC6, 0x02,
+    ];
+    let encoded: Vec<Vec<u8>> = disassemble(&bytes)
+        .unwrap()
+        .iter()
+        .map(|instruction| encode_instruction(instruction).unwrap())
+        .collect();
+
+    // Alternative encodings are replaced by the preferred ones
+    assert_eq!(
+        encoded,
+        vec![
+            vec![0x00, 0xF4],
+            vec![0xA0, 0xC5, 0x31],
+            vec![0x41],
+            vec![0x83, 0xC6, 0x02]
+        ]
+    );
+
+    let statement = Statement::new(Operation::JMP, vec![Argument::Target(0)]);
+    assert_eq!(encode(&statement, 0x100).unwrap(), vec![0xE9, 0xFD, 0xFE]);
+}
+
+#[test]
+fn test_assembly_errors() {
+    let error = assemble("bits 16\nmov ax, bx\nmovx ax, bx").unwrap_err();
+    assert_eq!(error.line, 3);
+    assert!(matches!(
+        error.kind,
+        AssemblyErrorKind::UnknownMnemonicError(_)
+    ));
+
+    let error = assemble("jmp nowhere").unwrap_err();
+    assert!(matches!(
+        error.kind,
+        AssemblyErrorKind::UndefinedLabelError(_)
+    ));
+
+    let error = assemble("a: nop\na: nop").unwrap_err();
+    assert_eq!(error.line, 2);
+    assert!(matches!(
+        error.kind,
+        AssemblyErrorKind::DuplicateLabelError(_)
+    ));
+
+    let error = assemble("inc [bx]").unwrap_err();
+    assert!(matches!(
+        error.kind,
+        AssemblyErrorKind::EncodingError(EncodingError::MissingSizeError(Operation::INC))
+    ));
+
+    let error = assemble("mov al, 256").unwrap_err();
+    assert!(matches!(
+        error.kind,
+        AssemblyErrorKind::EncodingError(EncodingError::UnsupportedArgumentsError(_))
+    ));
+
+    // Segment registers cannot be loaded with immediate
+    assert!(assemble("mov es, 5").is_err());
+
+    assert_eq!(
+        assemble("mov ax, [bx\n").unwrap_err().to_string(),
+        "line 1: cannot parse '[bx'"
+    );
+}
@@ -0,0 +1,487 @@
+use rust_decode::{CpuFlags, Executable, InstructionBuffer, Reg, StopReason, CPU, PSP_SEGMENT};
+
+fn run(bytes: &[u8]) -> CPU<'_> {
+    let mut cpu = CPU::new(InstructionBuffer::from_bytes(bytes));
+
+    cpu.execute_instructions().unwrap();
+    cpu
+}
+
+fn word_at(cpu: &CPU, index: usize) -> i16 {
+    i16::from_le_bytes([cpu.memory()[index], cpu.memory()[index + 1]])
+}
+
+#[test]
+fn test_byte_registers() {
+    // mov ax, 0x1234; mov al, 5; mov ah, 1; mov bl, ah
+    let cpu = run(&[0xB8, 0x34, 0x12, 0xB0, 0x05, 0xB4, 0x01, 0x88, 0xE3]);
+
+    assert_eq!(cpu.register(Reg::A), 0x0105);
+    assert_eq!(cpu.register(Reg::B), 0x0001);
+    assert_eq!(cpu.register(Reg::Sp), 0);
+}
+
+#[test]
+fn test_byte_memory() {
+    // mov word [bp+258], -1; mov byte [bp+259], 0; mov cx, [bp+258]
+    let cpu = run(&[
+        0xC7, 0x86, 0x02, 0x01, 0xFF, 0xFF, 0xC6, 0x86, 0x03, 0x01, 0x00, 0x8B, 0x8E, 0x02, 0x01,
+    ]);
+
+    assert_eq!(word_at(&cpu, 0x102), 0x00FF);
+    assert_eq!(cpu.memory()[0x104], 0);
+    assert_eq!(cpu.register(Reg::C), 0x00FF);
+}
+
+#[test]
+fn test_sign_extended_immediates() {
+    // mov bx, 5; add bx, -1; mov ax, 16; sub ax, -2; mov cx, -3; cmp cx, -3
+    let cpu = run(&[
+        0xBB, 0x05, 0x00, 0x83, 0xC3, 0xFF, 0xB8, 0x10, 0x00, 0x83, 0xE8, 0xFE, 0xB9, 0xFD, 0xFF,
+        0x83, 0xF9, 0xFD,
+    ]);
+
+    assert_eq!(cpu.register(Reg::B), 4);
+    assert_eq!(cpu.register(Reg::A), 18);
+    assert_eq!(cpu.register(Reg::C), -3);
+    assert!(cpu.flags().contains(CpuFlags::Z));
+}
+
+#[test]
+fn test_byte_flags() {
+    // mov ax, 0x0100; sub al, 1
+    let cpu = run(&[0xB8, 0x00, 0x01, 0x2C, 0x01]);
+
+    assert_eq!(cpu.register(Reg::A), 0x01FF);
+    assert!(cpu.flags().contains(CpuFlags::S));
+    assert!(!cpu.flags().contains(CpuFlags::Z));
+}
+
+#[test]
+fn test_carry_flags() {
+    // mov ax, 0xFFFF; add ax, 1; inc bx; adc cx, 0; sbb dx, 0
+    let cpu = run(&[
+        0xB8, 0xFF, 0xFF, 0x05, 0x01, 0x00, 0x43, 0x83, 0xD1, 0x00, 0x83, 0xDA, 0x00,
+    ]);
+
+    assert_eq!(cpu.register(Reg::A), 0);
+    assert_eq!(cpu.register(Reg::C), 1);
+    assert_eq!(cpu.register(Reg::D), 0);
+    assert_eq!(cpu.flags(), CpuFlags::Z | CpuFlags::P);
+}
+
+#[test]
+fn test_sign_extended_immediate_flags() {
+    // mov ax, 1; add ax, -1
+    let cpu = run(&[0xB8, 0x01, 0x00, 0x83, 0xC0, 0xFF]);
+    assert_eq!(cpu.register(Reg::A), 0);
+    assert_eq!(
+        cpu.flags(),
+        CpuFlags::C | CpuFlags::A | CpuFlags::Z | CpuFlags::P
+    );
+
+    // mov ax, 16; sub ax, -2
+    let cpu = run(&[0xB8, 0x10, 0x00, 0x83, 0xE8, 0xFE]);
+    assert_eq!(cpu.register(Reg::A), 18);
+    assert_eq!(cpu.flags(), CpuFlags::C | CpuFlags::P | CpuFlags::A);
+
+    // mov ax, 0x7FFF; cmp ax, -1
+    let cpu = run(&[0xB8, 0xFF, 0x7F, 0x83, 0xF8, 0xFF]);
+    assert_eq!(cpu.register(Reg::A), 0x7FFF);
+    assert_eq!(
+        cpu.flags(),
+        CpuFlags::C | CpuFlags::P | CpuFlags::S | CpuFlags::O
+    );
+}
+
+#[test]
+fn test_inc_keeps_carry() {
+    // stc; mov al, 0x7F; inc al
+    let cpu = run(&[0xF9, 0xB0, 0x7F, 0xFE, 0xC0]);
+
+    assert_eq!(cpu.register(Reg::A), 0x80);
+    assert_eq!(
+        cpu.flags(),
+        CpuFlags::C | CpuFlags::A | CpuFlags::S | CpuFlags::O
+    );
+}
+
+#[test]
+fn test_logic_flags() {
+    // mov ax, 0x80F0; stc; and ax, 0x8FFF; test al, 0x0F
+    let cpu = run(&[0xB8, 0xF0, 0x80, 0xF9, 0x25, 0xFF, 0x8F, 0xA8, 0x0F]);
+
+    assert_eq!(cpu.register(Reg::A), -0x7F10);
+    assert_eq!(cpu.flags(), CpuFlags::Z | CpuFlags::P);
+}
+
+#[test]
+fn test_conditional_jumps() {
+    // mov ax, 1; cmp ax, 2; jl +3; mov bx, 1; jb +3; mov cx, 1; jg +3; mov dx, 1
+    let cpu = run(&[
+        0xB8, 0x01, 0x00, 0x3D, 0x02, 0x00, 0x7C, 0x03, 0xBB, 0x01, 0x00, 0x72, 0x03, 0xB9, 0x01,
+        0x00, 0x7F, 0x03, 0xBA, 0x01, 0x00,
+    ]);
+
+    assert_eq!(cpu.register(Reg::B), 0);
+    assert_eq!(cpu.register(Reg::C), 0);
+    assert_eq!(cpu.register(Reg::D), 1);
+}
+
+#[test]
+fn test_loops() {
+    // mov cx, 5; inc ax; loop -3; mov cx, 4; inc bx; cmp bx, 2; loopnz -6; jcxz +3; mov dx, 1
+    let cpu = run(&[
+        0xB9, 0x05, 0x00, 0x40, 0xE2, 0xFD, 0xB9, 0x04, 0x00, 0x43, 0x83, 0xFB, 0x02, 0xE0, 0xFA,
+        0xE3, 0x03, 0xBA, 0x01, 0x00,
+    ]);
+
+    assert_eq!(cpu.register(Reg::A), 5);
+    assert_eq!(cpu.register(Reg::B), 2);
+    assert_eq!(cpu.register(Reg::C), 2);
+    assert_eq!(cpu.register(Reg::D), 1);
+}
+
+#[test]
+fn test_rep_movsb() {
+    let mut cpu = CPU::new(InstructionBuffer::from_bytes(&[0xF3, 0xA4]));
+    cpu.memory_mut()[0x100..0x104].copy_from_slice(&[1, 2, 3, 4]);
+    cpu.set_register(Reg::Si, 0x100);
+    cpu.set_register(Reg::Di, 0x200);
+    cpu.set_register(Reg::C, 3);
+
+    cpu.execute_instructions().unwrap();
+
+    assert_eq!(cpu.memory()[0x200..0x204], [1, 2, 3, 0]);
+    assert_eq!(cpu.register(Reg::C), 0);
+    assert_eq!(cpu.register(Reg::Si), 0x103);
+    assert_eq!(cpu.register(Reg::Di), 0x203);
+}
+
+#[test]
+fn test_repne_scasb() {
+    // mov di, 0x10; mov cx, 8; mov al, 3; std; cld; repne scasb
+    let mut cpu = CPU::new(InstructionBuffer::from_bytes(&[
+        0xBF, 0x10, 0x00, 0xB9, 0x08, 0x00, 0xB0, 0x03, 0xFD, 0xFC, 0xF2, 0xAE,
+    ]));
+    cpu.memory_mut()[0x10..0x15].copy_from_slice(&[9, 8, 3, 7, 3]);
+
+    cpu.execute_instructions().unwrap();
+
+    assert_eq!(cpu.register(Reg::Di), 0x13);
+    assert_eq!(cpu.register(Reg::C), 5);
+    assert!(cpu.flags().contains(CpuFlags::Z));
+    assert!(!cpu.flags().contains(CpuFlags::D));
+}
+
+#[test]
+fn test_std_stosw() {
+    // mov ax, 0x1234; mov di, 4; std; stosw; stosw
+    let cpu = run(&[0xB8, 0x34, 0x12, 0xBF, 0x04, 0x00, 0xFD, 0xAB, 0xAB]);
+
+    assert_eq!(word_at(&cpu, 4), 0x1234);
+    assert_eq!(word_at(&cpu, 2), 0x1234);
+    assert_eq!(cpu.register(Reg::Di), 0);
+}
+
+#[test]
+fn test_segment_override() {
+    // mov ax, 0x100; mov es, ax; mov es:[bx], word 7; mov cx, [bx]; mov dx, es:[bx]
+    let cpu = run(&[
+        0xB8, 0x00, 0x01, 0x8E, 0xC0, 0x26, 0xC7, 0x07, 0x07, 0x00, 0x8B, 0x0F, 0x26, 0x8B, 0x17,
+    ]);
+
+    assert_eq!(cpu.register(Reg::Es), 0x100);
+    assert_eq!(word_at(&cpu, 0x1000), 7);
+    // Code of the program is at DS:0
+    assert_eq!(cpu.register(Reg::C), 0x00B8);
+    assert_eq!(cpu.register(Reg::D), 7);
+}
+
+#[test]
+fn test_push_pop() {
+    // mov ax, 0x1234; mov bx, 0x5678; push ax; push bx; pop ax; pop bx;
+    // mov [256], word 7; push word [256]; pop cx; push sp; pop dx
+    let cpu = run(&[
+        0xB8, 0x34, 0x12, 0xBB, 0x78, 0x56, 0x50, 0x53, 0x58, 0x5B, 0xC7, 0x06, 0x00, 0x01, 0x07,
+        0x00, 0xFF, 0x36, 0x00, 0x01, 0x59, 0x54, 0x5A,
+    ]);
+
+    assert_eq!(cpu.register(Reg::A), 0x5678);
+    assert_eq!(cpu.register(Reg::B), 0x1234);
+    assert_eq!(cpu.register(Reg::C), 7);
+    assert_eq!(cpu.register(Reg::D), -2);
+    assert_eq!(cpu.register(Reg::Sp), 0);
+    assert_eq!(word_at(&cpu, 0xFFFC), 0x5678);
+}
+
+#[test]
+fn test_pushf_popf() {
+    // stc; pushf; clc; popf
+    let cpu = run(&[0xF9, 0x9C, 0xF8, 0x9D]);
+
+    assert_eq!(cpu.flags(), CpuFlags::C);
+    assert_eq!(word_at(&cpu, 0xFFFE), -0x0FFD);
+}
+
+#[test]
+fn test_call_ret() {
+    // call procedure; mov dx, 1; push dx; call cleanup; jmp end;
+    // procedure: mov cx, 5; ret; cleanup: ret 2; end:
+    let cpu = run(&[
+        0xE8, 0x09, 0x00, 0xBA, 0x01, 0x00, 0x52, 0xE8, 0x06, 0x00, 0xEB, 0x07, 0xB9, 0x05, 0x00,
+        0xC3, 0xC2, 0x02, 0x00,
+    ]);
+
+    assert_eq!(cpu.register(Reg::C), 5);
+    assert_eq!(cpu.register(Reg::D), 1);
+    assert_eq!(cpu.register(Reg::Sp), 0);
+    assert_eq!(cpu.ip(), 19);
+    assert_eq!(word_at(&cpu, 0xFFFC), 10);
+}
+
+#[test]
+fn test_far_call() {
+    // call 0xFFFF:26; mov ax, 1; jmp end; far_procedure: mov bx, 2; retf; end:
+    // Address of the procedure wraps around the end of the memory to 10
+    let cpu = run(&[
+        0x9A, 0x1A, 0x00, 0xFF, 0xFF, 0xB8, 0x01, 0x00, 0xEB, 0x04, 0xBB, 0x02, 0x00, 0xCB,
+    ]);
+
+    assert_eq!(cpu.register(Reg::A), 1);
+    assert_eq!(cpu.register(Reg::B), 2);
+    assert_eq!(cpu.register(Reg::Cs), 0);
+    assert_eq!(cpu.register(Reg::Sp), 0);
+    assert_eq!(word_at(&cpu, 0xFFFE), 0);
+    assert_eq!(word_at(&cpu, 0xFFFC), 5);
+}
+
+#[test]
+fn test_int_iret() {
+    // mov [132], word handler; int 33; into; mov dx, 1; jmp end; handler: mov cx, 9; iret; end:
+    let mut cpu = CPU::new(InstructionBuffer::from_bytes(&[
+        0xC7, 0x06, 0x84, 0x00, 0x0E, 0x00, 0xCD, 0x21, 0xCE, 0xBA, 0x01, 0x00, 0xEB, 0x04, 0xB9,
+        0x09, 0x00, 0xCF,
+    ]));
+    cpu.set_register(Reg::Ss, 0x100);
+
+    cpu.execute_instructions().unwrap();
+
+    assert_eq!(cpu.register(Reg::C), 9);
+    assert_eq!(cpu.register(Reg::D), 1);
+    assert_eq!(cpu.register(Reg::Sp), 0);
+    assert_eq!(cpu.flags(), CpuFlags::empty());
+
+    // Flags, CS and return address are pushed in this order
+    assert_eq!(word_at(&cpu, 0x10FFE), -0x0FFE);
+    assert_eq!(word_at(&cpu, 0x10FFC), 0);
+    assert_eq!(word_at(&cpu, 0x10FFA), 8);
+}
+
+#[test]
+fn test_multiply_divide() {
+    // mov al, 200; mov bl, 3; mul bl; mov cx, ax; mov ax, -300; mov dx, 1000; imul dx;
+    // mov si, ax; mov di, dx; mov [256], word 7; mov ax, 100; mov dx, 0; div word [256];
+    // mov bp, dx; mov ax, -100; mov bl, 7; idiv bl
+    let cpu = run(&[
+        0xB0, 0xC8, 0xB3, 0x03, 0xF6, 0xE3, 0x89, 0xC1, 0xB8, 0xD4, 0xFE, 0xBA, 0xE8, 0x03, 0xF7,
+        0xEA, 0x89, 0xC6, 0x89, 0xD7, 0xC7, 0x06, 0x00, 0x01, 0x07, 0x00, 0xB8, 0x64, 0x00, 0xBA,
+        0x00, 0x00, 0xF7, 0x36, 0x00, 0x01, 0x89, 0xD5, 0xB8, 0x9C, 0xFF, 0xB3, 0x07, 0xF6, 0xFB,
+    ]);
+
+    assert_eq!(cpu.register(Reg::C), 600);
+    assert_eq!(cpu.register(Reg::Si), 0x6C20);
+    assert_eq!(cpu.register(Reg::Di), -5);
+    assert_eq!(cpu.register(Reg::D), 2);
+    assert_eq!(cpu.register(Reg::Bp), 2);
+    // Quotient -14 in AL and remainder -2 in AH
+    assert_eq!(cpu.register(Reg::A), 0xFEF2u16 as i16);
+    assert_eq!(cpu.flags(), CpuFlags::C | CpuFlags::O);
+}
+
+#[test]
+fn test_divide_error() {
+    // mov [0], word handler; mov ax, 1000; mov bl, 2; div bl; mov cx, 1; jmp end;
+    // handler: mov dx, 5; iret; end:
+    let cpu = run(&[
+        0xC7, 0x06, 0x00, 0x00, 0x12, 0x00, 0xB8, 0xE8, 0x03, 0xB3, 0x02, 0xF6, 0xF3, 0xB9, 0x01,
+        0x00, 0xEB, 0x04, 0xBA, 0x05, 0x00, 0xCF,
+    ]);
+
+    assert_eq!(cpu.register(Reg::A), 1000);
+    assert_eq!(cpu.register(Reg::C), 1);
+    assert_eq!(cpu.register(Reg::D), 5);
+    assert_eq!(cpu.register(Reg::Sp), 0);
+    // 8086 returns to the instruction after the division
+    assert_eq!(word_at(&cpu, 0xFFFA), 13);
+}
+
+#[test]
+fn test_decimal_adjust() {
+    // mov al, 0x38; add al, 0x45; daa; mov bl, al; mov al, 0x12; sub al, 0x29; das; mov bh, al;
+    // mov ax, 9; add al, 8; aaa; mov cx, ax; mov ax, 5; sub al, 8; aas; mov dx, ax;
+    // mov al, 78; aam; mov si, ax; aad; mov di, ax
+    let cpu = run(&[
+        0xB0, 0x38, 0x04, 0x45, 0x27, 0x88, 0xC3, 0xB0, 0x12, 0x2C, 0x29, 0x2F, 0x88, 0xC7, 0xB8,
+        0x09, 0x00, 0x04, 0x08, 0x37, 0x89, 0xC1, 0xB8, 0x05, 0x00, 0x2C, 0x08, 0x3F, 0x89, 0xC2,
+        0xB0, 0x4E, 0xD4, 0x0A, 0x89, 0xC6, 0xD5, 0x0A, 0x89, 0xC7,
+    ]);
+
+    assert_eq!(cpu.register(Reg::B), 0x8383u16 as i16);
+    assert_eq!(cpu.register(Reg::C), 0x0107);
+    assert_eq!(cpu.register(Reg::D), 0xFF07u16 as i16);
+    assert_eq!(cpu.register(Reg::Si), 0x0708);
+    assert_eq!(cpu.register(Reg::Di), 78);
+    assert_eq!(cpu.flags(), CpuFlags::C | CpuFlags::A | CpuFlags::P);
+}
+
+#[test]
+fn test_shift_rotate() {
+    // mov ax, 0x8421; mov cl, 4; rol ax, cl; mov bx, ax; shr bx, 1; mov dx, -16; sar dx, cl;
+    // not dx; mov [256], word 0x4001; stc; rcl word [256], 1; mov si, [256]; mov di, 3; ror di, 1
+    let cpu = run(&[
+        0xB8, 0x21, 0x84, 0xB1, 0x04, 0xD3, 0xC0, 0x89, 0xC3, 0xD1, 0xEB, 0xBA, 0xF0, 0xFF, 0xD3,
+        0xFA, 0xF7, 0xD2, 0xC7, 0x06, 0x00, 0x01, 0x01, 0x40, 0xF9, 0xD1, 0x16, 0x00, 0x01, 0x8B,
+        0x36, 0x00, 0x01, 0xBF, 0x03, 0x00, 0xD1, 0xCF,
+    ]);
+
+    assert_eq!(cpu.register(Reg::A), 0x4218);
+    assert_eq!(cpu.register(Reg::B), 0x210C);
+    assert_eq!(cpu.register(Reg::D), 0);
+    assert_eq!(cpu.register(Reg::Si), 0x8003u16 as i16);
+    assert_eq!(cpu.register(Reg::Di), 0x8001u16 as i16);
+    // Rotate sets only carry and overflow, sign and parity are left from RCL
+    assert_eq!(
+        cpu.flags(),
+        CpuFlags::C | CpuFlags::P | CpuFlags::S | CpuFlags::O
+    );
+}
+
+#[test]
+fn test_exchange_load_address() {
+    // mov ax, 1; mov bx, 2; xchg ax, bx; mov cx, 0x5678; mov [256], word 0x1234;
+    // xchg [256], cx; mov [258], word 0x500; les di, [256]; lea si, [bx+di+5]
+    let cpu = run(&[
+        0xB8, 0x01, 0x00, 0xBB, 0x02, 0x00, 0x93, 0xB9, 0x78, 0x56, 0xC7, 0x06, 0x00, 0x01, 0x34,
+        0x12, 0x87, 0x0E, 0x00, 0x01, 0xC7, 0x06, 0x02, 0x01, 0x00, 0x05, 0xC4, 0x3E, 0x00, 0x01,
+        0x8D, 0x71, 0x05,
+    ]);
+
+    assert_eq!(cpu.register(Reg::A), 2);
+    assert_eq!(cpu.register(Reg::B), 1);
+    assert_eq!(cpu.register(Reg::C), 0x1234);
+    assert_eq!(word_at(&cpu, 0x100), 0x5678);
+    assert_eq!(cpu.register(Reg::Di), 0x5678);
+    assert_eq!(cpu.register(Reg::Es), 0x500);
+    assert_eq!(cpu.register(Reg::Si), 0x567E);
+}
+
+#[test]
+fn test_convert_flags_transfer() {
+    // mov al, 0xF0; cbw; cwd; mov ah, 0xC5; sahf; cmc; lahf
+    let cpu = run(&[0xB0, 0xF0, 0x98, 0x99, 0xB4, 0xC5, 0x9E, 0xF5, 0x9F]);
+
+    assert_eq!(cpu.register(Reg::D), -1);
+    // Bit 1 of FLAGS is always set
+    assert_eq!(cpu.register(Reg::A), 0xC6F0u16 as i16);
+    assert_eq!(cpu.flags(), CpuFlags::S | CpuFlags::Z | CpuFlags::P);
+}
+
+#[test]
+fn test_translate_ports_halt() {
+    // mov bx, 256; mov [261], byte 42; mov al, 5; xlat; mov cl, al; in ax, 0x60; out 0x61, al;
+    // nop; wait; hlt; mov cx, 0
+    let cpu = run(&[
+        0xBB, 0x00, 0x01, 0xC6, 0x06, 0x05, 0x01, 0x2A, 0xB0, 0x05, 0xD7, 0x88, 0xC1, 0xE5, 0x60,
+        0xE6, 0x61, 0x90, 0x9B, 0xF4, 0xB9, 0x00, 0x00,
+    ]);
+
+    assert_eq!(cpu.register(Reg::C), 42);
+    // Nothing is attached to the ports
+    assert_eq!(cpu.register(Reg::A), -1);
+    assert!(cpu.is_halted());
+    assert_eq!(cpu.exit_code(), None);
+    assert_eq!(cpu.ip(), 20);
+}
+
+#[test]
+fn test_self_modifying_code() {
+    // mov byte [9], 7; mov ax, 1; mov cl, 0
+    let cpu = run(&[0xC6, 0x06, 0x09, 0x00, 0x07, 0xB8, 0x01, 0x00, 0xB1, 0x00]);
+
+    assert_eq!(cpu.register(Reg::A), 1);
+    assert_eq!(cpu.register(Reg::C), 7);
+}
+
+#[test]
+fn test_com_program() {
+    // mov si, table; lodsw; mov bx, ax; ret; exit: hlt; table: dw 0x1234
+    let executable =
+        Executable::com(&[0xBE, 0x08, 0x01, 0xAD, 0x89, 0xC3, 0xC3, 0xF4, 0x34, 0x12]).unwrap();
+    let mut cpu = CPU::load(&executable);
+
+    // Vector of INT 20h points at the exit of the program
+    cpu.memory_mut()[0x80..0x84].copy_from_slice(&[0x07, 0x01, 0x00, 0x10]);
+
+    assert_eq!(cpu.ip(), 0x100);
+    cpu.execute_instructions().unwrap();
+
+    assert_eq!(cpu.register(Reg::B), 0x1234);
+    // RET jumps to INT 20h at the start of the PSP
+    assert_eq!(cpu.stop_reason(), Some(StopReason::Halted));
+    assert_eq!(cpu.ip(), 0x108);
+    assert_eq!(cpu.register(Reg::Cs), PSP_SEGMENT as i16);
+    assert_eq!(cpu.register(Reg::Sp), -6);
+}
+
+#[test]
+fn test_left_program() {
+    // jmp 0:0x100
+    let mut cpu = CPU::load(&Executable::com(&[0xEA, 0x00, 0x01, 0x00, 0x00]).unwrap());
+    cpu.execute_instructions().unwrap();
+
+    assert_eq!(cpu.stop_reason(), Some(StopReason::LeftProgram(0x100)));
+
+    // Raw programs end once IP reaches their end
+    let cpu = run(&[0x90]);
+    assert_eq!(cpu.stop_reason(), Some(StopReason::ProgramEnd));
+}
+
+#[test]
+fn test_exe_program() {
+    // Header of 2 paragraphs with the relocation of the word at 1:1, code at 1:0, stack at 2:64
+    let mut bytes = vec![0; 32];
+    for (index, value) in [
+        (0x02, 32 + 25),
+        (0x04, 1),
+        (0x06, 1),
+        (0x08, 2),
+        (0x0E, 2),
+        (0x10, 64),
+        (0x16, 1),
+        (0x18, 0x1C),
+        (0x1C, 1),
+        (0x1E, 1),
+    ] {
+        bytes[index..index + 2].copy_from_slice(&(value as u16).to_le_bytes());
+    }
+    bytes[..2].copy_from_slice(b"MZ");
+
+    // data: dw 0x5678, code: mov ax, seg data; mov ds, ax; mov bx, [0]
+    bytes.extend([0x78, 0x56]);
+    bytes.extend([0; 14]);
+    bytes.extend([0xB8, 0x00, 0x00, 0x8E, 0xD8, 0x8B, 0x1E, 0x00, 0x00]);
+
+    let mut cpu = CPU::load(&Executable::exe(&bytes).unwrap());
+    cpu.execute_instructions().unwrap();
+
+    let load_segment = PSP_SEGMENT as i16 + 0x10;
+    assert_eq!(cpu.register(Reg::A), load_segment);
+    assert_eq!(cpu.register(Reg::Ds), load_segment);
+    assert_eq!(cpu.register(Reg::B), 0x5678);
+    assert_eq!(cpu.register(Reg::Cs), load_segment + 1);
+    assert_eq!(cpu.register(Reg::Ss), load_segment + 2);
+    assert_eq!(cpu.register(Reg::Sp), 64);
+    assert_eq!(cpu.register(Reg::Es), PSP_SEGMENT as i16);
+    assert_eq!(cpu.ip(), 9);
+}
@@ -0,0 +1,181 @@
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::Duration;
+
+use rust_decode::{Debugger, DebuggerError, InstructionBuffer, Reg, CPU};
+
+// call procedure; mov dx, 1; push dx; call cleanup; jmp end;
+// procedure: mov cx, 5; ret; cleanup: ret 2; end:
+const CALLS: [u8; 19] = [
+    0xE8, 0x09, 0x00, 0xBA, 0x01, 0x00, 0x52, 0xE8, 0x06, 0x00, 0xEB, 0x07, 0xB9, 0x05, 0x00, 0xC3,
+    0xC2, 0x02, 0x00,
+];
+
+// mov ax, 0x1234; mov bx, 0x5678; push ax; push bx; pop ax; pop bx;
+// mov [256], word 7; push word [256]; pop cx
+const STACK: [u8; 21] = [
+    0xB8, 0x34, 0x12, 0xBB, 0x78, 0x56, 0x50, 0x53, 0x58, 0x5B, 0xC7, 0x06, 0x00, 0x01, 0x07, 0x00,
+    0xFF, 0x36, 0x00, 0x01, 0x59,
+];
+
+fn debugger(bytes: &[u8]) -> Debugger<'_> {
+    Debugger::new(CPU::new(InstructionBuffer::from_bytes(bytes)))
+}
+
+fn run(debugger: &mut Debugger, line: &str) -> String {
+    debugger.run(line.parse().unwrap()).unwrap()
+}
+
+#[test]
+fn test_breakpoints() {
+    let mut debugger = debugger(&CALLS);
+
+    assert_eq!(
+        run(&mut debugger, "break label_0"),
+        "breakpoint at 0x000c (label_0)"
+    );
+    assert_eq!(
+        run(&mut debugger, "continue"),
+        "breakpoint at 0x000c (label_0)"
+    );
+    assert_eq!(debugger.cpu().ip(), 0x0C);
+
+    run(&mut debugger, "break 0x10");
+    run(&mut debugger, "continue");
+    assert_eq!(debugger.cpu().ip(), 0x10);
+    assert_eq!(debugger.cpu().register(Reg::D), 1);
+
+    assert_eq!(run(&mut debugger, "continue"), "program finished");
+    assert_eq!(debugger.cpu().register(Reg::Sp), 0);
+}
+
+#[test]
+fn test_step_over_call() {
+    let mut debugger = debugger(&CALLS);
+
+    run(&mut debugger, "next");
+    assert_eq!(debugger.cpu().ip(), 3);
+    assert_eq!(debugger.cpu().register(Reg::C), 5);
+
+    run(&mut debugger, "step 2");
+    assert_eq!(debugger.cpu().ip(), 7);
+
+    run(&mut debugger, "next");
+    assert_eq!(debugger.cpu().ip(), 10);
+    assert_eq!(debugger.cpu().register(Reg::Sp), 0);
+}
+
+#[test]
+fn test_step_back() {
+    let mut debugger = debugger(&STACK);
+
+    run(&mut debugger, "step 8");
+    assert_eq!(debugger.cpu().memory()[256], 7);
+    assert_eq!(debugger.cpu().register(Reg::Sp), -2);
+
+    assert_eq!(run(&mut debugger, "back 2"), "back at 0x000a");
+    assert_eq!(debugger.cpu().memory()[256], 0);
+    assert_eq!(debugger.cpu().register(Reg::Sp), 0);
+    assert_eq!(debugger.cpu().register(Reg::A), 0x5678);
+
+    // Pushed words are removed from the stack as well
+    run(&mut debugger, "back 100");
+    assert_eq!(debugger.cpu().ip(), 0);
+    assert!(debugger.cpu().memory()[0xFFFC..]
+        .iter()
+        .all(|byte| *byte == 0));
+    assert_eq!(debugger.cpu().register(Reg::A), 0);
+
+    assert!(matches!(
+        debugger.run("back".parse().unwrap()),
+        Err(DebuggerError::EmptyHistoryError)
+    ));
+}
+
+#[test]
+fn test_watchpoints() {
+    let mut debugger = debugger(&STACK);
+
+    run(&mut debugger, "watch 0x100..0x102");
+    assert_eq!(
+        run(&mut debugger, "continue"),
+        "watchpoint [0x100..0x102] changed from 00 00 to 07 00"
+    );
+    assert_eq!(debugger.cpu().ip(), 0x10);
+
+    run(&mut debugger, "unwatch 0x100..0x102");
+    run(&mut debugger, "watch cl");
+    assert_eq!(
+        run(&mut debugger, "continue"),
+        "watchpoint cx changed from 00 00 to 07 00"
+    );
+}
+
+#[test]
+fn test_inspect_and_modify() {
+    let mut debugger = debugger(&STACK);
+
+    assert_eq!(run(&mut debugger, "set ah 0x12"), "ax: 0x1200");
+    assert_eq!(run(&mut debugger, "set ip 3"), "ip: 0x0003");
+    assert_eq!(run(&mut debugger, "write 0x20 1 2 0xff"), "00020: 01 02 ff");
+    assert_eq!(
+        run(&mut debugger, "memory 0x1e 20"),
+        "0001e: 00 00 01 02 ff 00 00 00 00 00 00 00 00 00 00 00\n0002e: 00 00 00 00"
+    );
+
+    assert_eq!(
+        run(&mut debugger, "list 1"),
+        "   00000  mov ax, 4660\n=> 00003  mov bx, 22136\n   00006  push ax"
+    );
+
+    run(&mut debugger, "step");
+    assert_eq!(debugger.cpu().register(Reg::A), 0x1200);
+    assert_eq!(debugger.cpu().register(Reg::B), 0x5678);
+
+    assert!(matches!(
+        debugger.run("break nowhere".parse().unwrap()),
+        Err(DebuggerError::UndefinedLabelError(_))
+    ));
+}
+
+#[test]
+fn test_halt_and_execution_error() {
+    // nop; hlt; mov ax, 1
+    let mut halted = debugger(&[0x90, 0xF4, 0xB8, 0x01, 0x00]);
+
+    assert_eq!(run(&mut halted, "continue"), "program halted");
+    assert!(halted.cpu().is_halted());
+    assert_eq!(halted.cpu().register(Reg::A), 0);
+
+    // nop; salc is not an instruction of 8086
+    let mut failing = debugger(&[0x90, 0xD6]);
+
+    assert!(matches!(
+        failing.run("step 2".parse().unwrap()),
+        Err(DebuggerError::ExecutionError(_))
+    ));
+    assert_eq!(failing.cpu().ip(), 1);
+}
+
+#[test]
+fn test_interrupt() {
+    // inc ax; label_0: jmp label_0
+    let mut looping = debugger(&[0x40, 0xEB, 0xFE]);
+    let interrupted = looping.interrupt_flag();
+
+    // Interrupt before the command does not stop it
+    interrupted.store(true, Ordering::Relaxed);
+    run(&mut looping, "step");
+    assert_eq!(looping.cpu().ip(), 1);
+
+    let interrupter = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(50));
+        interrupted.store(true, Ordering::Relaxed);
+    });
+
+    assert_eq!(
+        run(&mut looping, "continue"),
+        "interrupted at 0x0001 (label_0)"
+    );
+    interrupter.join().unwrap();
+}
@@ -0,0 +1,407 @@
+use rust_decode::{
+    disassemble, disassemble_bytes_in, disassemble_bytes_resilient, disassemble_next_instruction,
+    Decoded, DecodingError, DisassemblyErrorKind, InstructionBuffer, Operation,
+};
+
+fn test_instruction(bytes: Vec<u8>, instruction_str: &str) {
+    let mut buffer = InstructionBuffer::from_bytes(&bytes);
+
+    let instruction = disassemble_next_instruction(&mut buffer).unwrap();
+
+    println!("{:?}", instruction);
+
+    assert_eq!(buffer.position(), bytes.len());
+    assert_eq!(instruction.length(), bytes.len());
+    assert_eq!(format!("{}", instruction), instruction_str);
+}
+
+#[test]
+fn test_memory_mode_instruction() {
+    test_instruction(vec![0x8A, 0x00], "mov al, [bx + si]")
+}
+
+#[test]
+fn test_memory_mode_instruction_displacement() {
+    test_instruction(vec![0x8A, 0x80, 0x87, 0x13], "mov al, [bx + si+4999]")
+}
+
+#[test]
+fn test_mov_a() {
+    test_instruction(vec![0x8b, 0x41, 0xdb], "mov ax, [bx + di-37]")
+}
+
+#[test]
+fn test_immediate_mov_mem_to_acc() {
+    test_instruction(vec![0xa1, 0xfb, 0x09], "mov ax, [2555]")
+}
+
+#[test]
+fn test_immediate_acc_to_mem() {
+    test_instruction(vec![0xa3, 0x0f, 0x00], "mov [15], ax")
+}
+
+#[test]
+fn test_immediate_mov_direct_addr() {
+    test_instruction(vec![0x8b, 0x2e, 0x05, 0x00], "mov bp, [5]")
+}
+
+#[test]
+fn test_immediate_to_register() {
+    test_instruction(vec![0xBA, 0x6C, 0x0F], "mov dx, 3948")
+}
+
+#[test]
+fn test_immediate_to_memory() {
+    test_instruction(vec![0xC6, 0x03, 0x07], "mov [bp + di], byte 7")
+}
+
+#[test]
+fn test_immediate_add() {
+    test_instruction(vec![0x83, 0xc6, 0x02], "add si, 2")
+}
+
+#[test]
+fn test_immediate_add_a() {
+    test_instruction(vec![0x03, 0x18], "add bx, [bx + si]")
+}
+
+#[test]
+fn test_immediate_add_b() {
+    test_instruction(vec![0x03, 0x18], "add bx, [bx + si]")
+}
+
+#[test]
+fn test_immediate_add_c() {
+    test_instruction(vec![0x02, 0x7A, 0x04], "add bh, [bp + si+4]")
+}
+
+#[test]
+fn test_immediate_add_d() {
+    test_instruction(vec![0x05, 0xe8, 0x03], "add ax, 1000")
+}
+
+#[test]
+fn test_immediate_sub_a() {
+    test_instruction(vec![0x83, 0xee, 0x02], "sub si, 2")
+}
+
+#[test]
+fn test_immediate_sub_b() {
+    test_instruction(vec![0x2d, 0xe8, 0x03], "sub ax, 1000")
+}
+
+#[test]
+fn test_immediate_cmp() {
+    test_instruction(vec![0x3b, 0x18], "cmp bx, [bx + si]")
+}
+
+#[test]
+fn test_immediate_cmp_a() {
+    test_instruction(vec![0x3d, 0xe8, 0x03], "cmp ax, 1000")
+}
+
+#[test]
+fn test_immediate_cmp_b() {
+    test_instruction(vec![0x83, 0x3e, 0xe2, 0x12, 0x1d], "cmp word [4834], 29")
+}
+
+#[test]
+fn test_push_pop() {
+    test_instruction(vec![0x51], "push cx");
+    test_instruction(vec![0xff, 0x32], "push word [bp + si]");
+    test_instruction(vec![0x8f, 0x47, 0x02], "pop word [bx+2]")
+}
+
+#[test]
+fn test_xchg() {
+    test_instruction(vec![0x86, 0x18], "xchg [bx + si], bl");
+    test_instruction(vec![0x93], "xchg ax, bx");
+    test_instruction(vec![0x90], "nop")
+}
+
+#[test]
+fn test_in_out() {
+    test_instruction(vec![0xe4, 0xc8], "in al, -56");
+    test_instruction(vec![0xed], "in ax, dx");
+    test_instruction(vec![0xe7, 0x2c], "out 44, ax");
+    test_instruction(vec![0xee], "out dx, al")
+}
+
+#[test]
+fn test_lea_lds_les() {
+    test_instruction(vec![0x8d, 0x81, 0x8c, 0x05], "lea ax, [bx + di+1420]");
+    test_instruction(vec![0xc5, 0x1c], "lds bx, [si]");
+    test_instruction(vec![0xc4, 0x3e, 0x04, 0x00], "les di, [4]")
+}
+
+#[test]
+fn test_single_operand_arithmetic() {
+    test_instruction(vec![0xfe, 0xc6], "inc dh");
+    test_instruction(vec![0x4d], "dec bp");
+    test_instruction(vec![0xf7, 0x5e, 0x0a], "neg word [bp+10]");
+    test_instruction(vec![0xf6, 0x26, 0x4d, 0x01], "mul byte [333]");
+    test_instruction(vec![0xf7, 0xf9], "idiv cx")
+}
+
+#[test]
+fn test_immediate_group() {
+    test_instruction(vec![0x15, 0x10, 0x27], "adc ax, 10000");
+    test_instruction(vec![0x83, 0xdb, 0xff], "sbb bx, -1");
+    test_instruction(vec![0x80, 0x27, 0x0f], "and byte [bx], 15");
+    test_instruction(vec![0x81, 0xf1, 0x00, 0x10], "xor cx, 4096")
+}
+
+#[test]
+fn test_shift_rotate() {
+    test_instruction(vec![0xd1, 0xe0], "shl ax, 1");
+    test_instruction(vec![0xd2, 0x3f], "sar byte [bx], cl");
+    test_instruction(vec![0xd3, 0x56, 0x05], "rcl word [bp+5], cl")
+}
+
+#[test]
+fn test_test() {
+    test_instruction(vec![0x85, 0xcb], "test bx, cx");
+    test_instruction(vec![0xf6, 0x07, 0x80], "test byte [bx], -128");
+    test_instruction(vec![0xa9, 0xff, 0x00], "test ax, 255")
+}
+
+#[test]
+fn test_no_operand() {
+    test_instruction(vec![0x98], "cbw");
+    test_instruction(vec![0xd7], "xlatb");
+    test_instruction(vec![0xd4, 0x0a], "aam");
+    test_instruction(vec![0xd5, 0x10], "aad 16");
+    test_instruction(vec![0xfd], "std")
+}
+
+#[test]
+fn test_string() {
+    test_instruction(vec![0xa4], "movsb");
+    test_instruction(vec![0xa7], "cmpsw");
+    test_instruction(vec![0xab], "stosw")
+}
+
+#[test]
+fn test_control_transfer() {
+    test_instruction(vec![0xe8, 0x00, 0x01], "call $+259");
+    test_instruction(vec![0xff, 0xd3], "call bx");
+    test_instruction(vec![0x9a, 0x00, 0x01, 0x34, 0x12], "call 4660:256");
+    test_instruction(vec![0xff, 0x1f], "call far [bx]");
+    test_instruction(vec![0xeb, 0xfe], "jmp $+0");
+    test_instruction(vec![0xe9, 0x00, 0x00], "jmp near $+3");
+    test_instruction(vec![0xe9, 0x7f, 0x00], "jmp $+130");
+    test_instruction(vec![0xff, 0x2e, 0x10, 0x00], "jmp far [16]");
+    test_instruction(vec![0xc2, 0x04, 0x00], "ret 4");
+    test_instruction(vec![0xcb], "retf")
+}
+
+#[test]
+fn test_segment_registers() {
+    test_instruction(vec![0x8e, 0xd8], "mov ds, ax");
+    test_instruction(vec![0x8c, 0x47, 0x04], "mov [bx+4], es");
+    test_instruction(vec![0x1e], "push ds");
+    test_instruction(vec![0x17], "pop ss")
+}
+
+#[test]
+fn test_segment_override() {
+    test_instruction(vec![0x26, 0x8b, 0x00], "mov ax, es:[bx + si]");
+    test_instruction(vec![0x2e, 0xc6, 0x46, 0x02, 0x05], "mov cs:[bp+2], byte 5");
+    test_instruction(vec![0x36, 0xa1, 0x10, 0x00], "mov ax, ss:[16]")
+}
+
+#[test]
+fn test_prefixes() {
+    test_instruction(vec![0xf3, 0xa4], "rep movsb");
+    test_instruction(vec![0xf3, 0xa7], "rep cmpsw");
+    test_instruction(vec![0xf2, 0xae], "repne scasb");
+    test_instruction(vec![0xf3, 0xad], "rep lodsw");
+    test_instruction(vec![0xf3, 0xaa], "rep stosb");
+    test_instruction(vec![0x2e, 0xf3, 0xa5], "cs rep movsw");
+    test_instruction(vec![0xf3, 0x26, 0xa6], "rep es cmpsb");
+    test_instruction(vec![0x2e, 0xd7], "cs xlatb");
+    // 8086 ignores segment override without memory operand, it stays a prefix
+    test_instruction(vec![0x2e, 0x40], "cs inc ax");
+    test_instruction(vec![0x26, 0xf3, 0xaa], "es rep stosb");
+    test_instruction(vec![0xf0, 0x87, 0x07], "lock xchg [bx], ax");
+    test_instruction(vec![0xf0, 0x26, 0xfe, 0x07], "lock inc byte es:[bx]")
+}
+
+#[test]
+fn test_jump_labels() {
+    // mov cx, 3; label_0: dec cx; jnz label_0; je label_1; jmp $-9; label_1: loop label_0
+    let bytes = vec![
+        0xb9, 0x03, 0x00, 0x49, 0x75, 0xfd, 0x74, 0x02, 0xeb, 0xf5, 0xe2, 0xf7,
+    ];
+    let instructions = disassemble(&bytes).unwrap();
+    let labels: Vec<_> = instructions.iter().map(|instr| instr.label()).collect();
+    let text: Vec<_> = instructions.iter().map(|instr| instr.to_string()).collect();
+
+    assert_eq!(
+        labels,
+        [None, Some("label_0"), None, None, None, Some("label_1")]
+    );
+    assert_eq!(
+        text,
+        [
+            "mov cx, 3",
+            "dec cx",
+            "jnz label_0",
+            "je label_1",
+            "jmp $-9",
+            "loop label_0"
+        ]
+    );
+}
+
+#[test]
+fn test_interrupts() {
+    test_instruction(vec![0xcd, 0x21], "int 33");
+    test_instruction(vec![0xcc], "int3");
+    test_instruction(vec![0xcf], "iret")
+}
+
+#[test]
+fn test_group_extensions() {
+    let members = [
+        "inc word [bx]",
+        "dec word [bx]",
+        "call word [bx]",
+        "call far [bx]",
+        "jmp word [bx]",
+        "jmp far [bx]",
+        "push word [bx]",
+    ];
+
+    for (extension, member) in members.iter().enumerate() {
+        test_instruction(vec![0xff, 0b00_000_111 | (extension as u8) << 3], member);
+    }
+
+    // Data bytes are given by the member of the group, not by the opcode
+    test_instruction(vec![0xf6, 0x07, 0x05], "test byte [bx], 5");
+    test_instruction(vec![0xf6, 0x17], "not byte [bx]");
+
+    assert!(disassemble(&[0xff, 0x3f]).is_err());
+    assert!(disassemble(&[0x8e, 0xe0]).is_err());
+}
+
+#[test]
+fn test_buffer_sources() {
+    let bytes = [0x89, 0xd9, 0xb0, 0x05];
+    let path = std::env::temp_dir().join("rust_decode_test_buffer_sources.bin");
+    std::fs::write(&path, bytes).unwrap();
+
+    let buffers = [
+        InstructionBuffer::from_bytes(&bytes),
+        InstructionBuffer::from_reader(&bytes[..]).unwrap(),
+        InstructionBuffer::map_file(&path).unwrap(),
+    ];
+
+    for buffer in buffers {
+        let text: Vec<_> = disassemble_bytes_in(buffer)
+            .unwrap()
+            .iter()
+            .map(|instr| instr.to_string())
+            .collect();
+
+        assert_eq!(text, ["mov cx, bx", "mov al, 5"]);
+    }
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_unknown_opcode_error() {
+    let error = disassemble(&[0x89, 0xd9, 0xd6, 0x90]).unwrap_err();
+
+    assert!(matches!(
+        error.kind,
+        DisassemblyErrorKind::DecodeError(DecodingError::UnknownOpcodeError(0xD6))
+    ));
+    assert_eq!(error.offset, 2);
+    assert_eq!(error.bytes, [0xD6]);
+    assert!(error.instruction.is_none());
+    assert_eq!(error.to_string(), "unknown opcode 0xD6 at 0x0002: D6");
+}
+
+#[test]
+fn test_unknown_extension_error() {
+    let error = disassemble(&[0x2e, 0x8f, 0xc8]).unwrap_err();
+
+    assert!(matches!(
+        error.kind,
+        DisassemblyErrorKind::DecodeError(DecodingError::UnknownExtensionError(0x8F, 1))
+    ));
+    assert_eq!(error.offset, 0);
+    assert_eq!(error.bytes, [0x2E, 0x8F, 0xC8]);
+    assert!(error.instruction.is_some());
+
+    // Reserved bit of the segment register field is set
+    let error = disassemble(&[0x8c, 0xe0]).unwrap_err();
+    assert_eq!(error.to_string(), "unknown opcode 0x8C /4 at 0x0000: 8C E0");
+}
+
+#[test]
+fn test_register_instead_of_memory_error() {
+    for (bytes, opcode, extension) in [
+        ([0x8d, 0xc0], 0x8D, None),
+        ([0xc5, 0xd8], 0xC5, None),
+        ([0xc4, 0xc0], 0xC4, None),
+        ([0xff, 0xd8], 0xFF, Some(3)),
+        ([0xff, 0xe8], 0xFF, Some(5)),
+    ] {
+        let error = disassemble(&bytes).unwrap_err();
+
+        assert!(matches!(
+            error.kind,
+            DisassemblyErrorKind::DecodeError(DecodingError::MemoryOperandRequiredError(o, e))
+                if o == opcode && e == extension
+        ));
+    }
+
+    let error = disassemble(&[0xff, 0xd8]).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "opcode 0xFF /3 needs memory operand at 0x0000: FF D8"
+    );
+}
+
+#[test]
+fn test_truncated_instruction_error() {
+    let error = disassemble(&[0x90, 0x8b, 0x86, 0x10]).unwrap_err();
+
+    assert!(matches!(error.kind, DisassemblyErrorKind::BufferError(_)));
+    assert_eq!(error.offset, 1);
+    assert_eq!(error.bytes, [0x8B, 0x86, 0x10]);
+    assert_eq!(error.instruction.unwrap().operation(), Operation::MOV);
+}
+
+#[test]
+fn test_resilient_disassembly() {
+    let bytes = [0xeb, 0x03, 0xd6, 0x2e, 0x8f, 0x89, 0xd9, 0xb0];
+
+    let decoded = disassemble_bytes_resilient(InstructionBuffer::from_bytes(&bytes));
+    let text: Vec<_> = decoded.iter().map(|line| line.to_string()).collect();
+
+    assert_eq!(
+        text,
+        [
+            "jmp label_0",
+            "db 0xd6 ; unknown opcode 0xD6",
+            "db 0x2e ; unknown opcode 0x8F /1",
+            "db 0x8f ; unknown opcode 0x8F /1",
+            "mov cx, bx",
+            "db 0xb0 ; program ends in the middle of instruction",
+        ]
+    );
+
+    match &decoded[4] {
+        Decoded::Instruction(instr) => assert_eq!(instr.label(), Some("label_0")),
+        Decoded::Data(_, _) => panic!("Expected instruction"),
+    }
+
+    let decoded = disassemble_bytes_resilient(InstructionBuffer::from_bytes(&[0x2e, 0x40]));
+    let text: Vec<_> = decoded.iter().map(|line| line.to_string()).collect();
+
+    assert_eq!(text, ["cs inc ax"]);
+}
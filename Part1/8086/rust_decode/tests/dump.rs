@@ -0,0 +1,70 @@
+use std::fs;
+
+use rust_decode::{DumpFormat, InstructionBuffer, MemoryDump, CPU};
+
+const DRAW_RECT: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/../listings/fixtures/54_draw_rect.bin"
+);
+
+/// Image of 64x64 pixels the listing draws from BP=256
+fn draw_rect(format: DumpFormat) -> Vec<u8> {
+    let bytes = fs::read(DRAW_RECT).unwrap();
+    let mut cpu = CPU::new(InstructionBuffer::from_bytes(&bytes));
+    cpu.execute_instructions().unwrap();
+
+    let dump = MemoryDump {
+        range: 256..256 + 64 * 64 * 4,
+        format,
+        width: 64,
+    };
+    let mut output = Vec::new();
+    cpu.dump_memory(&dump, &mut output).unwrap();
+
+    output
+}
+
+/// Red is the X and blue the Y coordinate of the pixel
+fn pixel(x: usize, y: usize) -> [u8; 4] {
+    [x as u8, 0, y as u8, 255]
+}
+
+#[test]
+fn test_png_dump() {
+    let png = draw_rect(DumpFormat::Png);
+
+    let mut reader = png::Decoder::new(&png[..]).read_info().unwrap();
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut pixels).unwrap();
+
+    assert_eq!((info.width, info.height), (64, 64));
+    assert_eq!(info.color_type, png::ColorType::Rgba);
+    assert_eq!(pixels[..4], pixel(0, 0));
+    assert_eq!(pixels[(5 * 64 + 7) * 4..][..4], pixel(7, 5));
+    assert_eq!(pixels[pixels.len() - 4..], pixel(63, 63));
+}
+
+#[test]
+fn test_ppm_dump() {
+    let ppm = draw_rect(DumpFormat::Ppm);
+    let header = b"P6\n64 64\n255\n";
+
+    assert_eq!(ppm[..header.len()], *header);
+    assert_eq!(ppm.len(), header.len() + 64 * 64 * 3);
+    assert_eq!(
+        ppm[header.len() + (63 * 64 + 2) * 3..][..3],
+        pixel(2, 63)[..3]
+    );
+}
+
+#[test]
+fn test_raw_and_hex_dumps() {
+    assert_eq!(draw_rect(DumpFormat::Raw)[4..8], pixel(1, 0));
+
+    let hex = String::from_utf8(draw_rect(DumpFormat::Hex)).unwrap();
+    assert_eq!(
+        hex.lines().nth(1).unwrap(),
+        "00110: 04 00 00 ff 05 00 00 ff 06 00 00 ff 07 00 00 ff"
+    );
+    assert_eq!(hex.lines().count(), 64 * 64 * 4 / 16);
+}
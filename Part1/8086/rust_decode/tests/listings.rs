@@ -0,0 +1,106 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use strum::IntoEnumIterator;
+
+use rust_decode::{assemble, disassemble_bytes_in, InstructionBuffer, Reg, CPU};
+
+const LISTINGS: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../listings");
+
+/// Fixtures of the listing are stored under its name in the fixtures directory
+fn fixture(listing: &Path, extension: &str) -> PathBuf {
+    let name = listing.file_stem().unwrap();
+
+    Path::new(LISTINGS)
+        .join("fixtures")
+        .join(name)
+        .with_extension(extension)
+}
+
+fn listings() -> Vec<PathBuf> {
+    let mut listings: Vec<PathBuf> = fs::read_dir(LISTINGS)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "asm"))
+        .collect();
+
+    listings.sort();
+    listings
+}
+
+/// Same text the command line prints for the program
+fn disassembly(bytes: &[u8]) -> String {
+    let instructions = disassemble_bytes_in(InstructionBuffer::from_bytes(bytes)).unwrap();
+    let mut text = "bits 16\n".to_string();
+
+    for instruction in instructions {
+        if let Some(label) = instruction.label() {
+            text += &format!("{}:\n", label);
+        }
+
+        text += &format!("{}\n", instruction);
+    }
+
+    text
+}
+
+/// Registers missing from the expected state are zero
+fn check_registers(listing: &Path, bytes: &[u8], expected: &str) {
+    let mut cpu = CPU::new(InstructionBuffer::from_bytes(bytes));
+    cpu.execute_instructions().unwrap();
+
+    let mut registers: Vec<(String, u16)> = Reg::iter()
+        .map(|reg| (reg.to_string(), 0))
+        .chain([("ip".to_string(), 0)])
+        .collect();
+
+    for line in expected.lines().skip(1) {
+        let (name, value) = line.split_once(':').unwrap();
+
+        match name.trim() {
+            "flags" => assert_eq!(cpu.flags().to_string(), value.trim(), "{:?}", listing),
+            name => {
+                let hex = value
+                    .split_whitespace()
+                    .next()
+                    .unwrap()
+                    .trim_start_matches("0x");
+                let register = registers.iter_mut().find(|(reg, _)| reg == name).unwrap();
+
+                register.1 = u16::from_str_radix(hex, 16).unwrap();
+            }
+        }
+    }
+
+    for (name, value) in registers {
+        let actual = match Reg::iter().find(|reg| reg.to_string() == name) {
+            Some(reg) => cpu.register(reg) as u16,
+            None => cpu.ip() as u16,
+        };
+
+        assert_eq!(actual, value, "{} of {:?}", name, listing);
+    }
+}
+
+#[test]
+fn test_listings() {
+    let listings = listings();
+    assert!(!listings.is_empty());
+
+    for listing in listings {
+        let bytes = fs::read(fixture(&listing, "bin")).unwrap();
+        let source = fs::read_to_string(&listing).unwrap();
+
+        assert_eq!(assemble(&source).unwrap(), bytes, "{:?}", listing);
+
+        let text = disassembly(&bytes);
+        let expected = fs::read_to_string(fixture(&listing, "txt")).unwrap();
+
+        assert_eq!(text, expected, "{:?}", listing);
+        assert_eq!(assemble(&text).unwrap(), bytes, "{:?}", listing);
+
+        if let Ok(expected) = fs::read_to_string(fixture(&listing, "registers")) {
+            check_registers(&listing, &bytes, &expected);
+        }
+    }
+}
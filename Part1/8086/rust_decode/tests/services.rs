@@ -0,0 +1,184 @@
+use std::{fs, process};
+
+use rust_decode::{assemble, CpuFlags, DosServices, Executable, Reg, Services, CPU, PSP_SEGMENT};
+
+/// Physical address of the offset in the segment of the .COM program
+fn address(offset: usize) -> usize {
+    ((PSP_SEGMENT as usize) << 4) + offset
+}
+
+fn com(source: &str) -> CPU<'static> {
+    CPU::load(&Executable::com(&assemble(source).unwrap()).unwrap())
+}
+
+#[test]
+fn test_console_output() {
+    let mut output = Vec::new();
+    let mut cpu = com("
+        mov ah, 9
+        mov dx, 0x200
+        int 0x21
+        mov ah, 2
+        mov dl, '!'
+        int 0x21
+        mov ax, 0x0E3F
+        int 0x10
+        mov ax, 0x4C03
+        int 0x21
+        mov bx, 1");
+    cpu.memory_mut()[address(0x200)..address(0x208)].copy_from_slice(b"Hello\r\n$");
+
+    cpu.set_services(Box::new(DosServices::new(&b""[..], &mut output)));
+    cpu.execute_instructions().unwrap();
+    drop(cpu);
+
+    assert_eq!(output, b"Hello\r\n!?");
+}
+
+#[test]
+fn test_terminate() {
+    let mut cpu = com("mov ax, 0x4C03\nint 0x21\nmov bx, 1");
+    cpu.set_services(Box::new(DosServices::new(&b""[..], Vec::new())));
+    cpu.execute_instructions().unwrap();
+
+    assert_eq!(cpu.exit_code(), Some(3));
+    assert_eq!(cpu.register(Reg::B), 0);
+    assert!(cpu.is_finished());
+
+    // RET of .COM program runs INT 20h at the start of the PSP
+    let mut cpu = com("mov bx, 1\nret\nmov bx, 2");
+    cpu.set_services(Box::new(DosServices::new(&b""[..], Vec::new())));
+    cpu.execute_instructions().unwrap();
+
+    assert_eq!(cpu.exit_code(), Some(0));
+    assert_eq!(cpu.register(Reg::B), 1);
+}
+
+#[test]
+fn test_console_input() {
+    let mut output = Vec::new();
+    let mut cpu = com("
+        mov ah, 1
+        int 0x21
+        mov bl, al
+        mov ah, 8
+        int 0x21
+        mov bh, al
+        mov ah, 0x3F
+        mov bx, 0
+        mov cx, 16
+        mov dx, 0x200
+        int 0x21
+        mov si, ax
+        mov ah, 0x3F
+        int 0x21
+        mov di, ax
+        mov ah, 1
+        int 0x21");
+
+    cpu.set_services(Box::new(DosServices::new(&b"ab\ncd\n"[..], &mut output)));
+    cpu.execute_instructions().unwrap();
+
+    // Standard input is read line by line
+    assert_eq!(cpu.register(Reg::Si), 1);
+    assert_eq!(cpu.register(Reg::Di), 3);
+    assert_eq!(cpu.memory()[address(0x200)..address(0x203)], *b"cd\n");
+    // End of the input is returned as Ctrl-Z
+    assert_eq!(cpu.register(Reg::A) & 0xFF, 0x1A);
+    drop(cpu);
+
+    assert_eq!(output, b"a\x1A");
+}
+
+#[test]
+fn test_files() {
+    let sandbox = std::env::temp_dir().join(format!("rust_decode_services_{}", process::id()));
+    fs::create_dir_all(&sandbox).unwrap();
+
+    let mut cpu = com("
+        mov ah, 0x3C
+        mov cx, 0
+        mov dx, 0x200
+        int 0x21
+        mov bx, ax
+        mov ah, 0x40
+        mov cx, 5
+        mov dx, 0x210
+        int 0x21
+        mov ah, 0x3E
+        int 0x21
+        mov ax, 0x3D00
+        mov dx, 0x200
+        int 0x21
+        mov bx, ax
+        mov ah, 0x3F
+        mov cx, 100
+        mov dx, 0x220
+        int 0x21
+        mov si, ax
+        mov ah, 0x3E
+        int 0x21
+        mov ax, 0x3D00
+        mov dx, 0x230
+        int 0x21
+        mov di, ax");
+    let memory = cpu.memory_mut();
+    memory[address(0x200)..address(0x208)].copy_from_slice(b"OUT.TXT\0");
+    memory[address(0x210)..address(0x215)].copy_from_slice(b"hello");
+    memory[address(0x230)..address(0x23A)].copy_from_slice(b"..\\SECRET\0");
+
+    cpu.set_services(Box::new(
+        DosServices::new(&b""[..], Vec::new()).with_sandbox(&sandbox),
+    ));
+    cpu.execute_instructions().unwrap();
+
+    assert_eq!(fs::read(sandbox.join("OUT.TXT")).unwrap(), b"hello");
+    fs::remove_dir_all(&sandbox).unwrap();
+
+    assert_eq!(cpu.register(Reg::Si), 5);
+    assert_eq!(cpu.memory()[address(0x220)..address(0x225)], *b"hello");
+    // Paths leaving the sandbox are refused with access denied
+    assert_eq!(cpu.register(Reg::Di), 5);
+    assert!(cpu.flags().contains(CpuFlags::C));
+}
+
+#[test]
+fn test_get_time() {
+    let mut cpu = com("mov ah, 0x2C\nint 0x21");
+    cpu.set_services(Box::new(
+        DosServices::new(&b""[..], Vec::new()).with_time([12, 34, 56, 78]),
+    ));
+    cpu.execute_instructions().unwrap();
+
+    assert_eq!(cpu.register(Reg::C), 0x0C22);
+    assert_eq!(cpu.register(Reg::D), 0x384E);
+}
+
+/// Serves only its own interrupt
+struct Counter(u16);
+
+impl Services for Counter {
+    fn interrupt(&mut self, vector: u8, cpu: &mut CPU<'_>) -> bool {
+        if vector != 0x80 {
+            return false;
+        }
+
+        self.0 += 1;
+        cpu.set_register(Reg::B, self.0 as i16);
+        true
+    }
+}
+
+#[test]
+fn test_custom_services() {
+    // Vector of interrupt 0x81 points at its handler at 0x10B
+    let mut cpu = com("int 0x80\nint 0x80\nint 0x81\nmov dx, 1\njmp end\nmov cx, 7\niret\nend:");
+    cpu.memory_mut()[0x204..0x208].copy_from_slice(&[0x0B, 0x01, 0x00, 0x10]);
+
+    cpu.set_services(Box::new(Counter(0)));
+    cpu.execute_instructions().unwrap();
+
+    assert_eq!(cpu.register(Reg::B), 2);
+    assert_eq!(cpu.register(Reg::C), 7);
+    assert_eq!(cpu.register(Reg::D), 1);
+}
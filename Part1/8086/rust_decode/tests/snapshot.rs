@@ -0,0 +1,50 @@
+use std::fs;
+use std::io;
+
+use rust_decode::{InstructionBuffer, Processor, Reg, Snapshot, TraceFormat, CPU};
+use strum::IntoEnumIterator;
+
+const DRAW_RECT: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/../listings/fixtures/54_draw_rect.bin"
+);
+
+fn cpu(bytes: &[u8]) -> CPU<'_> {
+    let mut cpu = CPU::new(InstructionBuffer::from_bytes(bytes));
+    cpu.set_tracer(TraceFormat::Binary.tracer(io::sink()));
+    cpu.estimate_clocks(Processor::I8086);
+
+    cpu
+}
+
+#[test]
+fn test_resume_from_snapshot() {
+    let bytes = fs::read(DRAW_RECT).unwrap();
+
+    let mut expected = cpu(&bytes);
+    expected.execute_instructions().unwrap();
+
+    let mut first = cpu(&bytes);
+    for _ in 0..1000 {
+        first.execute_next_instruction().unwrap();
+    }
+
+    let mut saved = Vec::new();
+    first.snapshot().write(&mut saved).unwrap();
+    drop(first);
+
+    // Program of the resumed machine is replaced by the one in the snapshot
+    let mut resumed = cpu(&[]);
+    resumed.restore_snapshot(Snapshot::read(&saved[..]).unwrap());
+    assert!(!resumed.is_finished());
+
+    resumed.execute_instructions().unwrap();
+
+    for reg in Reg::iter() {
+        assert_eq!(resumed.register(reg), expected.register(reg), "{}", reg);
+    }
+    assert_eq!(resumed.ip(), expected.ip());
+    assert_eq!(resumed.flags(), expected.flags());
+    assert_eq!(resumed.clocks(), expected.clocks());
+    assert!(resumed.memory() == expected.memory());
+}
@@ -0,0 +1,133 @@
+use std::cell::RefCell;
+use std::io;
+use std::rc::Rc;
+
+use rust_decode::{
+    read_binary_trace, InstructionBuffer, MemoryWrite, Processor, Reg, RegisterChange, TraceFormat,
+    TraceStep, Tracer, CPU,
+};
+use serde_json::Value;
+
+// mov bx, 4; mov word [bx+256], 0x1234; sub bx, 5
+const PROGRAM: [u8; 12] = [
+    0xBB, 0x04, 0x00, 0xC7, 0x87, 0x00, 0x01, 0x34, 0x12, 0x83, 0xEB, 0x05,
+];
+
+/// Keeps the steps so they can be compared with the written traces
+struct Collector(Rc<RefCell<Vec<TraceStep>>>);
+
+impl Tracer for Collector {
+    fn trace(&mut self, step: &TraceStep) {
+        self.0.borrow_mut().push(step.clone());
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn trace(format: TraceFormat, processor: Option<Processor>) -> Vec<u8> {
+    let mut output = Vec::new();
+    let mut cpu = CPU::new(InstructionBuffer::from_bytes(&PROGRAM));
+
+    if let Some(processor) = processor {
+        cpu.estimate_clocks(processor);
+    }
+
+    cpu.set_tracer(format.tracer(&mut output));
+    cpu.execute_instructions().unwrap();
+    cpu.finish_trace().unwrap();
+    drop(cpu);
+
+    output
+}
+
+fn collect(processor: Option<Processor>) -> Vec<TraceStep> {
+    let steps = Rc::new(RefCell::new(Vec::new()));
+    let mut cpu = CPU::new(InstructionBuffer::from_bytes(&PROGRAM));
+
+    if let Some(processor) = processor {
+        cpu.estimate_clocks(processor);
+    }
+
+    cpu.set_tracer(Box::new(Collector(steps.clone())));
+    cpu.execute_instructions().unwrap();
+    drop(cpu);
+
+    Rc::try_unwrap(steps).unwrap().into_inner()
+}
+
+#[test]
+fn test_collected_steps() {
+    let steps = collect(None);
+
+    assert_eq!(steps.len(), 3);
+    assert_eq!(steps[1].instruction, "mov [bx+256], word 4660");
+    assert_eq!(steps[1].bytes, PROGRAM[3..9]);
+    assert_eq!(
+        steps[1].memory,
+        vec![
+            MemoryWrite {
+                address: 0x104,
+                old: 0,
+                new: 0x34
+            },
+            MemoryWrite {
+                address: 0x105,
+                old: 0,
+                new: 0x12
+            }
+        ]
+    );
+    assert_eq!(
+        steps[2].registers,
+        vec![RegisterChange {
+            reg: Reg::B,
+            old: 4,
+            new: 0xFFFF
+        }]
+    );
+    assert_ne!(steps[2].old_flags, steps[2].flags);
+    assert_eq!(steps[2].next_ip, PROGRAM.len());
+}
+
+#[test]
+fn test_text_trace() {
+    let text = String::from_utf8(trace(TraceFormat::Text, None)).unwrap();
+
+    assert_eq!(
+        text.lines().nth(3).unwrap(),
+        "sub bx, 5 ; bx:0x4->0xffff ip:0x9->0xc flags:->CPAS"
+    );
+}
+
+#[test]
+fn test_json_trace() {
+    let text = String::from_utf8(trace(TraceFormat::Json, Some(Processor::I8086))).unwrap();
+    let lines: Vec<Value> = text
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[0]["mnemonic"], "mov");
+    assert_eq!(lines[0]["bytes"], "bb0400");
+    assert_eq!(lines[0]["registers"][0]["register"], "bx");
+    assert_eq!(lines[0]["registers"][0]["new"], 4);
+    assert_eq!(lines[1]["memory"][1]["address"], 0x105);
+    assert_eq!(lines[1]["memory"][1]["new"], 0x12);
+    assert_eq!(lines[2]["flags"], "CPAS");
+    assert_eq!(lines[0]["clocks"]["step"], 4);
+    assert!(lines[2]["clocks"]["total"].as_u64().unwrap() > 4);
+}
+
+#[test]
+fn test_binary_trace() {
+    for processor in [None, Some(Processor::I8088)] {
+        let bytes = trace(TraceFormat::Binary, processor);
+
+        assert_eq!(read_binary_trace(&bytes[..]).unwrap(), collect(processor));
+    }
+
+    assert!(read_binary_trace(&b"T86\x00"[..]).is_err());
+}